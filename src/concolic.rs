@@ -0,0 +1,266 @@
+//! Concolic execution driven by concrete inputs.
+//!
+//! Instead of exploring all paths at once, a concolic run follows the single
+//! path a concrete standard input takes through the program, while still
+//! collecting the symbolic conditions of the branches on the way. Negating
+//! these conditions with the solver yields inputs for the neighbouring paths,
+//! which are explored in generations like in SAGE's generational search.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use crate::Program;
+use crate::concrete::{ExecutionError, STACK_BASE};
+use crate::math::{SymExpr, SymCondition, Integer, DataType, Symbol, Solver, SharedSolver};
+use crate::math::MAX_MODEL_INPUT;
+use crate::sym::{SymState, MemoryStrategy, Event, StdioKind, SymbolMap};
+use crate::validate::MAX_STEPS;
+use crate::x86_64::Register;
+
+
+/// The path a concrete input takes through a program.
+#[derive(Debug, Clone)]
+pub struct ConcolicPath {
+    /// The standard input driving the run.
+    pub input: Vec<u8>,
+    /// The branches that depend on the input in the order they were taken.
+    pub branches: Vec<Branch>,
+    /// What the program wrote to standard output.
+    pub stdout: Vec<u8>,
+}
+
+/// An input-dependent branch on a concolic path.
+#[derive(Debug, Clone)]
+pub struct Branch {
+    /// The address of the instruction after the jump.
+    pub addr: u64,
+    /// The condition that holds on this path, either the jump condition or
+    /// its negation.
+    pub condition: SymCondition,
+    /// Where the symbols of the condition come from.
+    pub symbols: SymbolMap,
+}
+
+/// Run the program symbolically along the path the input takes, with the
+/// data sections loaded like in the concrete interpreter.
+///
+/// Symbols which are not input (uninitialized memory and registers) get the
+/// value zero like in the concrete interpreter and are replaced by it in the
+/// branch conditions, so that only the input remains symbolic.
+pub fn run_concolic(program: &Program, stdin: &[u8]) -> Result<ConcolicPath, ExecutionError> {
+    let index: HashMap<u64, usize> = program.code.iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.0, index))
+        .collect();
+
+    let values = |symbol: Symbol| Some(match symbol {
+        Symbol(DataType::N8, "stdin", index) => {
+            Integer(DataType::N8, stdin.get(index).copied().unwrap_or(0) as u64)
+        },
+        Symbol(DataType::N64, "size", 0) => Integer::from_ptr(stdin.len() as u64),
+        Symbol(data_type, _, _) => Integer(data_type, 0),
+    });
+
+    let mut state = SymState::new(MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+    state.set_reg(Register::RSP, SymExpr::from_ptr(STACK_BASE));
+    for (addr, bytes) in &program.data {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let value = SymExpr::Int(Integer(DataType::N8, byte as u64));
+            state.memory[0].write_direct(addr + i as u64, value);
+        }
+    }
+    let mut ip = program.entry;
+    let mut branches = Vec::new();
+    let mut stdout = Vec::new();
+
+    for _ in 0 .. MAX_STEPS {
+        let &position = index.get(&ip).ok_or(ExecutionError::InvalidAddress(ip))?;
+        let (addr, len, instruction, microcode) = &program.code[position];
+        let next_addr = addr + len;
+        let mut target = next_addr;
+
+        state.track(instruction, *addr);
+
+        for op in &microcode.ops {
+            match state.step(next_addr, op) {
+                Some(Event::Jump { target: jump, condition, relative }) => {
+                    let mut condition = state.evaluate_condition(&condition);
+                    let taken = condition.evaluate(&values);
+
+                    condition.replace_symbols(&|symbol| match symbol {
+                        Symbol(DataType::N8, "stdin", _) | Symbol(DataType::N64, "size", 0) => {
+                            SymExpr::Sym(symbol)
+                        },
+                        symbol => SymExpr::Int(values(symbol).unwrap()),
+                    });
+
+                    if !matches!(condition, SymCondition::Bool(_)) {
+                        let condition = if taken { condition } else { condition.not() };
+                        let symbols = state.get_symbol_map_for(&condition);
+                        branches.push(Branch { addr: next_addr, condition, symbols });
+                    }
+
+                    if taken {
+                        let jump = jump.evaluate(&values).1;
+                        target = if relative { next_addr.wrapping_add(jump) } else { jump };
+                        break;
+                    }
+                },
+                Some(Event::Stdio(StdioKind::Stdout, mut accesses)) => {
+                    // Bytes at symbolic positions come once for every position
+                    // and a symbolic count yields accesses up to its bound, of
+                    // which only the written number is output.
+                    accesses.dedup_by(|a, b| a.1 == b.1);
+                    let count = state.get_reg(Register::RAX).evaluate(&values).1 as usize;
+                    for (_, access) in accesses.into_iter().take(count) {
+                        let value = state.memory[0].read_expr(access.0, access.1);
+                        stdout.push(value.evaluate(&values).1 as u8);
+                    }
+                },
+                Some(Event::Exit) => {
+                    return Ok(ConcolicPath { input: stdin.to_vec(), branches, stdout });
+                },
+                _ => {},
+            }
+        }
+
+        ip = target;
+    }
+
+    Err(ExecutionError::StepLimit(MAX_STEPS))
+}
+
+/// Explores the paths of a program with generational search starting from a
+/// seed input.
+///
+/// Every run produces a generation of new inputs, one for each branch after
+/// the one that led to the run, by negating that branch under the conditions
+/// of the path before it. Each input is run once and the paths are yielded
+/// in the order they were found.
+pub struct ConcolicExplorer<'p> {
+    program: &'p Program,
+    solver: SharedSolver,
+    /// The inputs waiting to be run with the index of the first branch that
+    /// may be negated.
+    queue: VecDeque<(Vec<u8>, usize)>,
+    seen: HashSet<Vec<u8>>,
+}
+
+impl<'p> ConcolicExplorer<'p> {
+    /// Create a new explorer starting with the seed input.
+    pub fn new(program: &'p Program, seed: Vec<u8>) -> ConcolicExplorer<'p> {
+        ConcolicExplorer::with_solver(program, seed, Rc::new(Solver::new()))
+    }
+
+    /// Create a new explorer that generates inputs with the given solver.
+    pub fn with_solver(program: &'p Program, seed: Vec<u8>, solver: SharedSolver) -> ConcolicExplorer<'p> {
+        let mut seen = HashSet::new();
+        seen.insert(seed.clone());
+
+        let mut queue = VecDeque::new();
+        queue.push_back((seed, 0));
+
+        ConcolicExplorer { program, solver, queue, seen }
+    }
+
+    /// Queue the inputs for all branches from `bound` on negated.
+    fn expand(&mut self, path: &ConcolicPath, bound: usize) {
+        let mut prefix = SymCondition::TRUE;
+        for (index, branch) in path.branches.iter().enumerate() {
+            if index >= bound {
+                let negated = prefix.clone().and(branch.condition.clone().not());
+                if let Some(model) = self.solver.get_model(&negated) {
+                    let input = mutate(&path.input, &model);
+                    if self.seen.insert(input.clone()) {
+                        self.queue.push_back((input, index + 1));
+                    }
+                }
+            }
+
+            prefix = prefix.and(branch.condition.clone());
+        }
+    }
+}
+
+impl Iterator for ConcolicExplorer<'_> {
+    type Item = Result<ConcolicPath, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (input, bound) = self.queue.pop_front()?;
+        let result = run_concolic(self.program, &input);
+        if let Ok(path) = &result {
+            self.expand(path, bound);
+        }
+        Some(result)
+    }
+}
+
+/// Change the bytes of the input to the ones from the model. Bytes that are
+/// not part of the model keep their value, so that the new input takes the
+/// same path where the model does not force another one.
+fn mutate(input: &[u8], model: &HashMap<Symbol, Integer>) -> Vec<u8> {
+    let mut input = input.to_vec();
+    let size = model.get(&Symbol(DataType::N64, "size", 0))
+        .map(|size| size.1.min(MAX_MODEL_INPUT) as usize);
+
+    if let Some(size) = size {
+        input.resize(size, 0);
+    }
+
+    for (symbol, value) in model {
+        if let Symbol(DataType::N8, "stdin", index) = *symbol {
+            if index >= input.len() {
+                if size.is_some() {
+                    continue;
+                }
+                input.resize(index + 1, 0);
+            }
+            input[index] = value.1 as u8;
+        }
+    }
+
+    input
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::validate::run_concrete;
+    use super::*;
+
+    #[test]
+    fn generational_search() {
+        let program = Program::new("target/bin/case");
+        let paths: Vec<_> = ConcolicExplorer::new(&program, b"a".to_vec())
+            .map(Result::unwrap)
+            .collect();
+
+        // Lowercase letters, bytes below and bytes above them.
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[0].stdout, b"A");
+        for path in &paths {
+            assert_eq!(path.stdout, run_concrete(&program, &path.input).unwrap());
+            assert!(path.branches.iter().all(|branch| {
+                let values = |symbol| match symbol {
+                    Symbol(DataType::N8, "stdin", 0) => Some(Integer(DataType::N8, path.input[0] as u64)),
+                    Symbol(DataType::N64, "size", 0) => Some(Integer::from_ptr(1)),
+                    _ => None,
+                };
+                branch.condition.evaluate(&values)
+            }));
+        }
+
+        // Bytes below 'a' already leave after the first comparison.
+        let lengths: HashSet<_> = paths.iter().map(|path| path.branches.len()).collect();
+        assert_eq!(lengths, [1, 2].iter().copied().collect());
+    }
+
+    #[test]
+    fn symbolic_count() {
+        // Writes as many bytes of its buffer as the input says.
+        let program = Program::new("target/bin/echo");
+        for (input, output) in [(b"\x00", &b""[..]), (b"\x03", b"abc"), (b"\x08", b"abcdefgh")] {
+            assert_eq!(run_concolic(&program, input).unwrap().stdout, output);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Concrete byte-addressed memory.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::math::{Integer, DataType};
+
+
+/// Sparse byte-addressed memory where uninitialized bytes read as zero.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConcreteMemory {
+    name: &'static str,
+    data: HashMap<u64, u8>,
+}
+
+impl ConcreteMemory {
+    /// Create a new blank memory.
+    pub fn new(name: &'static str) -> ConcreteMemory {
+        ConcreteMemory {
+            name,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Read an integer of the given type stored in little endian.
+    pub fn read(&self, addr: u64, data_type: DataType) -> Integer {
+        let bytes = self.read_bytes(addr, data_type.bytes() as u64);
+        Integer::from_bytes(&bytes, data_type)
+    }
+
+    /// Write an integer in little endian.
+    pub fn write(&mut self, addr: u64, value: Integer) {
+        self.write_bytes(addr, &value.to_bytes());
+    }
+
+    /// Read a sequence of bytes.
+    pub fn read_bytes(&self, addr: u64, len: u64) -> Vec<u8> {
+        (0 .. len)
+            .map(|i| self.data.get(&addr.wrapping_add(i)).copied().unwrap_or(0))
+            .collect()
+    }
+
+    /// Write a sequence of bytes.
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.data.insert(addr.wrapping_add(i as u64), byte);
+        }
+    }
+}
+
+impl Display for ConcreteMemory {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ConcreteMemory {} [", self.name)?;
+        let mut bytes: Vec<_> = self.data.iter().collect();
+        bytes.sort();
+        if !bytes.is_empty() { writeln!(f)?; }
+        for (addr, byte) in bytes {
+            writeln!(f, "    {:#x}: {:#04x}", addr, byte)?;
+        }
+        write!(f, "]")
+    }
+}
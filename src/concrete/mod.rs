@@ -0,0 +1,350 @@
+//! Concrete microcode execution.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::Program;
+use crate::ir::{MicroOperation, Location, Temporary, MemoryMapped};
+use crate::math::{SymExpr, SymCondition, Integer, DataType, Symbol, Traversed};
+use crate::x86_64::Register;
+use DataType::*;
+
+mod mem;
+pub use mem::*;
+
+
+/// The initial value of the stack pointer.
+pub const STACK_BASE: u64 = 0x7fff_ffff_0000;
+
+/// The concrete execution state.
+#[derive(Debug, Clone)]
+pub struct ConcreteState {
+    /// The values of the temporaries (T0, T1, ...).
+    pub temporaries: HashMap<usize, Integer>,
+    /// The two memory spaces for main memory and registers.
+    pub memory: [ConcreteMemory; 2],
+    /// The current instruction pointer.
+    pub ip: u64,
+    /// The bytes that can be read from standard input.
+    pub stdin: Vec<u8>,
+    /// The bytes that were written to standard output.
+    pub stdout: Vec<u8>,
+    /// How many bytes of the standard input were already read.
+    stdin_pos: usize,
+}
+
+/// Events occuring during concrete execution.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// A jump to the absolute address was taken.
+    Jump(u64),
+    /// The program exited with the status code.
+    Exit(u64),
+}
+
+impl ConcreteState {
+    /// Create a new state that starts at the address and reads from the given
+    /// standard input.
+    pub fn new(entry: u64, stdin: Vec<u8>) -> ConcreteState {
+        let mut state = ConcreteState {
+            temporaries: HashMap::new(),
+            memory: [ConcreteMemory::new("mem"), ConcreteMemory::new("reg")],
+            ip: entry,
+            stdin,
+            stdout: Vec::new(),
+            stdin_pos: 0,
+        };
+        state.set_reg(Register::RSP, Integer::from_ptr(STACK_BASE));
+        state.set_reg(Register::RIP, Integer::from_ptr(entry));
+        state
+    }
+
+    /// Create a new state for the program with its text and data sections
+    /// loaded into main memory.
+    pub fn from_program(program: &Program, stdin: Vec<u8>) -> ConcreteState {
+        let mut state = ConcreteState::new(program.entry, stdin);
+        state.memory[0].write_bytes(program.base, &program.binary);
+        for (addr, bytes) in &program.data {
+            state.memory[0].write_bytes(*addr, bytes);
+        }
+        state
+    }
+
+    /// Run the program until it exits and return the exit status.
+    ///
+    /// Fails if more than `max_steps` instructions are executed or if the
+    /// execution ends up at an address where no instruction is.
+    pub fn run(&mut self, program: &Program, max_steps: usize) -> ExecResult<u64> {
+        let index: HashMap<u64, usize> = program.code.iter()
+            .enumerate()
+            .map(|(index, entry)| (entry.0, index))
+            .collect();
+
+        for _ in 0 .. max_steps {
+            let &position = index.get(&self.ip)
+                .ok_or(ExecutionError::InvalidAddress(self.ip))?;
+
+            let (addr, len, _, microcode) = &program.code[position];
+            let next_addr = addr + len;
+            let mut target = next_addr;
+
+            // Execute the microcode until the first jump or the exit.
+            for op in &microcode.ops {
+                match self.step(next_addr, op)? {
+                    Some(Event::Jump(addr)) => { target = addr; break; },
+                    Some(Event::Exit(status)) => return Ok(status),
+                    None => {},
+                }
+            }
+
+            self.ip = target;
+        }
+
+        Err(ExecutionError::StepLimit(max_steps))
+    }
+
+    /// Execute a micro operation.
+    pub fn step(&mut self, addr: u64, operation: &MicroOperation) -> ExecResult<Option<Event>> {
+        use MicroOperation as Op;
+
+        self.set_reg(Register::RIP, Integer::from_ptr(addr));
+
+        match operation {
+            Op::Mov { dest, src } => {
+                let value = self.read_location(*src)?;
+                self.write_location(*dest, value)?;
+            },
+
+            Op::Const { dest, constant } => self.set_temp(*dest, *constant),
+            Op::Cast { target, new, signed } => {
+                let new_value = self.get_temp(*target)?.cast(*new, *signed);
+                self.set_temp(Temporary(*new, target.1), new_value);
+            },
+
+            Op::Add { sum, a, b } => self.do_binop(*sum, *a, *b, Integer::add)?,
+            Op::Sub { diff, a, b } => self.do_binop(*diff, *a, *b, Integer::sub)?,
+            Op::Mul { prod, a, b } => self.do_binop(*prod, *a, *b, Integer::mul)?,
+
+            Op::And { and, a, b } => self.do_binop(*and, *a, *b, Integer::bitand)?,
+            Op::Or { or, a, b } => self.do_binop(*or, *a, *b, Integer::bitor)?,
+            Op::Not { not, a } => {
+                let value = self.get_temp(*a)?.bitnot();
+                self.set_temp(*not, value);
+            },
+
+            Op::Set { target, condition } => {
+                let value = self.evaluate_condition(condition)?;
+                self.set_temp(*target, Integer::from_bool(value, target.0));
+            },
+            Op::Jump { target, condition, relative } => {
+                if self.evaluate_condition(condition)? {
+                    let offset = self.get_temp(*target)?.1;
+                    let target = if *relative { addr.wrapping_add(offset) } else { offset };
+                    return Ok(Some(Event::Jump(target)));
+                }
+            },
+
+            Op::Syscall => {
+                let num = self.get_reg(Register::RAX).1;
+                return self.do_syscall(num);
+            },
+        }
+
+        Ok(None)
+    }
+
+    /// Evaluate a condition over temporaries.
+    pub fn evaluate_condition(&self, condition: &SymCondition) -> ExecResult<bool> {
+        // Make sure all symbols are known before evaluating.
+        let mut missing = None;
+        condition.traverse(&mut |node| {
+            if let Traversed::Expr(SymExpr::Sym(sym)) = node {
+                if sym.1 != "T" || !self.temporaries.contains_key(&sym.2) {
+                    missing = Some(*sym);
+                }
+            }
+        });
+
+        if let Some(sym) = missing {
+            return Err(ExecutionError::UnknownSymbol(sym));
+        }
+
+        Ok(condition.evaluate(&|sym| self.get_temp(Temporary(sym.0, sym.2)).ok()))
+    }
+
+    /// Retrieve data from a location.
+    pub fn read_location(&self, src: Location) -> ExecResult<Integer> {
+        Ok(match src {
+            Location::Temp(temp) => self.get_temp(temp)?,
+            Location::Direct(data_type, space, addr) => self.memory[space].read(addr, data_type),
+            Location::Indirect(data_type, space, temp) => {
+                let addr = self.get_temp(temp)?;
+                assert_eq!(addr.0, N64, "read_location: address has to be 64-bit");
+                self.memory[space].read(addr.1, data_type)
+            },
+        })
+    }
+
+    /// Write data to a location.
+    pub fn write_location(&mut self, dest: Location, value: Integer) -> ExecResult<()> {
+        assert_eq!(dest.data_type(), value.0,
+            "write_location: incompatible data types for write");
+
+        match dest {
+            Location::Temp(temp) => self.set_temp(temp, value),
+            Location::Direct(_, space, addr) => {
+                // Like on the real CPU, writing a 32-bit register clears the upper half
+                // of the 64-bit register.
+                if space == 1 && value.0 == N32 {
+                    self.memory[space].write(addr, value.cast(N64, false));
+                } else {
+                    self.memory[space].write(addr, value);
+                }
+            },
+            Location::Indirect(_, space, temp) => {
+                let addr = self.get_temp(temp)?;
+                assert_eq!(addr.0, N64, "write_location: address has to be 64-bit");
+                self.memory[space].write(addr.1, value);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Return the integer stored in the temporary.
+    pub fn get_temp(&self, temp: Temporary) -> ExecResult<Integer> {
+        let value = *self.temporaries.get(&temp.1)
+            .ok_or_else(|| ExecutionError::UnknownSymbol(temp.to_symbol()))?;
+        assert_eq!(temp.0, value.0, "get_temp: incompatible data types");
+        Ok(value)
+    }
+
+    /// Set the temporary to a new value.
+    pub fn set_temp(&mut self, temp: Temporary, value: Integer) {
+        assert_eq!(temp.0, value.0, "set_temp: incompatible data types");
+        self.temporaries.insert(temp.1, value);
+    }
+
+    /// Get a value from a register.
+    pub fn get_reg(&self, reg: Register) -> Integer {
+        self.memory[1].read(reg.address(), reg.data_type())
+    }
+
+    /// Set a register to a value.
+    pub fn set_reg(&mut self, reg: Register, value: Integer) {
+        self.memory[1].write(reg.address(), value);
+    }
+
+    /// Do a binary operation.
+    fn do_binop<F>(&mut self, target: Temporary, a: Temporary, b: Temporary, binop: F)
+    -> ExecResult<()> where F: FnOnce(Integer, Integer) -> Integer {
+        let value = binop(self.get_temp(a)?, self.get_temp(b)?);
+        self.set_temp(target, value);
+        Ok(())
+    }
+
+    /// Emulate a Linux syscall.
+    fn do_syscall(&mut self, num: u64) -> ExecResult<Option<Event>> {
+        match num {
+            // Read from standard input or write to standard output.
+            0 | 1 => {
+                let buf = self.get_reg(Register::RSI).1;
+                let count = self.get_reg(Register::RDX).1 as usize;
+
+                let done = if num == 0 {
+                    let available = self.stdin.len() - self.stdin_pos;
+                    let read = count.min(available);
+                    let bytes = &self.stdin[self.stdin_pos .. self.stdin_pos + read];
+                    self.memory[0].write_bytes(buf, bytes);
+                    self.stdin_pos += read;
+                    read
+                } else {
+                    let bytes = self.memory[0].read_bytes(buf, count as u64);
+                    self.stdout.extend(bytes);
+                    count
+                };
+
+                self.set_reg(Register::RAX, Integer::from_ptr(done as u64));
+                Ok(None)
+            },
+
+            // System exit
+            60 => Ok(Some(Event::Exit(self.get_reg(Register::RDI).1))),
+            s => Err(ExecutionError::Syscall(s)),
+        }
+    }
+}
+
+
+/// The error type for concrete execution.
+#[derive(Eq, PartialEq)]
+pub enum ExecutionError {
+    /// There is no instruction at the address.
+    InvalidAddress(u64),
+    /// The value of the symbol is not known.
+    UnknownSymbol(Symbol),
+    /// The syscall with the number is not supported.
+    Syscall(u64),
+    /// The maximum number of steps was exceeded.
+    StepLimit(usize),
+}
+
+pub(in super) type ExecResult<T> = Result<T, ExecutionError>;
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ExecutionError::InvalidAddress(addr) => write!(f, "No instruction at {:#x}", addr),
+            ExecutionError::UnknownSymbol(sym) => write!(f, "Unknown value for {}", sym),
+            ExecutionError::Syscall(num) => write!(f, "Unimplemented syscall number {}", num),
+            ExecutionError::StepLimit(max) => write!(f, "Exceeded the limit of {} steps", max),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+debug_display!(ExecutionError);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(filename: &str, stdin: &[u8], stdout: &[u8]) {
+        let path = format!("target/bin/{}", filename);
+        let program = Program::new(path);
+        let mut state = ConcreteState::from_program(&program, stdin.to_vec());
+        assert_eq!(state.run(&program, 100_000), Ok(0));
+        assert_eq!(state.stdout, stdout);
+    }
+
+    #[test]
+    fn concrete_io() {
+        test("case", b"q", b"Q");
+        test("case", b"?", b"?");
+        test("func", b"A", b"R");
+        test("func", b"0", b"L");
+        test("bufs", b"z:S", b"S");
+        test("overwrite", b"a\x01S", b"S");
+        test("min", b"", b"");
+        test("global", b"\x03", b"d3");
+    }
+
+    #[test]
+    fn concrete_loop() {
+        let program = Program::new("target/bin/loop");
+        let mut state = ConcreteState::from_program(&program, vec![]);
+        assert_eq!(state.run(&program, 1000), Err(ExecutionError::StepLimit(1000)));
+    }
+
+    #[test]
+    fn concrete_registers() {
+        let mut state = ConcreteState::new(0, vec![]);
+        state.set_reg(Register::RAX, Integer(N64, 0xffff_ffff_ffff_ffff));
+        state.write_location(Location::Direct(N32, 1, 0x00), Integer(N32, 5)).unwrap();
+        assert_eq!(state.get_reg(Register::RAX), Integer(N64, 5));
+        state.set_reg(Register::AL, Integer(N8, 0xab));
+        assert_eq!(state.get_reg(Register::RAX), Integer(N64, 0xab));
+        assert_eq!(state.get_reg(Register::EAX), Integer(N32, 0xab));
+    }
+}
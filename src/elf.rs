@@ -7,6 +7,17 @@ use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 use byteorder::{ReadBytesExt, LE};
 
+/// Section type of sections with contents defined by the program.
+pub const SHT_PROGBITS: u32 = 1;
+/// Section type of sections that occupy no space in the file, like `.bss`.
+pub const SHT_NOBITS: u32 = 8;
+/// Section flag for sections that occupy memory during execution.
+pub const SHF_ALLOC: u64 = 0x2;
+/// Section flag for sections with executable machine instructions.
+pub const SHF_EXECINSTR: u64 = 0x4;
+/// Section flag for thread-local sections, whose addresses are templates.
+pub const SHF_TLS: u64 = 0x400;
+
 
 /// Handle for an `ELF` file.
 #[derive(Debug)]
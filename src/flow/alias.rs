@@ -95,8 +95,7 @@ impl<'g> AliasExplorer<'g> {
                     for (source, _) in instruction.flows() {
                         if let ValueSource::Storage(storage) = source {
                             if let Some(access) = exp.state.get_access_for_storage(storage) {
-                                let trace = exp.state.trace.clone();
-                                let location = AbstractLocation::new(addr, trace, storage);
+                                let location = exp.state.abstract_location(addr, storage);
                                 self.handle_read_access(&exp, access, location);
                             }
                         }
@@ -113,7 +112,7 @@ impl<'g> AliasExplorer<'g> {
 
             // Add all nodes reachable from that one as targets.
             for &id in &self.cfg.outgoing[exp.target] {
-                if relevant.contains(&id) {
+                if relevant.contains(&id) && !self.cfg.truncated.contains(&(exp.target, id)) {
                     let condition = &self.cfg.edges[&(exp.target, id)];
                     let evaluated = exp.state.evaluate_condition(condition);
 
@@ -295,12 +294,14 @@ mod tests {
             addr: 0x39d,
             trace: vec![0x2ba],
             storage: StorageLocation::indirect_reg(N8, Register::RDX),
+            region: None,
         });
 
         let secret_flow_condition = &alias_map.map[&AbstractLocation {
             addr: 0x3aa,
             trace: vec![0x2ba],
             storage: StorageLocation::indirect_reg(N8, Register::RAX),
+            region: None,
         }].0;
 
         // Ascii 'z' is 122 and ':' is 58, so the difference is exactly 64.
@@ -329,6 +330,7 @@ mod tests {
                 scaled_offset: Some((Register::RAX, 1)),
                 displacement: Some(-0x410),
             },
+            region: None,
         });
     }
 
@@ -338,6 +340,7 @@ mod tests {
             addr: 0x399,
             trace: vec![0x2ba],
             storage: StorageLocation::indirect_reg(N8, Register::RDX),
+            region: None,
         });
     }
 }
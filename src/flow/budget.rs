@@ -0,0 +1,215 @@
+//! Bounding the work of explorations.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::math::Solver;
+use crate::sym::Heap;
+use super::ExplorationConfig;
+
+
+/// Stops a running exploration from the outside, for example from another
+/// thread. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a token which is not cancelled yet.
+    pub fn new() -> CancellationToken {
+        CancellationToken { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Request all explorations using this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// The reason for stopping an exploration early.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Limit {
+    Paths,
+    Nodes,
+    SolverQueries,
+    Time,
+    Cancelled,
+}
+
+/// How much of the program an exploration covered.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExplorationReport {
+    /// The limit that stopped the exploration, `None` if it ran until all
+    /// paths were explored.
+    pub stopped: Option<Limit>,
+    /// The number of paths explored to their end.
+    pub paths: usize,
+    /// The number of nodes in the graph.
+    pub nodes: usize,
+    /// The number of queries handed to the solver.
+    pub solver_queries: usize,
+    /// The number of exploration targets which were left when stopping.
+    pub pending: usize,
+    /// The time the exploration took.
+    pub elapsed: Duration,
+    /// The instruction pointers and target addresses of reads from heap
+    /// memory that was not mapped, in ascending order.
+    pub unmapped_reads: Vec<(u64, u64)>,
+}
+
+impl ExplorationReport {
+    /// Whether all paths were explored. Even then, loops and recursion may
+    /// have been cut off at their limits, which the truncated edges of the
+    /// control flow graph show.
+    pub fn is_complete(&self) -> bool {
+        self.stopped.is_none()
+    }
+}
+
+/// Keeps track of the resources used by an exploration.
+#[derive(Debug, Clone)]
+pub(crate) struct Budget {
+    config: ExplorationConfig,
+    start: Instant,
+    queries: usize,
+    paths: usize,
+    unmapped_reads: BTreeSet<(u64, u64)>,
+}
+
+impl Budget {
+    /// Start tracking an exploration whose queries go to the solver.
+    pub fn new(config: &ExplorationConfig, solver: &Solver) -> Budget {
+        Budget {
+            config: config.clone(),
+            start: Instant::now(),
+            queries: solver.queries(),
+            paths: 0,
+            unmapped_reads: BTreeSet::new(),
+        }
+    }
+
+    /// Record that a path was explored to its end.
+    pub fn finish_path(&mut self) {
+        self.paths += 1;
+    }
+
+    /// Take over the unmapped reads of an explored state, before the state
+    /// is cloned for the following targets.
+    pub fn collect_unmapped_reads(&mut self, heap: &mut Heap) {
+        self.unmapped_reads.extend(heap.unmapped_reads.drain(..));
+    }
+
+    /// The first limit that is reached with the given number of nodes.
+    pub fn exceeded(&self, nodes: usize, solver: &Solver) -> Option<Limit> {
+        let reached = |limit: Option<usize>, value| limit.is_some_and(|limit| value >= limit);
+
+        if self.config.cancellation.as_ref().is_some_and(|token| token.is_cancelled()) {
+            Some(Limit::Cancelled)
+        } else if reached(self.config.max_paths, self.paths) {
+            Some(Limit::Paths)
+        } else if reached(self.config.max_nodes, nodes) {
+            Some(Limit::Nodes)
+        } else if reached(self.config.max_solver_queries, solver.queries() - self.queries) {
+            Some(Limit::SolverQueries)
+        } else if self.config.time_limit.is_some_and(|limit| self.start.elapsed() >= limit) {
+            Some(Limit::Time)
+        } else {
+            None
+        }
+    }
+
+    /// Summarize the exploration.
+    pub fn report(&self, stopped: Option<Limit>, nodes: usize, pending: usize, solver: &Solver)
+    -> ExplorationReport {
+        ExplorationReport {
+            stopped,
+            paths: self.paths,
+            nodes,
+            solver_queries: solver.queries() - self.queries,
+            pending,
+            elapsed: self.start.elapsed(),
+            unmapped_reads: self.unmapped_reads.iter().copied().collect(),
+        }
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Limit::Paths => "path limit",
+            Limit::Nodes => "node limit",
+            Limit::SolverQueries => "solver query limit",
+            Limit::Time => "time limit",
+            Limit::Cancelled => "cancellation",
+        })
+    }
+}
+
+impl Display for ExplorationReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.stopped {
+            Some(limit) => write!(f, "stopped by {} with {} targets pending", limit, self.pending)?,
+            None => write!(f, "complete")?,
+        }
+        write!(f, " after {} paths, {} nodes and {} solver queries in {:?}",
+            self.paths, self.nodes, self.solver_queries, self.elapsed)?;
+        if !self.unmapped_reads.is_empty() {
+            write!(f, " with {} reads from unmapped memory", self.unmapped_reads.len())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let solver = Solver::new();
+        let token = CancellationToken::new();
+
+        let mut config = ExplorationConfig::new();
+        config.max_paths = Some(2);
+        config.max_nodes = Some(10);
+        config.cancellation = Some(token.clone());
+
+        let mut budget = Budget::new(&config, &solver);
+        assert_eq!(budget.exceeded(9, &solver), None);
+        assert_eq!(budget.exceeded(10, &solver), Some(Limit::Nodes));
+
+        budget.finish_path();
+        budget.finish_path();
+        assert_eq!(budget.exceeded(0, &solver), Some(Limit::Paths));
+
+        // Reads seen on several paths are reported once.
+        let mut heap = Heap::new();
+        heap.unmapped_reads = vec![(0x40, 0x1000_0000), (0x20, 0x1000_0008)];
+        budget.collect_unmapped_reads(&mut heap.clone());
+        budget.collect_unmapped_reads(&mut heap);
+        assert!(heap.unmapped_reads.is_empty());
+
+        token.cancel();
+        assert_eq!(budget.exceeded(0, &solver), Some(Limit::Cancelled));
+
+        let report = budget.report(Some(Limit::Cancelled), 3, 1, &solver);
+        assert!(!report.is_complete());
+        assert_eq!(report.paths, 2);
+        assert_eq!(report.solver_queries, 0);
+        assert_eq!(report.unmapped_reads, vec![(0x20, 0x1000_0008), (0x40, 0x1000_0000)]);
+
+        config = ExplorationConfig::new();
+        config.time_limit = Some(Duration::from_secs(0));
+        assert_eq!(Budget::new(&config, &solver).exceeded(0, &solver), Some(Limit::Time));
+    }
+}
@@ -1,14 +1,16 @@
 //! Control flow graph calculation.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::Program;
-use crate::ir::{Microcode, MicroEncoder};
+use crate::ir::{Microcode, MicroEncoder, MicroOptimizer, MicroVerifier};
 use crate::math::{SymExpr, SymCondition, Integer, DataType, Solver};
 use crate::sym::{SymState, MemoryStrategy, Event};
 use crate::x86_64::{Instruction, Mnemoic};
+use super::{Budget, CancellationToken, ExplorationReport};
 
 
 /// The control flow graph representation of a program.
@@ -25,6 +27,14 @@ pub struct ControlFlowGraph {
     pub incoming: Vec<Vec<usize>>,
     /// The nodes which have edges to the node with the index.
     pub outgoing: Vec<Vec<usize>>,
+    /// The edges whose targets were not explored again because the loop
+    /// bound or the recursion depth was reached. They lead back to the
+    /// latest occurrence of the target on the path, so they are the only
+    /// edges closing cycles and explorations of paths must not follow them.
+    /// Truncated call edges mean that deeper recursion is not covered.
+    pub truncated: HashSet<(usize, usize)>,
+    /// How much of the program the exploration covered.
+    pub report: ExplorationReport,
 }
 
 /// A node in the control flow graph, that is a basic block in some context.
@@ -35,15 +45,21 @@ pub struct ControlFlowNode {
     pub addr: u64,
     /// The call trace in (callsite, target) pairs.
     pub trace: Vec<(u64, u64)>,
+    /// How often the block was already executed in the same call trace on
+    /// the paths to this node, that is, the loop iteration.
+    pub iteration: usize,
 }
 
 impl ControlFlowNode {
-    /// Return the same node but without cycles in the trace.
-    fn decycled(&self) -> ControlFlowNode {
-        ControlFlowNode {
-            addr: self.addr,
-            trace: decycle(&self.trace, |a, b| a.1 == b.1)
-        }
+    /// Return the same node but with the recursion in the trace limited to
+    /// the given depth. Without a depth, every call target occurs only once.
+    fn decycled(&self, depth: Option<usize>) -> ControlFlowNode {
+        let trace = match depth {
+            Some(depth) => decycle(&self.trace, depth, |a, b| a == b),
+            None => decycle(&self.trace, 1, |a, b| a.1 == b.1),
+        };
+
+        ControlFlowNode { addr: self.addr, trace, iteration: self.iteration }
     }
 }
 
@@ -62,7 +78,14 @@ pub struct BasicBlock {
 impl ControlFlowGraph {
     /// Generate a control flow graph of a program.
     pub fn new(program: &Program) -> ControlFlowGraph {
-        crate::timings::with("control-flow-graph", || ControlFlowExplorer::new(program).run())
+        ControlFlowGraph::with_config(program, &ExplorationConfig::new())
+    }
+
+    /// Generate a control flow graph of a program with custom exploration limits.
+    pub fn with_config(program: &Program, config: &ExplorationConfig) -> ControlFlowGraph {
+        crate::timings::with("control-flow-graph", || {
+            ControlFlowExplorer::new(program, config.clone()).run()
+        })
     }
 
     /// Visualize this flow graph in a graphviz DOT file.
@@ -85,6 +108,9 @@ impl ControlFlowGraph {
             if let Some(name) = program.symbols.get(&node.addr) {
                 write!(f, " &lt;{}&gt;", name)?;
             }
+            if node.iteration > 0 {
+                write!(f, " #{}", node.iteration)?;
+            }
             if !node.trace.is_empty() {
                 write!(f, " by ")?;
                 let mut first = true;
@@ -118,10 +144,13 @@ impl ControlFlowGraph {
             writeln!(f, "]")?;
         }
 
-        write_edges(&mut f, &self.edges, |f, (_, condition)| {
+        write_edges(&mut f, &self.edges, |f, (edge, condition)| {
             if condition != &SymCondition::TRUE {
                 write!(f, "label=\"{}\", ", condition)?;
             }
+            if self.truncated.contains(&edge) {
+                write!(f, "style=dashed, ")?;
+            }
             Ok(())
         })?;
 
@@ -140,21 +169,69 @@ pub enum VisualizationStyle {
     Microcode,
 }
 
+/// Limits for the exploration of a program's paths.
+///
+/// The loop and recursion limits shape the control flow graph. The other
+/// limits bound the work of an exploration and are unlimited by default.
+/// When one of them is reached, the exploration stops and returns what it
+/// found so far.
+#[derive(Debug, Clone)]
+pub struct ExplorationConfig {
+    /// How often a block may be executed in the same call trace on one path.
+    /// The default of one means that loop bodies run at most once, which is
+    /// also what a bound of zero means.
+    pub loop_bound: usize,
+    /// How often the same call may be on the call stack, with a context for
+    /// each level of recursion. By default, a call may be on the stack twice
+    /// to capture the returns of a recursive function to itself and to the
+    /// outside, but all calls of a function share the context of the first
+    /// one. A depth of zero is treated as one.
+    pub recursion_depth: Option<usize>,
+    /// The maximum number of paths explored to their end.
+    pub max_paths: Option<usize>,
+    /// The maximum number of nodes in the graph.
+    pub max_nodes: Option<usize>,
+    /// The maximum number of queries handed to the solver.
+    pub max_solver_queries: Option<usize>,
+    /// The maximum time the exploration may take.
+    pub time_limit: Option<Duration>,
+    /// Stops the exploration when it is cancelled.
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl ExplorationConfig {
+    /// Create a configuration with the default limits.
+    pub fn new() -> ExplorationConfig {
+        ExplorationConfig {
+            loop_bound: 1,
+            recursion_depth: None,
+            max_paths: None,
+            max_nodes: None,
+            max_solver_queries: None,
+            time_limit: None,
+            cancellation: None,
+        }
+    }
+}
+
 /// Constructs a control flow graph representation of a program.
 #[derive(Clone)]
 struct ControlFlowExplorer<'a> {
     program: &'a Program,
+    config: ExplorationConfig,
     stack: Vec<ExplorationTarget>,
     nodes: HashMap<ControlFlowNode, usize>,
     blocks: HashMap<u64, BasicBlock>,
     edges: HashMap<(usize, usize), SymCondition>,
+    truncated: HashSet<(usize, usize)>,
 }
 
 #[derive(Clone)]
 struct ExplorationTarget {
     node: ControlFlowNode,
     state: SymState,
-    path: Vec<usize>,
+    /// The decycled nodes before this one on the path.
+    path: Vec<ControlFlowNode>,
 }
 
 #[derive(Clone)]
@@ -173,20 +250,29 @@ enum ExitKind {
 }
 
 impl<'a> ControlFlowExplorer<'a> {
-    fn new(program: &'a Program) -> ControlFlowExplorer<'a> {
+    fn new(program: &'a Program, mut config: ExplorationConfig) -> ControlFlowExplorer<'a> {
+        // Every block and call has to be explored at least once.
+        config.loop_bound = config.loop_bound.max(1);
+        config.recursion_depth = config.recursion_depth.map(|depth| depth.max(1));
+
         ControlFlowExplorer {
             program,
+            config,
             blocks: HashMap::new(),
             nodes: HashMap::new(),
             edges: HashMap::new(),
+            truncated: HashSet::new(),
             stack: Vec::new(),
         }
     }
 
     /// Build the control flow graph.
     fn run(mut self) -> ControlFlowGraph {
-        let node = ControlFlowNode { addr: self.program.entry, trace: vec![], };
-        let base_state = SymState::new(MemoryStrategy::PerfectMatches, Rc::new(Solver::new()));
+        let node = ControlFlowNode { addr: self.program.entry, trace: vec![], iteration: 0 };
+        let solver = Rc::new(Solver::new());
+        let base_state = SymState::new(MemoryStrategy::PerfectMatches, solver.clone());
+        let mut budget = Budget::new(&self.config, &solver);
+        let mut stopped = None;
 
         self.stack.push(ExplorationTarget {
             node,
@@ -195,30 +281,62 @@ impl<'a> ControlFlowExplorer<'a> {
         });
 
         while let Some(mut exp) = self.stack.pop() {
-            self.insert_node(exp.node.decycled());
+            self.insert_node(exp.node.decycled(self.config.recursion_depth));
 
             // Explore this block and find all the ones reachable from this one.
-            if let Some(exit) = self.execute_block(&mut exp) {
+            let pending = self.stack.len();
+            let exit = self.execute_block(&mut exp);
+            budget.collect_unmapped_reads(&mut exp.state.heap);
+            if let Some(exit) = exit {
                 self.explore_exit(&exp, exit);
             }
+
+            if self.stack.len() <= pending {
+                budget.finish_path();
+            }
+
+            stopped = budget.exceeded(self.nodes.len(), &solver);
+            if stopped.is_some() {
+                break;
+            }
         }
 
-        self.finish()
+        let report = budget.report(stopped, self.nodes.len(), self.stack.len(), &solver);
+        self.finish(report)
     }
 
     /// Arrange all data in the way expected for the flow graph.
-    fn finish(self) -> ControlFlowGraph {
+    fn finish(self, mut report: ExplorationReport) -> ControlFlowGraph {
         // Arrange the nodes into a vector.
-        let count = self.nodes.len();
-        let mut nodes = vec![ControlFlowNode { addr: 0, trace: Vec::new() }; count];
+        let default = ControlFlowNode { addr: 0, trace: Vec::new(), iteration: 0 };
+        let mut all_nodes = vec![default; self.nodes.len()];
         for (node, index) in self.nodes.into_iter() {
-            nodes[index] = node;
+            all_nodes[index] = node;
+        }
+
+        // If the exploration was stopped, some targets of edges were never
+        // parsed. They are left out, keeping the entry at index zero.
+        let mut indices = vec![None; all_nodes.len()];
+        let mut nodes = Vec::new();
+        for (index, node) in all_nodes.into_iter().enumerate() {
+            if self.blocks.contains_key(&node.addr) {
+                indices[index] = Some(nodes.len());
+                nodes.push(node);
+            }
         }
 
+        let reindex = |(start, end): (usize, usize)| Some((indices[start]?, indices[end]?));
+        let edges: HashMap<_, _> = self.edges.into_iter()
+            .filter_map(|(edge, condition)| Some((reindex(edge)?, condition)))
+            .collect();
+        let truncated = self.truncated.into_iter().filter_map(reindex).collect();
+
         // Add the outgoing and incoming edges to the nodes.
+        let count = nodes.len();
+        report.nodes = count;
         let mut incoming = vec![Vec::new(); count];
         let mut outgoing = vec![Vec::new(); count];
-        for &(start, end) in self.edges.keys() {
+        for &(start, end) in edges.keys() {
             outgoing[start].push(end);
             incoming[end].push(start);
         }
@@ -230,9 +348,11 @@ impl<'a> ControlFlowExplorer<'a> {
         ControlFlowGraph {
             nodes,
             blocks: self.blocks,
-            edges: self.edges,
+            edges,
             incoming,
             outgoing,
+            truncated,
+            report,
         }
     }
 
@@ -321,7 +441,7 @@ impl<'a> ControlFlowExplorer<'a> {
     }
 
     /// Add a target to the search stack if it was not visited already
-    /// through some kind of cycle.
+    /// through some kind of cycle more often than the limits allow.
     fn explore_acyclic(
         &mut self,
         exp: &ExplorationTarget,
@@ -331,38 +451,56 @@ impl<'a> ControlFlowExplorer<'a> {
         condition: SymCondition
     ) {
         // Assemble the new node.
-        let mut target_node = ControlFlowNode { addr, trace: exp.node.trace.clone() };
+        let mut target_node = ControlFlowNode { addr, trace: exp.node.trace.clone(), iteration: 0 };
         match exit_kind {
             ExitKind::Call => target_node.trace.push((jumpsite, addr)),
             ExitKind::Return => { target_node.trace.pop(); },
             _ => {},
         }
 
+        // Check whether the call would exceed the recursion depth. In that
+        // case, the decycled trace is the one of the deepest allowed call.
+        let depth = self.config.recursion_depth;
+        let recursing = matches!(exit_kind, ExitKind::Call) && exp.node.trace.iter()
+            .filter(|&&jump| jump == (jumpsite, addr)).count() >= depth.unwrap_or(2);
+
+        // The iteration is the number of times the block already occurs on
+        // the path. At the loop bound or the recursion depth, the edge goes
+        // back to the last occurrence instead.
+        let start_node = exp.node.decycled(depth);
+        let mut end_node = target_node.decycled(depth);
+        let iterations: Vec<usize> = exp.path.iter()
+            .chain(std::iter::once(&start_node))
+            .filter(|node| node.addr == end_node.addr && node.trace == end_node.trace)
+            .map(|node| node.iteration)
+            .collect();
+
+        let truncated = recursing || iterations.len() >= self.config.loop_bound;
+        end_node.iteration = if truncated {
+            iterations.last().copied().unwrap_or(0)
+        } else {
+            iterations.len()
+        };
+        target_node.iteration = end_node.iteration;
+
         // Insert a new edge for the jump.
-        let start = self.insert_node(exp.node.decycled());
-        let end = self.insert_node(target_node.decycled());
+        let start = self.insert_node(start_node.clone());
+        let end = self.insert_node(end_node);
         self.edges.insert((start, end), condition);
 
-        // Only consider the target if it is acyclic or recursing in the allowed limits.
-        let looping = exp.path.contains(&end);
-        if !looping {
-            // Check if we are already recursing.
-            // We allow to recursive twice because we want to capture the returns
-            // of the recursing function to itself and the outside.
-            let fully_recursive = exp.node.trace.iter()
-                .filter(|&&jump| jump == (jumpsite, addr)).count() >= 2;
-
-            if !fully_recursive {
-                // Add the current block to the path.
-                let mut path = exp.path.to_vec();
-                path.push(start);
-
-                self.stack.push(ExplorationTarget {
-                    node: target_node,
-                    path,
-                    state: exp.state.clone(),
-                });
-            }
+        // Only consider the target if it is within the limits.
+        if truncated {
+            self.truncated.insert((start, end));
+        } else {
+            // Add the current block to the path.
+            let mut path = exp.path.to_vec();
+            path.push(start_node);
+
+            self.stack.push(ExplorationTarget {
+                node: target_node,
+                path,
+                state: exp.state.clone(),
+            });
         }
     }
 
@@ -385,6 +523,7 @@ enum BlockParser<'a> {
         index: u64,
         binary: &'a [u8],
         encoder: MicroEncoder,
+        verifier: MicroVerifier,
         code: Vec<(u64, u64, Instruction, Microcode)>,
     },
 }
@@ -401,7 +540,8 @@ impl<'a> BlockParser<'a> {
             entry,
             index: 0,
             binary: &binary[(entry - base) as usize ..],
-            encoder: MicroEncoder::new(),
+            encoder: MicroEncoder::with_optimizer(MicroOptimizer::new()),
+            verifier: MicroVerifier::new(),
             code: Vec::new(),
         }
     }
@@ -413,12 +553,13 @@ impl<'a> BlockParser<'a> {
                 *index += 1;
                 &block.code[*index - 1]
             },
-            BlockParser::Binary { entry, index, binary, encoder, code } => {
+            BlockParser::Binary { entry, index, binary, encoder, verifier, code } => {
                 let bytes = &binary[*index as usize ..];
 
                 let len = Instruction::length(bytes);
                 let instruction = Instruction::decode(bytes).unwrap();
                 let microcode = encoder.encode(&instruction).unwrap();
+                verifier.verify(&instruction, &microcode).unwrap();
                 code.push((*index + *entry, len, instruction, microcode));
                 *index += len;
 
@@ -442,17 +583,19 @@ impl<'a> BlockParser<'a> {
     }
 }
 
-/// Remove all cycles from a list of comparable items, where `cmp` determines
-/// if two items are equal. For example this turns 1 -> 2 -> 3 -> 2 -> 4 into
-/// 1 -> 2 -> 4.
-fn decycle<T: Clone, F>(sequence: &[T], cmp: F) -> Vec<T> where F: Fn(&T, &T) -> bool {
+/// Remove the cycles from a list of comparable items that would make an item
+/// occur more than `depth` times, where `cmp` determines if two items are
+/// equal. Such a cycle returns to the last occurrence of the item. For example
+/// with a depth of one this turns 1 -> 2 -> 3 -> 2 -> 4 into 1 -> 2 -> 4.
+fn decycle<T: Clone, F>(sequence: &[T], depth: usize, cmp: F) -> Vec<T>
+where F: Fn(&T, &T) -> bool {
     let mut out = Vec::new();
 
     for item in sequence {
-        if let Some(pos) = out.iter().position(|x| cmp(item, x)) {
-            for _ in 0 .. out.len() - pos - 1 {
-                out.pop();
-            }
+        let count = out.iter().filter(|x| cmp(item, x)).count();
+        if count >= depth {
+            let pos = out.iter().rposition(|x| cmp(item, x)).unwrap();
+            out.truncate(pos + 1);
         } else {
             out.push(item.clone());
         }
@@ -464,6 +607,7 @@ fn decycle<T: Clone, F>(sequence: &[T], cmp: F) -> Vec<T> where F: Fn(&T, &T) ->
 
 #[cfg(test)]
 mod tests {
+    use crate::flow::Limit;
     use crate::flow::visualize::test::compile;
     use super::*;
 
@@ -496,8 +640,108 @@ mod tests {
         test("min");
     }
 
+    #[test]
+    fn loop_unrolling() {
+        let program = Program::new("target/bin/loop");
+
+        let graph = ControlFlowGraph::new(&program);
+        assert!(graph.nodes.iter().all(|node| node.iteration == 0));
+        assert!(!graph.truncated.is_empty());
+
+        let mut config = ExplorationConfig::new();
+        config.loop_bound = 0;
+        let zero = ControlFlowGraph::with_config(&program, &config);
+        assert_eq!(zero.nodes, graph.nodes);
+
+        let mut config = ExplorationConfig::new();
+        config.loop_bound = 3;
+        let graph = ControlFlowGraph::with_config(&program, &config);
+        assert_eq!(graph.nodes.iter().map(|node| node.iteration).max(), Some(2));
+        assert!(!graph.truncated.is_empty());
+
+        // Without the truncated edges, the graph is acyclic.
+        let mut degrees: Vec<usize> = (0 .. graph.nodes.len())
+            .map(|index| graph.incoming[index].iter()
+                .filter(|&&start| !graph.truncated.contains(&(start, index)))
+                .count())
+            .collect();
+        let mut queue: Vec<usize> = (0 .. graph.nodes.len()).filter(|&i| degrees[i] == 0).collect();
+        let mut visited = 0;
+        while let Some(index) = queue.pop() {
+            visited += 1;
+            for &end in &graph.outgoing[index] {
+                if !graph.truncated.contains(&(index, end)) {
+                    degrees[end] -= 1;
+                    if degrees[end] == 0 {
+                        queue.push(end);
+                    }
+                }
+            }
+        }
+        assert_eq!(visited, graph.nodes.len());
+    }
+
+    #[test]
+    fn recursion_depth() {
+        let program = Program::new("target/bin/recursive-1");
+        let max_depth = |graph: &ControlFlowGraph| graph.nodes.iter()
+            .map(|node| node.trace.len())
+            .max()
+            .unwrap();
+
+        // By default, recursive calls share one context.
+        let graph = ControlFlowGraph::new(&program);
+        assert!(graph.nodes.iter().all(|node| {
+            node.trace.iter().enumerate().all(|(i, a)| node.trace[.. i].iter().all(|b| a.1 != b.1))
+        }));
+
+        let mut config = ExplorationConfig::new();
+        config.recursion_depth = Some(1);
+        let shallow = ControlFlowGraph::with_config(&program, &config);
+        config.recursion_depth = Some(0);
+        assert_eq!(ControlFlowGraph::with_config(&program, &config).nodes, shallow.nodes);
+        config.recursion_depth = Some(3);
+        let deep = ControlFlowGraph::with_config(&program, &config);
+
+        // Each level of recursion gets its own context and the calls
+        // beyond the deepest one are truncated.
+        assert_eq!(max_depth(&deep), max_depth(&shallow) + 2);
+        for graph in &[&shallow, &deep] {
+            assert!(graph.truncated.iter().any(|&(start, end)| {
+                graph.nodes[end].trace.len() == graph.nodes[start].trace.len()
+            }));
+        }
+    }
+
+    #[test]
+    fn partial_graph() {
+        let program = Program::new("target/bin/paths");
+        let full = ControlFlowGraph::new(&program);
+        assert!(full.report.is_complete());
+        assert_eq!(full.report.pending, 0);
+
+        let mut config = ExplorationConfig::new();
+        config.max_nodes = Some(5);
+        let graph = ControlFlowGraph::with_config(&program, &config);
+        assert_eq!(graph.report.stopped, Some(Limit::Nodes));
+        assert!(graph.nodes.len() < full.nodes.len());
+        assert_eq!(graph.nodes[0], full.nodes[0]);
+
+        // The partial graph only refers to parsed blocks.
+        assert!(graph.nodes.iter().all(|node| graph.blocks.contains_key(&node.addr)));
+        assert!(graph.edges.keys().all(|&(start, end)| {
+            start < graph.nodes.len() && end < graph.nodes.len()
+        }));
+
+        config = ExplorationConfig::new();
+        config.max_paths = Some(1);
+        let graph = ControlFlowGraph::with_config(&program, &config);
+        assert_eq!(graph.report.stopped, Some(Limit::Paths));
+        assert_eq!(graph.report.paths, 1);
+    }
+
     fn test_decycle(left: Vec<&str>, right: Vec<&str>) {
-        assert_eq!(decycle(&left, |a, b| a == b), right);
+        assert_eq!(decycle(&left, 1, |a, b| a == b), right);
     }
 
     #[test]
@@ -511,5 +755,11 @@ mod tests {
         test_decycle(vec!["main", "foo", "bar", "foo", "bar", "a"],
                      vec!["main", "foo", "bar", "a"]);
         test_decycle(vec!["main", "foo", "bar", "bar", "foo", "a"], vec!["main", "foo", "a"]);
+
+        let deep = |left: Vec<&'static str>| decycle(&left, 2, |a, b| a == b);
+        assert_eq!(deep(vec!["main", "fib", "fib"]), vec!["main", "fib", "fib"]);
+        assert_eq!(deep(vec!["main", "fib", "fib", "fib", "a"]), vec!["main", "fib", "fib", "a"]);
+        assert_eq!(deep(vec!["main", "foo", "bar", "foo", "bar", "foo", "a"]),
+                   vec!["main", "foo", "bar", "foo", "a"]);
     }
 }
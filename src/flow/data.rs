@@ -1,12 +1,12 @@
 //! Data flow analysis.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::rc::Rc;
 
 use crate::x86_64::Register;
-use crate::math::{SymCondition, Integer, Symbol, SharedSolver, Solver};
-use crate::sym::{SymState, Event, MemoryStrategy, TypedMemoryAccess, SymbolMap, StdioKind};
+use crate::math::{SymCondition, Integer, Symbol, SharedSolver, Solver, PathSolver, SatResult};
+use crate::sym::{SymState, Event, MemoryStrategy, TypedMemoryAccess, SymbolMap, StdioKind, EntryState};
 use super::*;
 
 
@@ -18,6 +18,11 @@ pub struct DataDependencyGraph {
     /// The conditions for data flow between the abstract locations.
     /// The key pairs are indices into the `nodes` vector.
     pub edges: HashMap<(usize, usize), (SymCondition, SymbolMap)>,
+    /// The edges whose conditions the solver could not decide. The flow
+    /// may be impossible, but it is kept to not miss any actual flow.
+    pub approximate: HashSet<(usize, usize)>,
+    /// How much of the control flow graph the exploration covered.
+    pub report: ExplorationReport,
 }
 
 /// A node in the data dependency graph, describing some kind of value.
@@ -30,8 +35,31 @@ pub enum DependencyNode {
 
 impl DataDependencyGraph {
     /// Create a new data dependency graph graph for all abstract locations.
+    ///
+    /// Like the control flow graph, the exploration starts with a symbolic
+    /// stack pointer and no process entry state.
     pub fn new(cfg: &ControlFlowGraph) -> DataDependencyGraph {
-        crate::timings::with("data-dependency-graph", || DataFlowExplorer::new(cfg).run())
+        DataDependencyGraph::with_config(cfg, None, &ExplorationConfig::new())
+    }
+
+    /// Create a new data dependency graph for a program started in the
+    /// given entry state, whose symbolic inputs become input nodes.
+    pub fn with_entry(cfg: &ControlFlowGraph, entry: &EntryState) -> DataDependencyGraph {
+        DataDependencyGraph::with_config(cfg, Some(entry), &ExplorationConfig::new())
+    }
+
+    /// Create a new data dependency graph whose exploration is bounded by
+    /// the budget of the configuration, optionally from an entry state. The
+    /// loop and recursion limits are already part of the control flow graph
+    /// and are not used here.
+    pub fn with_config(
+        cfg: &ControlFlowGraph,
+        entry: Option<&EntryState>,
+        config: &ExplorationConfig,
+    ) -> DataDependencyGraph {
+        crate::timings::with("data-dependency-graph", || {
+            DataFlowExplorer::new(cfg).run(entry, config)
+        })
     }
 
     /// Visualize this flow graph in a graphviz DOT file.
@@ -58,6 +86,10 @@ impl DataDependencyGraph {
                     let color = match kind {
                         StdioKind::Stdin => "#4caf50",
                         StdioKind::Stdout => "#03a9f4",
+                        StdioKind::Stderr => "#f44336",
+                        StdioKind::FileIn(_) => "#009688",
+                        StdioKind::FileOut(_) => "#3f51b5",
+                        StdioKind::Args | StdioKind::Env | StdioKind::Auxv => "#8bc34a",
                     };
 
                     writeln!(f, "b{} [label=<<b>{}</b>>,shape=box,style=filled,fillcolor=\"{}\"]",
@@ -109,6 +141,7 @@ struct DataFlowExplorer<'g> {
     solver: SharedSolver,
     nodes: HashMap<DependencyNode, usize>,
     edges: HashMap<(usize, usize), (SymCondition, SymbolMap)>,
+    approximate: HashSet<(usize, usize)>,
 }
 
 #[derive(Clone)]
@@ -139,6 +172,7 @@ impl<'g> DataFlowExplorer<'g> {
             solver: Rc::new(Solver::new()),
             nodes: HashMap::new(),
             edges: HashMap::new(),
+            approximate: HashSet::new(),
         }
     }
 
@@ -150,18 +184,51 @@ impl<'g> DataFlowExplorer<'g> {
     /// All direct flows are translated into edges with condition _True_ in the
     /// graph. Indirect flows through memory can have more complex conditions
     /// associated with them.
-    fn run(mut self) -> DataDependencyGraph {
-        let base_state = SymState::new(MemoryStrategy::ConditionalTrees, self.solver.clone());
+    fn run(mut self, entry: Option<&EntryState>, config: &ExplorationConfig) -> DataDependencyGraph {
+        let mut base_state = SymState::new(MemoryStrategy::ConditionalTrees, self.solver.clone());
+        let mut inputs = Vec::new();
+        if let Some(entry) = entry {
+            base_state.ip = self.cfg.nodes[0].addr;
+            inputs = base_state.enter(entry);
+        }
 
-        let mut targets = vec![ExplorationTarget {
+        // The preconditions of the explored path stay asserted in this solver.
+        // Since targets are explored depth-first, consecutive targets share
+        // most of their preconditions.
+        let solver = self.solver.clone();
+        let mut path = solver.path_solver();
+        let mut budget = Budget::new(config, &solver);
+        let mut stopped = None;
+
+        let mut start = ExplorationTarget {
             target: 0,
             state: base_state,
             preconditions: Vec::new(),
             location_links: HashMap::new(),
             write_accesses: Vec::new(),
-        }];
+        };
+
+        for event in inputs {
+            if let Event::Stdio(kind, ios) = event {
+                self.handle_io(&mut path, &mut start, kind, ios);
+            }
+        }
+
+        let mut targets = vec![start];
 
         while let Some(mut exp) = targets.pop() {
+            stopped = budget.exceeded(self.nodes.len(), &solver);
+            if stopped.is_some() {
+                targets.push(exp);
+                break;
+            }
+
+            // Paths that can never be taken cannot produce any flows.
+            path.enter(&exp.preconditions);
+            if !path.feasible() {
+                continue;
+            }
+
             let node = &self.cfg.nodes.get(exp.target)
                 .expect("data flow explorer: expected node in control flow graph");
 
@@ -173,7 +240,7 @@ impl<'g> DataFlowExplorer<'g> {
                 let next_addr = addr + len;
 
                 for (source, sink) in instruction.flows() {
-                    let sink_index = self.insert_loc(addr, &exp.state.trace, sink);
+                    let sink_index = self.insert_loc(addr, &exp.state, sink);
 
                     // The source may be a constant or a storage location.
                     let source_data = match source {
@@ -188,7 +255,7 @@ impl<'g> DataFlowExplorer<'g> {
                                 Some((sink, sink_index))
 
                             } else {
-                                let source_index = self.insert_loc(addr, &exp.state.trace, source);
+                                let source_index = self.insert_loc(addr, &exp.state, source);
                                 self.link_location(&mut exp, source, source_index, false);
 
                                 // For flows inherent to an instruction the condition is
@@ -215,7 +282,7 @@ impl<'g> DataFlowExplorer<'g> {
                         // For reading memory accesses we need to check if they alias
                         // with any of the previous writing accesses.
                         if let Some(access) = exp.state.get_access_for_storage(source) {
-                            self.handle_read_access(&exp, access, source_index);
+                            self.handle_read_access(&mut path, &exp, access, source_index);
                         }
                     }
 
@@ -232,15 +299,23 @@ impl<'g> DataFlowExplorer<'g> {
                 for op in &microcode.ops {
                     if let Some(event) = exp.state.step(next_addr, op) {
                         match event {
-                            Event::Stdio(kind, ios) => self.handle_io(&mut exp, kind, ios),
+                            Event::Stdio(kind, ios) => self.handle_io(&mut path, &mut exp, kind, ios),
                             _ => {},
                         }
                     }
                 }
             }
 
-            // Add all nodes reachable from that one as targets.
+            budget.collect_unmapped_reads(&mut exp.state.heap);
+
+            // Add all nodes reachable from that one as targets. Back edges
+            // of loops at their bound are not followed.
+            let pending = targets.len();
             for &id in &self.cfg.outgoing[exp.target] {
+                if self.cfg.truncated.contains(&(exp.target, id)) {
+                    continue;
+                }
+
                 let condition = &self.cfg.edges[&(exp.target, id)];
 
                 // If the arrow to the next basic block has a condition, we
@@ -258,13 +333,18 @@ impl<'g> DataFlowExplorer<'g> {
                     write_accesses: exp.write_accesses.clone(),
                 });
             }
+
+            if targets.len() == pending {
+                budget.finish_path();
+            }
         }
 
-        self.finish()
+        let report = budget.report(stopped, self.nodes.len(), targets.len(), &solver);
+        self.finish(report)
     }
 
     /// Arrange all data in the way expected for the flow graph.
-    fn finish(self) -> DataDependencyGraph {
+    fn finish(self, report: ExplorationReport) -> DataDependencyGraph {
         // Arrange the nodes into a vector.
         let default = DependencyNode::Constant(0, Integer::from_ptr(0));
         let mut nodes = vec![default; self.nodes.len()];
@@ -275,12 +355,15 @@ impl<'g> DataFlowExplorer<'g> {
         DataDependencyGraph {
             nodes,
             edges: self.edges,
+            approximate: self.approximate,
+            report,
         }
     }
 
     /// Add I/O nodes and abstract locations for reads and writes.
     fn handle_io(
         &mut self,
+        path: &mut PathSolver,
         exp: &mut ExplorationTarget,
         kind: StdioKind,
         ios: Vec<(Symbol, TypedMemoryAccess)>
@@ -297,17 +380,14 @@ impl<'g> DataFlowExplorer<'g> {
                 (location_index, exp.preconditions.len())
             );
 
-            // If it is a stdin read, that is, a memory write, add it
+            // If it is an input, that is, a memory write, add it
             // to the write access list.
-            match kind {
-                StdioKind::Stdin => {
-                    exp.write_accesses.push((location_index, access, exp.preconditions.len()));
-                    self.insert_pre_edge(&exp, 0, index, location_index);
-                },
-                StdioKind::Stdout => {
-                    self.handle_read_access(exp, access, location_index);
-                    self.insert_pre_edge(&exp, 0, location_index, index);
-                },
+            if kind.is_input() {
+                exp.write_accesses.push((location_index, access, exp.preconditions.len()));
+                self.insert_pre_edge(&exp, 0, index, location_index);
+            } else {
+                self.handle_read_access(path, exp, access, location_index);
+                self.insert_pre_edge(&exp, 0, location_index, index);
             }
         }
     }
@@ -316,6 +396,7 @@ impl<'g> DataFlowExplorer<'g> {
     /// conditional edges in between if necessary.
     fn handle_read_access(
         &mut self,
+        path: &mut PathSolver,
         exp: &ExplorationTarget,
         read: TypedMemoryAccess,
         location_index: usize
@@ -323,7 +404,21 @@ impl<'g> DataFlowExplorer<'g> {
         let mut overwritten = SymCondition::FALSE;
 
         for (prev_index, prev, num_preconditions) in exp.write_accesses.iter().rev() {
-            let mut alias = determine_alias(prev, &read);
+            // Accesses into different memory regions can never alias.
+            if exp.state.memory[0].regions().disjoint(&prev.0, &read.0) {
+                continue;
+            }
+
+            // We have to make sure a later write has not overwritten this one.
+            let mut alias = determine_alias(prev, &read).and(overwritten.clone().not());
+
+            // The preconditions of the path are already asserted, so accesses
+            // that cannot alias on this path are ruled out cheaply. If the
+            // solver gives up, the flow is kept but marked as approximate.
+            let sat = path.check_sat(&alias);
+            if sat == SatResult::Unsat {
+                continue;
+            }
 
             // Any condition that has to be met on this path *additionally* to those
             // already active for the current write have to be included in the conditions.
@@ -331,15 +426,20 @@ impl<'g> DataFlowExplorer<'g> {
                 alias = alias.and(pre.clone());
             }
 
-            // We have to make sure a later write has not overwritten this one.
-            alias = alias.and(overwritten.clone().not());
-
             crate::timings::with("simplify-alias", || {
                 alias = self.solver.simplify_condition(&alias);
             });
 
             // If this cannot match with the neccessary preconditions, we can skip this, too.
             if alias != SymCondition::FALSE {
+                // The flow is also approximate if the memory could not rule
+                // out the write either, since the value read then contains it.
+                let approximate = sat == SatResult::Unknown
+                    || exp.state.memory[0].may_overlap(prev, &read) == SatResult::Unknown;
+                if approximate {
+                    self.approximate.insert((*prev_index, location_index));
+                }
+
                 self.insert_edge(exp, *prev_index, location_index, alias);
 
                 // Determine the condition that the read access was overwritten by the
@@ -394,8 +494,8 @@ impl<'g> DataFlowExplorer<'g> {
     }
 
     /// Insert a new abstract location node for a storage location in a context.
-    fn insert_loc(&mut self, addr: u64, trace: &[u64], storage: StorageLocation) -> usize {
-        let location = AbstractLocation::new(addr, trace.to_vec(), storage);
+    fn insert_loc(&mut self, addr: u64, state: &SymState, storage: StorageLocation) -> usize {
+        let location = state.abstract_location(addr, storage);
         let node = DependencyNode::Location(location);
         self.insert_node(node)
     }
@@ -474,6 +574,111 @@ mod tests {
         });
     }
 
+    #[test]
+    fn partial_data_flow() {
+        let program = Program::new("target/bin/paths");
+        let cfg = ControlFlowGraph::new(&program);
+        let full = DataDependencyGraph::new(&cfg);
+        assert!(full.report.is_complete());
+        assert!(full.report.solver_queries > 0);
+
+        let mut config = ExplorationConfig::new();
+        config.max_solver_queries = Some(1);
+        let ddg = DataDependencyGraph::with_config(&cfg, None, &config);
+        assert_eq!(ddg.report.stopped, Some(Limit::SolverQueries));
+        assert!(ddg.report.pending > 0);
+        assert!(ddg.nodes.len() < full.nodes.len());
+
+        let token = CancellationToken::new();
+        token.cancel();
+        config = ExplorationConfig::new();
+        config.cancellation = Some(token);
+        let ddg = DataDependencyGraph::with_config(&cfg, None, &config);
+        assert_eq!(ddg.report.stopped, Some(Limit::Cancelled));
+        assert_eq!(ddg.report.paths, 0);
+    }
+
+    #[test]
+    fn entry_inputs() {
+        let program = Program::new("target/bin/min");
+        let cfg = ControlFlowGraph::new(&program);
+        let is_arg = |node: &DependencyNode| matches!(node, DependencyNode::Io(StdioKind::Args, _));
+
+        // The entry state is only used when asked for.
+        let ddg = DataDependencyGraph::new(&cfg);
+        assert!(!ddg.nodes.iter().any(is_arg));
+
+        let mut entry = EntryState::new();
+        entry.args = vec![2];
+        let ddg = DataDependencyGraph::with_entry(&cfg, &entry);
+        assert_eq!(ddg.nodes.iter().filter(|node| is_arg(node)).count(), 2);
+    }
+
+    #[test]
+    fn approximate_memory() {
+        use std::collections::HashMap;
+        use crate::math::{SymExpr, SolverBackend, PathBackend, Z3Backend};
+
+        // Decides path conditions, but gives up on the aliasing checks of
+        // the symbolic memory.
+        struct Undecided(Z3Backend);
+        impl SolverBackend for Undecided {
+            fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+                self.0.simplify_expr(expr)
+            }
+            fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
+                self.0.simplify_condition(cond)
+            }
+            fn check_sat(&self, _: &SymCondition) -> SatResult {
+                SatResult::Unknown
+            }
+            fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+                self.0.get_model(cond)
+            }
+            fn set_timeout(&mut self, _: Option<std::time::Duration>) {}
+            fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+                self.0.path_backend()
+            }
+            fn name(&self) -> String {
+                "undecided".to_string()
+            }
+        }
+
+        let program = Program::new("target/bin/bufs");
+        let cfg = ControlFlowGraph::new(&program);
+        let full = DataDependencyGraph::new(&cfg);
+
+        let mut explorer = DataFlowExplorer::new(&cfg);
+        explorer.solver = Rc::new(Solver::with_backend(Box::new(Undecided(Z3Backend::new()))));
+        let ddg = explorer.run(None, &ExplorationConfig::new());
+
+        // Only the memory could not decide aliasing, the flows themselves
+        // were checked on the path.
+        assert!(full.approximate.is_empty());
+        assert!(!ddg.approximate.is_empty());
+        assert!(ddg.approximate.len() < ddg.edges.len());
+        for edge in &ddg.approximate {
+            assert!(ddg.edges.contains_key(edge));
+        }
+    }
+
+    #[test]
+    fn data_models() {
+        let program = Program::new("target/bin/bufs");
+        let cfg = ControlFlowGraph::new(&program);
+        let ddg = DataDependencyGraph::new(&cfg);
+        let solver = Solver::new();
+
+        // Every flow that can happen at all must have an input triggering it.
+        for (condition, _) in ddg.edges.values() {
+            if let Some(model) = solver.get_model(condition) {
+                assert!(condition.evaluate(&|symbol| model.get(&symbol).copied()));
+            } else {
+                assert_eq!(solver.simplify_condition(condition), SymCondition::FALSE);
+            }
+        }
+    }
+
     #[test] fn data_bufs() { test("bufs") }
     #[test] fn data_paths() { test("paths") }
     #[test] fn data_deep() { test("deep") }
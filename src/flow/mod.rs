@@ -2,16 +2,21 @@
 
 use std::fmt::{self, Display, Formatter};
 use crate::math::{Integer, DataType};
+use crate::sym::Region;
 use crate::x86_64::{Register, Operand};
 
 mod control;
+mod budget;
 mod alias;
 mod data;
+mod ssa;
 mod visualize;
 
 pub use control::*;
+pub use budget::*;
 pub use alias::*;
 pub use data::*;
+pub use ssa::*;
 
 
 /// A storage location within the context in which it is valid.
@@ -23,11 +28,14 @@ pub struct AbstractLocation {
     pub trace: Vec<u64>,
     /// The storage location.
     pub storage: StorageLocation,
+    /// The heap object an indirect location points into if it is known.
+    /// Other regions are not recorded since they follow from the base register.
+    pub region: Option<Region>,
 }
 
 impl AbstractLocation {
     pub fn new(addr: u64, trace: Vec<u64>, storage: StorageLocation) -> AbstractLocation {
-        AbstractLocation { addr, trace, storage }
+        AbstractLocation { addr, trace, storage, region: None }
     }
 }
 
@@ -106,7 +114,11 @@ pub enum ValueSource {
 
 impl Display for AbstractLocation {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{} at {:x}", self.storage, self.addr)?;
+        write!(f, "{}", self.storage)?;
+        if let Some(region) = self.region {
+            write!(f, " in {}", region)?;
+        }
+        write!(f, " at {:x}", self.addr)?;
         if !self.trace.is_empty() {
             write!(f, " by ")?;
         }
@@ -0,0 +1,935 @@
+//! Static single assignment form of control flow graph regions.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::ir::{MicroOperation, Location, Temporary, MemoryMapped};
+use crate::math::{SymExpr, SymCondition, DataType, Symbol, Traversed};
+use crate::x86_64::{Mnemoic, Register};
+use super::ControlFlowGraph;
+use DataType::*;
+
+
+/// A region of a control flow graph in static single assignment form.
+///
+/// Registers and temporaries are variables which get a new versioned value
+/// whenever they are written. Where control flow joins, phi nodes select the
+/// version depending on the predecessor. The flags are not stored explicitly in
+/// the microcode: conditions refer to the temporaries of the last flag-modifying
+/// operation, which are therefore versioned the same way.
+///
+/// Main memory is not versioned but accessed through loads and stores.
+#[derive(Debug, Clone)]
+pub struct SsaFunction {
+    /// The blocks of the region. The first one is the entry.
+    pub blocks: Vec<SsaBlock>,
+    /// The values the variables have when the entry block is entered.
+    pub inputs: Vec<(Variable, Value)>,
+    /// The number of values defined in the function.
+    pub values: usize,
+}
+
+/// A basic block in static single assignment form.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsaBlock {
+    /// The index of the node in the control flow graph.
+    pub node: usize,
+    /// The start address of the block.
+    pub addr: u64,
+    /// The phi nodes at the start of the block.
+    pub phis: Vec<Phi>,
+    /// The operations of the block.
+    pub operations: Vec<SsaOperation>,
+    /// The indices of the blocks with edges to this block.
+    pub predecessors: Vec<usize>,
+    /// The indices of the blocks this block has edges to and the conditions
+    /// under which the edges are taken.
+    pub successors: Vec<(usize, SymCondition)>,
+}
+
+/// Selects the value of a variable depending on the predecessor block.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Phi {
+    pub dest: Value,
+    pub variable: Variable,
+    /// The value for each predecessor block (by index).
+    pub sources: Vec<(usize, Value)>,
+}
+
+/// An operation in static single assignment form.
+///
+/// The expressions only contain integers and value symbols (`v0`, `v1`, ...).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SsaOperation {
+    /// Define the value as the result of the expression.
+    Assign { dest: Value, expr: SymExpr },
+    /// Load a value from main memory.
+    Load { dest: Value, addr: SymExpr },
+    /// Store a value in main memory.
+    Store { addr: SymExpr, value: SymExpr },
+    /// Perform a syscall with the values of RAX, RDI, RSI and RDX as arguments
+    /// and define the new value of RAX.
+    Syscall { args: Vec<SymExpr>, result: Value },
+    /// Call a function, which defines new values for all caller-saved registers.
+    Call { target: SymExpr, results: Vec<(Register, Value)> },
+    /// Jump to an absolute address if the condition is true.
+    Jump { target: SymExpr, condition: SymCondition },
+}
+
+/// Something that is assigned versioned values.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Variable {
+    /// A 64-bit base register. Writes to the smaller registers sharing its
+    /// memory create new versions of it.
+    Register(Register),
+    /// A microcode temporary.
+    Temp(Temporary),
+}
+
+/// A value defined exactly once, identified by an index.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Value(pub DataType, pub usize);
+
+impl Value {
+    /// Create a symbol matching this value.
+    pub fn to_symbol(self) -> Symbol {
+        Symbol(self.0, "v", self.1)
+    }
+
+    /// Convert this into a symbol expression.
+    pub fn to_expr(self) -> SymExpr {
+        SymExpr::Sym(self.to_symbol())
+    }
+}
+
+/// The registers whose values are not preserved by a function call.
+const CALLER_SAVED: [Register; 9] = [
+    Register::RAX, Register::RCX, Register::RDX, Register::RSI, Register::RDI,
+    Register::R8, Register::R9, Register::R10, Register::R11,
+];
+
+/// The 64-bit registers, used to find the variable for a register address.
+const BASE_REGISTERS: [Register; 17] = [
+    Register::RAX, Register::RCX, Register::RDX, Register::RBX,
+    Register::RSP, Register::RBP, Register::RSI, Register::RDI,
+    Register::R8, Register::R9, Register::R10, Register::R11,
+    Register::R12, Register::R13, Register::R14, Register::R15,
+    Register::RIP,
+];
+
+impl SsaFunction {
+    /// Lift the function starting at the node with the given index.
+    ///
+    /// The region contains all blocks reachable from the entry in the same
+    /// call context. Calls are not followed, but continue at the return site
+    /// after a call operation.
+    pub fn from_function(graph: &ControlFlowGraph, entry: usize) -> SsaFunction {
+        let context = &graph.nodes[entry].trace;
+
+        let mut region = vec![entry];
+        let mut edges = Vec::new();
+        let mut visited = HashSet::new();
+        visited.insert(entry);
+
+        let mut index = 0;
+        while index < region.len() {
+            let node = region[index];
+            let block = &graph.blocks[&graph.nodes[node].addr];
+            index += 1;
+
+            let targets = match block.code.last().map(|code| code.2.mnemoic) {
+                // Returns leave the function.
+                Some(Mnemoic::Ret) => vec![],
+
+                // Calls continue at the return site in the same context.
+                Some(Mnemoic::Call) => {
+                    let return_site = block.addr + block.len;
+                    graph.nodes.iter()
+                        .position(|n| n.addr == return_site && &n.trace == context)
+                        .or_else(|| graph.nodes.iter().position(|n| n.addr == return_site))
+                        .map(|target| (target, SymCondition::TRUE))
+                        .into_iter()
+                        .collect()
+                },
+
+                _ => graph.outgoing[node].iter()
+                    .map(|&target| (target, graph.edges[&(node, target)].clone()))
+                    .collect(),
+            };
+
+            for (target, condition) in targets {
+                if visited.insert(target) {
+                    region.push(target);
+                }
+                edges.push((node, target, condition));
+            }
+        }
+
+        SsaFunction::from_region(graph, &region, &edges)
+    }
+
+    /// Lift a region of the control flow graph. The first node is the entry
+    /// of the region and the edges are given as (start, end, condition) triples
+    /// of node indices.
+    ///
+    /// Panics if the entry is the target of an edge in the region.
+    pub fn from_region(
+        graph: &ControlFlowGraph,
+        region: &[usize],
+        edges: &[(usize, usize, SymCondition)]
+    ) -> SsaFunction {
+        crate::timings::with("ssa", || SsaBuilder::new(graph, region, edges).run())
+    }
+
+    /// Find the operation or phi node defining the value.
+    pub fn definition(&self, value: Value) -> Option<Definition> {
+        for (b, block) in self.blocks.iter().enumerate() {
+            for (index, phi) in block.phis.iter().enumerate() {
+                if phi.dest == value {
+                    return Some(Definition::Phi(b, index));
+                }
+            }
+
+            for (index, op) in block.operations.iter().enumerate() {
+                if op.defines().contains(&value) {
+                    return Some(Definition::Operation(b, index));
+                }
+            }
+        }
+
+        self.inputs.iter()
+            .find(|input| input.1 == value)
+            .map(|input| Definition::Input(input.0))
+    }
+
+    /// Replace all uses of values by other values.
+    fn substitute(&mut self, map: &HashMap<Value, Value>) {
+        let resolve = |mut value: Value| {
+            while let Some(&next) = map.get(&value) {
+                value = next;
+            }
+            value
+        };
+
+        let replace = |sym: Symbol| match sym {
+            Symbol(data_type, "v", index) => resolve(Value(data_type, index)).to_expr(),
+            sym => SymExpr::Sym(sym),
+        };
+
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                for source in &mut phi.sources {
+                    source.1 = resolve(source.1);
+                }
+            }
+
+            for op in &mut block.operations {
+                op.replace_symbols(&replace);
+            }
+        }
+    }
+}
+
+/// Where a value is defined.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Definition {
+    /// The value is the input value of the variable.
+    Input(Variable),
+    /// By the phi node with the index in the block with the index.
+    Phi(usize, usize),
+    /// By the operation with the index in the block with the index.
+    Operation(usize, usize),
+}
+
+impl SsaOperation {
+    /// The values defined by this operation.
+    pub fn defines(&self) -> Vec<Value> {
+        match self {
+            SsaOperation::Assign { dest, .. } | SsaOperation::Load { dest, .. } => vec![*dest],
+            SsaOperation::Syscall { result, .. } => vec![*result],
+            SsaOperation::Call { results, .. } => results.iter().map(|r| r.1).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The values used by this operation.
+    pub fn uses(&self) -> Vec<Value> {
+        let mut values = Vec::new();
+        let mut collect = |node: Traversed| {
+            if let Traversed::Expr(&SymExpr::Sym(Symbol(data_type, "v", index))) = node {
+                values.push(Value(data_type, index));
+            }
+        };
+
+        match self {
+            SsaOperation::Assign { expr, .. } => expr.traverse(&mut collect),
+            SsaOperation::Load { addr, .. } => addr.traverse(&mut collect),
+            SsaOperation::Store { addr, value } => {
+                addr.traverse(&mut collect);
+                value.traverse(&mut collect);
+            },
+            SsaOperation::Syscall { args, .. } => {
+                for arg in args {
+                    arg.traverse(&mut collect);
+                }
+            },
+            SsaOperation::Call { target, .. } => target.traverse(&mut collect),
+            SsaOperation::Jump { target, condition } => {
+                target.traverse(&mut collect);
+                condition.traverse(&mut collect);
+            },
+        }
+
+        values
+    }
+
+    /// Replace the symbols in all expressions of the operation.
+    fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
+        match self {
+            SsaOperation::Assign { expr, .. } => expr.replace_symbols(symbols),
+            SsaOperation::Load { addr, .. } => addr.replace_symbols(symbols),
+            SsaOperation::Store { addr, value } => {
+                addr.replace_symbols(symbols);
+                value.replace_symbols(symbols);
+            },
+            SsaOperation::Syscall { args, .. } => {
+                for arg in args {
+                    arg.replace_symbols(symbols);
+                }
+            },
+            SsaOperation::Call { target, .. } => target.replace_symbols(symbols),
+            SsaOperation::Jump { target, condition } => {
+                target.replace_symbols(symbols);
+                condition.replace_symbols(symbols);
+            },
+        }
+    }
+}
+
+/// Builds the static single assignment form of a region.
+///
+/// Uses the algorithm by Braun et al. ("Simple and Efficient Construction of
+/// Static Single Assignment Form"): Variables are looked up recursively in the
+/// predecessors and phi nodes are only placed where a variable is actually read.
+struct SsaBuilder<'g> {
+    graph: &'g ControlFlowGraph,
+    function: SsaFunction,
+    /// The latest value of each variable at the end of the block.
+    defs: Vec<HashMap<Variable, Value>>,
+    /// Phi nodes created before all predecessors of the block were filled.
+    incomplete: Vec<Vec<usize>>,
+    filled: Vec<bool>,
+    sealed: Vec<bool>,
+    /// The blocks ending in calls.
+    calls: HashSet<usize>,
+}
+
+impl<'g> SsaBuilder<'g> {
+    fn new(
+        graph: &'g ControlFlowGraph,
+        region: &[usize],
+        edges: &[(usize, usize, SymCondition)]
+    ) -> SsaBuilder<'g> {
+        let index: HashMap<usize, usize> = region.iter()
+            .enumerate()
+            .map(|(i, &node)| (node, i))
+            .collect();
+
+        let mut blocks: Vec<SsaBlock> = region.iter()
+            .map(|&node| SsaBlock {
+                node,
+                addr: graph.nodes[node].addr,
+                phis: Vec::new(),
+                operations: Vec::new(),
+                predecessors: Vec::new(),
+                successors: Vec::new(),
+            })
+            .collect();
+
+        for (start, end, condition) in edges {
+            let (start, end) = (index[start], index[end]);
+            assert!(end != 0, "ssa: entry block of region is target of an edge");
+            blocks[start].successors.push((end, condition.clone()));
+            blocks[end].predecessors.push(start);
+        }
+
+        let calls = blocks.iter()
+            .enumerate()
+            .filter(|(_, block)| {
+                let code = &graph.blocks[&block.addr].code;
+                code.last().map(|c| c.2.mnemoic) == Some(Mnemoic::Call)
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        let count = blocks.len();
+        SsaBuilder {
+            graph,
+            function: SsaFunction { blocks, inputs: Vec::new(), values: 0 },
+            defs: vec![HashMap::new(); count],
+            incomplete: vec![Vec::new(); count],
+            filled: vec![false; count],
+            sealed: vec![false; count],
+            calls,
+        }
+    }
+
+    /// Lift all blocks and clean up.
+    fn run(mut self) -> SsaFunction {
+        self.seal_ready();
+
+        for block in self.reverse_postorder() {
+            self.fill(block);
+
+            // The edge conditions refer to the temporaries at the end of the block.
+            for index in 0 .. self.function.blocks[block].successors.len() {
+                let condition = self.function.blocks[block].successors[index].1.clone();
+                let condition = self.read_condition(block, &condition);
+                self.function.blocks[block].successors[index].1 = condition;
+            }
+
+            self.filled[block] = true;
+            self.seal_ready();
+        }
+
+        self.remove_trivial_phis();
+        self.function
+    }
+
+    /// The block indices in reverse postorder, so that all predecessors of a
+    /// block except for those through back edges come before it.
+    fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.function.blocks.len()];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+
+        while let Some((block, next)) = stack.pop() {
+            let successors = &self.function.blocks[block].successors;
+            if next < successors.len() {
+                stack.push((block, next + 1));
+                let target = successors[next].0;
+                if !visited[target] {
+                    visited[target] = true;
+                    stack.push((target, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    /// Seal all blocks whose predecessors are all filled.
+    fn seal_ready(&mut self) {
+        for block in 0 .. self.function.blocks.len() {
+            let ready = self.function.blocks[block].predecessors.iter()
+                .all(|&pred| self.filled[pred]);
+
+            if !self.sealed[block] && ready {
+                self.sealed[block] = true;
+                for phi in std::mem::take(&mut self.incomplete[block]) {
+                    self.add_phi_sources(block, phi);
+                }
+            }
+        }
+    }
+
+    /// Translate the microcode of a block.
+    fn fill(&mut self, block: usize) {
+        use MicroOperation as Op;
+
+        let graph = self.graph;
+        let code = &graph.blocks[&self.function.blocks[block].addr].code;
+
+        for (addr, len, _, microcode) in code {
+            let next_addr = addr + len;
+            for op in &microcode.ops {
+                match op {
+                    Op::Mov { dest, src } => {
+                        let value = self.read_location(block, *src, next_addr);
+                        self.write_location(block, *dest, value);
+                    },
+
+                    Op::Const { dest, constant } => {
+                        self.write_temp(block, *dest, SymExpr::Int(*constant));
+                    },
+                    Op::Cast { target, new, signed } => {
+                        let value = self.read_temp(block, *target).cast(*new, *signed);
+                        self.write_temp(block, Temporary(*new, target.1), value);
+                    },
+
+                    Op::Add { sum, a, b } => self.binop(block, *sum, *a, *b, SymExpr::add),
+                    Op::Sub { diff, a, b } => self.binop(block, *diff, *a, *b, SymExpr::sub),
+                    Op::Mul { prod, a, b } => self.binop(block, *prod, *a, *b, SymExpr::mul),
+
+                    Op::And { and, a, b } => self.binop(block, *and, *a, *b, SymExpr::bitand),
+                    Op::Or { or, a, b } => self.binop(block, *or, *a, *b, SymExpr::bitor),
+                    Op::Not { not, a } => {
+                        let value = self.read_temp(block, *a).bitnot();
+                        self.write_temp(block, *not, value);
+                    },
+
+                    Op::Set { target, condition } => {
+                        let value = self.read_condition(block, condition).as_expr(target.0);
+                        self.write_temp(block, *target, value);
+                    },
+
+                    Op::Jump { target, condition, relative } => {
+                        let mut target = self.read_temp(block, *target);
+                        if *relative {
+                            target = target.add(SymExpr::from_ptr(next_addr));
+                        }
+
+                        if self.calls.contains(&block) {
+                            self.call(block, target);
+                        } else {
+                            let condition = self.read_condition(block, condition);
+                            self.push(block, SsaOperation::Jump { target, condition });
+                        }
+
+                        return;
+                    },
+
+                    Op::Syscall => {
+                        let args = [Register::RAX, Register::RDI, Register::RSI, Register::RDX]
+                            .iter()
+                            .map(|&reg| self.read_variable(block, Variable::Register(reg), N64))
+                            .map(Value::to_expr)
+                            .collect();
+
+                        let result = self.new_value(N64);
+                        self.push(block, SsaOperation::Syscall { args, result });
+                        self.defs[block].insert(Variable::Register(Register::RAX), result);
+                    },
+                }
+            }
+        }
+    }
+
+    /// Translate a call, after which the caller-saved registers have new values
+    /// and the return address is popped from the stack.
+    fn call(&mut self, block: usize, target: SymExpr) {
+        let results: Vec<_> = CALLER_SAVED.iter()
+            .map(|&reg| (reg, self.new_value(N64)))
+            .collect();
+
+        for &(reg, value) in &results {
+            self.defs[block].insert(Variable::Register(reg), value);
+        }
+
+        self.push(block, SsaOperation::Call { target, results });
+
+        let rsp = self.read_register(block, Register::RSP);
+        self.write_register(block, Register::RSP, rsp.add(SymExpr::from_ptr(8)));
+    }
+
+    /// Read the value of a microcode location.
+    fn read_location(&mut self, block: usize, src: Location, next_addr: u64) -> SymExpr {
+        match src {
+            Location::Temp(temp) => self.read_temp(block, temp),
+
+            // The instruction pointer is constant for each instruction.
+            Location::Direct(data_type, 1, addr) if addr == Register::RIP.address() => {
+                SymExpr::from_ptr(next_addr).cast(data_type, false)
+            },
+            Location::Direct(data_type, 1, addr) => {
+                self.read_register(block, register_at(addr)).cast(data_type, false)
+            },
+
+            Location::Direct(data_type, _, addr) => {
+                self.load(block, SymExpr::from_ptr(addr), data_type)
+            },
+            Location::Indirect(data_type, _, temp) => {
+                let addr = self.read_temp(block, temp);
+                self.load(block, addr, data_type)
+            },
+        }
+    }
+
+    /// Write a value into a microcode location.
+    fn write_location(&mut self, block: usize, dest: Location, value: SymExpr) {
+        match dest {
+            Location::Temp(temp) => self.write_temp(block, temp, value),
+
+            Location::Direct(data_type, 1, addr) => {
+                let reg = register_at(addr);
+                let value = match data_type {
+                    N64 => value,
+                    // Like on the real CPU, writing a 32-bit register clears the
+                    // upper half of the 64-bit register.
+                    N32 => value.cast(N64, false),
+                    _ => {
+                        let mask = (1u64 << data_type.bits()) - 1;
+                        self.read_register(block, reg)
+                            .bitand(SymExpr::from_ptr(!mask))
+                            .bitor(value.cast(N64, false))
+                    },
+                };
+                self.write_register(block, reg, value);
+            },
+
+            Location::Direct(_, _, addr) => {
+                let addr = SymExpr::from_ptr(addr);
+                self.push(block, SsaOperation::Store { addr, value });
+            },
+            Location::Indirect(_, _, temp) => {
+                let addr = self.read_temp(block, temp);
+                self.push(block, SsaOperation::Store { addr, value });
+            },
+        }
+    }
+
+    /// Translate a binary operation on temporaries.
+    fn binop<F>(&mut self, block: usize, target: Temporary, a: Temporary, b: Temporary, binop: F)
+    where F: FnOnce(SymExpr, SymExpr) -> SymExpr {
+        let value = binop(self.read_temp(block, a), self.read_temp(block, b));
+        self.write_temp(block, target, value);
+    }
+
+    /// Replace the temporaries in the condition by their current values.
+    fn read_condition(&mut self, block: usize, condition: &SymCondition) -> SymCondition {
+        let mut temps = Vec::new();
+        condition.traverse(&mut |node| {
+            if let Traversed::Expr(&SymExpr::Sym(Symbol(data_type, "T", index))) = node {
+                temps.push(Temporary(data_type, index));
+            }
+        });
+
+        let values: HashMap<usize, SymExpr> = temps.into_iter()
+            .map(|temp| (temp.1, self.read_temp(block, temp)))
+            .collect();
+
+        let mut condition = condition.clone();
+        condition.replace_symbols(&|sym| match sym {
+            Symbol(_, "T", index) => values[&index].clone(),
+            sym => SymExpr::Sym(sym),
+        });
+        condition
+    }
+
+    /// Load a value from main memory.
+    fn load(&mut self, block: usize, addr: SymExpr, data_type: DataType) -> SymExpr {
+        let dest = self.new_value(data_type);
+        self.push(block, SsaOperation::Load { dest, addr });
+        dest.to_expr()
+    }
+
+    fn read_temp(&mut self, block: usize, temp: Temporary) -> SymExpr {
+        self.read_variable(block, Variable::Temp(temp), temp.0).to_expr()
+    }
+
+    fn write_temp(&mut self, block: usize, temp: Temporary, value: SymExpr) {
+        let value = self.define(block, value);
+        self.defs[block].insert(Variable::Temp(temp), value);
+    }
+
+    fn read_register(&mut self, block: usize, reg: Register) -> SymExpr {
+        self.read_variable(block, Variable::Register(reg), N64).to_expr()
+    }
+
+    fn write_register(&mut self, block: usize, reg: Register, value: SymExpr) {
+        let value = self.define(block, value);
+        self.defs[block].insert(Variable::Register(reg), value);
+    }
+
+    /// Return the value for the expression, assigning a new one if it is not
+    /// just a value already.
+    fn define(&mut self, block: usize, expr: SymExpr) -> Value {
+        if let SymExpr::Sym(Symbol(data_type, "v", index)) = expr {
+            Value(data_type, index)
+        } else {
+            let dest = self.new_value(expr.data_type());
+            self.push(block, SsaOperation::Assign { dest, expr });
+            dest
+        }
+    }
+
+    /// Find the value of a variable at the end of the block.
+    fn read_variable(&mut self, block: usize, variable: Variable, data_type: DataType) -> Value {
+        if let Some(&value) = self.defs[block].get(&variable) {
+            return value;
+        }
+
+        let predecessors = self.function.blocks[block].predecessors.clone();
+        let value = if !self.sealed[block] {
+            // Not all predecessors are known yet, so we need a phi node
+            // which is completed when the block is sealed.
+            let phi = self.new_phi(block, variable, data_type);
+            self.incomplete[block].push(phi);
+            self.function.blocks[block].phis[phi].dest
+        } else if predecessors.is_empty() {
+            let value = self.new_value(data_type);
+            self.function.inputs.push((variable, value));
+            value
+        } else if predecessors.len() == 1 {
+            self.read_variable(predecessors[0], variable, data_type)
+        } else {
+            // Define the phi node first to break cycles through loops.
+            let phi = self.new_phi(block, variable, data_type);
+            let value = self.function.blocks[block].phis[phi].dest;
+            self.defs[block].insert(variable, value);
+            self.add_phi_sources(block, phi);
+            value
+        };
+
+        self.defs[block].insert(variable, value);
+        value
+    }
+
+    fn new_phi(&mut self, block: usize, variable: Variable, data_type: DataType) -> usize {
+        let dest = self.new_value(data_type);
+        let phis = &mut self.function.blocks[block].phis;
+        phis.push(Phi { dest, variable, sources: Vec::new() });
+        phis.len() - 1
+    }
+
+    fn add_phi_sources(&mut self, block: usize, phi: usize) {
+        let variable = self.function.blocks[block].phis[phi].variable;
+        let data_type = self.function.blocks[block].phis[phi].dest.0;
+
+        for pred in self.function.blocks[block].predecessors.clone() {
+            let value = self.read_variable(pred, variable, data_type);
+            self.function.blocks[block].phis[phi].sources.push((pred, value));
+        }
+    }
+
+    /// Remove phi nodes which select only one value (besides themselves) and
+    /// replace their uses by that value.
+    fn remove_trivial_phis(&mut self) {
+        loop {
+            let mut map = HashMap::new();
+
+            for block in &mut self.function.blocks {
+                block.phis.retain(|phi| {
+                    let mut sources = phi.sources.iter()
+                        .map(|source| source.1)
+                        .filter(|&value| value != phi.dest);
+
+                    match sources.next() {
+                        Some(first) if sources.all(|value| value == first) => {
+                            map.insert(phi.dest, first);
+                            false
+                        },
+                        _ => true,
+                    }
+                });
+            }
+
+            if map.is_empty() {
+                break;
+            }
+
+            self.function.substitute(&map);
+        }
+    }
+
+    fn new_value(&mut self, data_type: DataType) -> Value {
+        let value = Value(data_type, self.function.values);
+        self.function.values += 1;
+        value
+    }
+
+    fn push(&mut self, block: usize, op: SsaOperation) {
+        self.function.blocks[block].operations.push(op);
+    }
+}
+
+/// The 64-bit register at the address in the register memory space.
+fn register_at(addr: u64) -> Register {
+    *BASE_REGISTERS.iter()
+        .find(|reg| reg.address() == addr)
+        .unwrap_or_else(|| panic!("register_at: no register at address {:#x}", addr))
+}
+
+impl Display for SsaFunction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "SsaFunction [")?;
+
+        write!(f, "    inputs:")?;
+        for (variable, value) in &self.inputs {
+            write!(f, " {} = {}", variable, value)?;
+        }
+        writeln!(f)?;
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "    b{} ({:x}):", index, block.addr)?;
+            for phi in &block.phis {
+                writeln!(f, "        {}", phi)?;
+            }
+            for op in &block.operations {
+                writeln!(f, "        {}", op)?;
+            }
+            for (target, condition) in &block.successors {
+                write!(f, "        -> b{}", target)?;
+                if condition != &SymCondition::TRUE {
+                    write!(f, " if {}", condition)?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl Display for Phi {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} = phi {} [", self.dest, self.variable)?;
+        let mut first = true;
+        for (block, value) in &self.sources {
+            if !first { write!(f, ", ")?; } first = false;
+            write!(f, "b{}: {}", block, value)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Display for SsaOperation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use SsaOperation::*;
+        match self {
+            Assign { dest, expr } => write!(f, "{} = {}", dest, expr),
+            Load { dest, addr } => write!(f, "{} = load [{}]", dest, addr),
+            Store { addr, value } => write!(f, "store [{}] = {}", addr, value),
+            Syscall { args, result } => {
+                write!(f, "{} = syscall(", result)?;
+                let mut first = true;
+                for arg in args {
+                    if !first { write!(f, ", ")?; } first = false;
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            },
+            Call { target, .. } => write!(f, "call {}", target),
+            Jump { target, condition } => {
+                write!(f, "jump to {}", target)?;
+                if condition != &SymCondition::TRUE {
+                    write!(f, " if {}", condition)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Display for Variable {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Variable::Register(reg) => write!(f, "{}", reg),
+            Variable::Temp(temp) => write!(f, "{}", temp),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "v{}:{}", self.1, self.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use crate::Program;
+    use super::*;
+
+    fn test(filename: &str) -> SsaFunction {
+        let path = format!("target/bin/{}", filename);
+        let program = Program::new(path);
+        let graph = ControlFlowGraph::new(&program);
+
+        let main = program.symbols.iter()
+            .find(|(_, name)| name.as_str() == "main")
+            .map(|(&addr, _)| addr)
+            .unwrap();
+        let entry = graph.nodes.iter().position(|node| node.addr == main).unwrap();
+
+        let function = SsaFunction::from_function(&graph, entry);
+
+        fs::create_dir("target/out").ok();
+        fs::create_dir("target/out/ssa").ok();
+        let ssa_path = format!("target/out/ssa/{}.txt", filename);
+        let mut ssa_file = File::create(ssa_path).unwrap();
+        writeln!(ssa_file, "SSA form of main in {}\n", filename).unwrap();
+        write!(ssa_file, "{}", function).unwrap();
+
+        // Every value has to be defined exactly once.
+        let mut defined = HashSet::new();
+        for &(_, value) in &function.inputs {
+            assert!(defined.insert(value));
+        }
+        for block in &function.blocks {
+            for phi in &block.phis {
+                assert!(defined.insert(phi.dest));
+            }
+            for op in &block.operations {
+                for value in op.defines() {
+                    assert!(defined.insert(value));
+                }
+            }
+        }
+
+        // And every used value has to be defined.
+        for block in &function.blocks {
+            for phi in &block.phis {
+                assert_eq!(phi.sources.len(), block.predecessors.len());
+                for (_, value) in &phi.sources {
+                    assert!(defined.contains(value));
+                }
+            }
+            for op in &block.operations {
+                for value in op.uses() {
+                    assert!(defined.contains(&value), "{} is not defined", value);
+                }
+            }
+            for (_, condition) in &block.successors {
+                condition.traverse(&mut |node| {
+                    if let Traversed::Expr(&SymExpr::Sym(symbol)) = node {
+                        let Symbol(data_type, name, index) = symbol;
+                        assert_eq!(name, "v", "{} is not a value", symbol);
+                        assert!(defined.contains(&Value(data_type, index)));
+                    }
+                });
+            }
+        }
+
+        function
+    }
+
+    #[test]
+    fn ssa_functions() {
+        test("case");
+        test("func");
+        test("bufs");
+        test("paths");
+        test("deep");
+        test("overwrite");
+    }
+
+    #[test]
+    fn ssa_loop() {
+        let function = test("loop");
+
+        // The loop in main jumps back to the return site of the first call, where
+        // the stack pointers after both calls are merged by a phi node.
+        let header = function.blocks.iter()
+            .find(|block| block.predecessors.len() == 2)
+            .unwrap();
+
+        let phi = header.phis.iter()
+            .find(|phi| phi.variable == Variable::Register(Register::RSP))
+            .unwrap();
+
+        for &(pred, value) in &phi.sources {
+            match function.definition(value) {
+                Some(Definition::Operation(block, _)) => assert_eq!(block, pred),
+                def => panic!("unexpected definition of {}: {:?}", value, def),
+            }
+        }
+    }
+}
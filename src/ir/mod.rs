@@ -1,12 +1,18 @@
 //! Microcode encoding of instructions.
 
+use std::collections::HashSet;
 use std::fmt::{self, Display, Formatter};
 
-use crate::math::{SymExpr, SymCondition, Integer, DataType, Symbol};
+use crate::math::{SymExpr, SymCondition, Integer, DataType, Symbol, Traversed};
 use crate::x86_64::{Instruction, Mnemoic, Operand, Register};
 use Register::*;
 use SymCondition::*;
 
+mod opt;
+mod verify;
+pub use opt::*;
+pub use verify::*;
+
 
 /// A sequence of micro operations.
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -106,6 +112,7 @@ pub struct MicroEncoder {
     ops: Vec<MicroOperation>,
     temps: usize,
     last_flag_op: Option<SymExpr>,
+    optimizer: Option<MicroOptimizer>,
 }
 
 type EncoderResult<T> = Result<T, String>;
@@ -127,7 +134,16 @@ impl MicroEncoder {
         MicroEncoder {
             ops: vec![],
             temps: 0,
-            last_flag_op: None
+            last_flag_op: None,
+            optimizer: None,
+        }
+    }
+
+    /// Create a new encoder that runs the optimizer over the encoded microcode.
+    pub fn with_optimizer(optimizer: MicroOptimizer) -> MicroEncoder {
+        MicroEncoder {
+            optimizer: Some(optimizer),
+            .. MicroEncoder::new()
         }
     }
 
@@ -213,7 +229,8 @@ impl MicroEncoder {
             Jmp => self.encode_jump(inst, SymCondition::TRUE),
             Je  => self.encode_jump(inst, condition!(self;
                 SymExpr::Sub(a, b) => Equal(a, b),
-                SymExpr::BitAnd(a, b) => SymExpr::from_int(a.data_type(), 0).equal(a.bitand(*b))
+                SymExpr::BitAnd(a, b) => SymExpr::from_int(a.data_type(), 0)
+                    .equal(a.into_inner().bitand(b.into_inner()))
             )?),
             Jbe => self.encode_jump(inst, condition!(self; SymExpr::Sub(a, b) => LessEqual(a, b, false))?),
             Jl  => self.encode_jump(inst, condition!(self; SymExpr::Sub(a, b) => LessThan(a, b, true))?),
@@ -252,7 +269,27 @@ impl MicroEncoder {
 
         let mut ops = Vec::new();
         std::mem::swap(&mut ops, &mut self.ops);
-        Ok(Microcode { ops })
+        let mut microcode = Microcode { ops };
+
+        if let Some(optimizer) = &self.optimizer {
+            optimizer.optimize(&mut microcode, &self.live_temporaries());
+        }
+
+        Ok(microcode)
+    }
+
+    /// The indices of the temporaries that may be read by later instructions
+    /// (through the conditions derived from the last flag-modifying operation).
+    fn live_temporaries(&self) -> HashSet<usize> {
+        let mut live = HashSet::new();
+        if let Some(op) = &self.last_flag_op {
+            op.traverse(&mut |node| {
+                if let Traversed::Expr(&SymExpr::Sym(Symbol(_, "T", index))) = node {
+                    live.insert(index);
+                }
+            });
+        }
+        live
     }
 
     /// Encode a binary operation like an add or a subtract.
@@ -570,7 +607,7 @@ mod tests {
         assert_eq!(code.to_string(), display);
     }
 
-    fn codify(code: &str) -> String {
+    pub fn codify(code: &str) -> String {
         let mut output = "Microcode [\n".to_string();
         for line in code.lines() {
             if !line.chars().all(|c| c.is_whitespace()) {
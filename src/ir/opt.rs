@@ -0,0 +1,487 @@
+//! Optimization passes over microcode.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::math::{SymExpr, SymCondition, Symbol, Integer, Traversed};
+use super::{Microcode, MicroOperation, Location, Temporary};
+use MicroOperation as Op;
+
+
+/// Runs a pipeline of optimization passes over microcode.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MicroOptimizer {
+    passes: Vec<Pass>,
+}
+
+/// A single optimization pass.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Pass {
+    /// Evaluate operations on constant temporaries and remove operations that
+    /// are neutral (like multiplying by one).
+    ConstantFolding,
+    /// Replace copies of temporaries by the original ones and fuse loads into
+    /// temporaries that are directly stored somewhere else.
+    CopyPropagation,
+    /// Remove operations writing temporaries which are never read afterwards.
+    DeadTemporaries,
+    /// Remove casts that do not change the value.
+    RedundantCasts,
+}
+
+/// How often the whole pipeline is run at most until nothing changes anymore.
+const MAX_ROUNDS: usize = 8;
+
+impl MicroOptimizer {
+    /// Create an optimizer running all passes.
+    pub fn new() -> MicroOptimizer {
+        MicroOptimizer::with_passes(vec![
+            Pass::ConstantFolding,
+            Pass::RedundantCasts,
+            Pass::CopyPropagation,
+            Pass::DeadTemporaries,
+        ])
+    }
+
+    /// Create an optimizer running the given passes in order.
+    pub fn with_passes(passes: Vec<Pass>) -> MicroOptimizer {
+        MicroOptimizer { passes }
+    }
+
+    /// Optimize the microcode in place.
+    ///
+    /// The temporaries with the indices in `live` are read after this microcode
+    /// was executed (for example by the conditions of later jumps) and are
+    /// therefore preserved.
+    pub fn optimize(&self, microcode: &mut Microcode, live: &HashSet<usize>) {
+        let ops = &mut microcode.ops;
+
+        for _ in 0 .. MAX_ROUNDS {
+            let mut changed = false;
+            for pass in &self.passes {
+                changed |= match pass {
+                    Pass::ConstantFolding => fold_constants(ops),
+                    Pass::CopyPropagation => propagate_copies(ops, live),
+                    Pass::DeadTemporaries => eliminate_dead(ops, live),
+                    Pass::RedundantCasts => remove_redundant_casts(ops),
+                };
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+/// Replace operations on constants by their results.
+fn fold_constants(ops: &mut Vec<MicroOperation>) -> bool {
+    let mut constants: HashMap<usize, Integer> = HashMap::new();
+    let mut changed = false;
+    let mut index = 0;
+
+    while index < ops.len() {
+        let get = |temp: &Temporary| constants.get(&temp.1).copied();
+        let binop = |target, a: &Temporary, b: &Temporary, op, neutral, commutative| {
+            fold_binop(target, (*a, get(a)), (*b, get(b)), op, neutral, commutative)
+        };
+
+        let folded = match &ops[index] {
+            Op::Mov { dest: Location::Temp(dest), src: Location::Temp(src) } => {
+                get(src).map(|constant| Some(Op::Const { dest: *dest, constant }))
+            },
+            Op::Cast { target, new, signed } => get(target).map(|constant| Some(Op::Const {
+                dest: Temporary(*new, target.1),
+                constant: constant.cast(*new, *signed),
+            })),
+
+            Op::Add { sum, a, b } => binop(*sum, a, b, Integer::add, 0, true),
+            Op::Sub { diff, a, b } => binop(*diff, a, b, Integer::sub, 0, false),
+            Op::Mul { prod, a, b } => binop(*prod, a, b, Integer::mul, 1, true),
+            Op::And { and, a, b } => binop(*and, a, b, Integer::bitand, !0, true),
+            Op::Or { or, a, b } => binop(*or, a, b, Integer::bitor, 0, true),
+            Op::Not { not, a } => get(a).map(|x| Some(Op::Const { dest: *not, constant: x.bitnot() })),
+
+            Op::Set { target, condition } => {
+                let mut constant = true;
+                for_each_temp(condition, |temp| constant &= constants.contains_key(&temp.1));
+                if constant {
+                    let value = condition.evaluate(&|sym| match sym {
+                        Symbol(_, "T", index) => constants.get(&index).copied(),
+                        _ => None,
+                    });
+                    let constant = Integer::from_bool(value, target.0);
+                    Some(Some(Op::Const { dest: *target, constant }))
+                } else {
+                    None
+                }
+            },
+
+            _ => None,
+        };
+
+        // `Some(None)` means the operation has no effect at all and `Some(Some(op))`
+        // that it is to be replaced by `op`.
+        match folded {
+            Some(Some(op)) => { ops[index] = op; changed = true; },
+            Some(None) => { ops.remove(index); changed = true; continue; },
+            None => {},
+        }
+
+        let op = &ops[index];
+        if let Some(written) = writes(op) {
+            match op {
+                Op::Const { constant, .. } => { constants.insert(written, *constant); },
+                _ => { constants.remove(&written); },
+            }
+        }
+
+        index += 1;
+    }
+
+    changed
+}
+
+/// Fold a binary operation given its operands and their values if they are
+/// constant. If one operand is the `neutral` element, the operation degenerates
+/// to a move (or to nothing if it works in place). The left operand is only
+/// considered for commutative operations.
+fn fold_binop(
+    target: Temporary,
+    (a, ca): (Temporary, Option<Integer>),
+    (b, cb): (Temporary, Option<Integer>),
+    op: fn(Integer, Integer) -> Integer,
+    neutral: u64,
+    commutative: bool,
+) -> Option<Option<MicroOperation>> {
+    let is_neutral = |x: Integer| x == Integer(x.0, neutral);
+    let forward = |src: Temporary| if target == src {
+        None
+    } else {
+        Some(Op::Mov { dest: Location::Temp(target), src: Location::Temp(src) })
+    };
+
+    match (ca, cb) {
+        (Some(x), Some(y)) => Some(Some(Op::Const { dest: target, constant: op(x, y) })),
+        (_, Some(y)) if is_neutral(y) => Some(forward(a)),
+        (Some(x), _) if commutative && is_neutral(x) => Some(forward(b)),
+        _ => None,
+    }
+}
+
+/// Replace reads of temporaries that are plain copies of other temporaries
+/// by reads of the original ones and fuse loads directly followed by a store.
+fn propagate_copies(ops: &mut Vec<MicroOperation>, live: &HashSet<usize>) -> bool {
+    let mut copies: HashMap<usize, Temporary> = HashMap::new();
+    let mut changed = false;
+
+    for op in ops.iter_mut() {
+        if !copies.is_empty() {
+            let before = op.clone();
+            rename_reads(op, &|temp| copies.get(&temp.1).copied().unwrap_or(temp));
+            changed |= *op != before;
+        }
+
+        if let Some(written) = writes(op) {
+            copies.remove(&written);
+            copies.retain(|_, original| original.1 != written);
+        }
+
+        if let Op::Mov { dest: Location::Temp(dest), src: Location::Temp(src) } = *op {
+            if dest.1 != src.1 {
+                copies.insert(dest.1, src);
+            }
+        }
+    }
+
+    // Fuse a load into a temporary that is directly stored in another location.
+    let mut index = 0;
+    while index + 1 < ops.len() {
+        let fused = match (&ops[index], &ops[index + 1]) {
+            (
+                Op::Mov { dest: Location::Temp(temp), src },
+                Op::Mov { dest, src: Location::Temp(stored) }
+            ) if temp.1 == stored.1
+                && !reads_location(*dest, temp.1)
+                && is_dead_after(ops, index + 2, temp.1, live) => {
+                Some(Op::Mov { dest: *dest, src: *src })
+            },
+            _ => None,
+        };
+
+        if let Some(op) = fused {
+            ops[index] = op;
+            ops.remove(index + 1);
+            changed = true;
+        }
+
+        index += 1;
+    }
+
+    changed
+}
+
+/// Remove operations without side effects whose results are never read.
+fn eliminate_dead(ops: &mut Vec<MicroOperation>, live: &HashSet<usize>) -> bool {
+    let mut live = live.clone();
+    let mut changed = false;
+
+    for index in (0 .. ops.len()).rev() {
+        let op = &ops[index];
+        let written = writes(op);
+
+        if let Some(written) = written {
+            if !live.contains(&written) && is_pure(op) {
+                ops.remove(index);
+                changed = true;
+                continue;
+            }
+            live.remove(&written);
+        }
+
+        live.extend(reads(op));
+    }
+
+    changed
+}
+
+/// Remove casts to the same type and extensions that are truncated again
+/// before the temporary is used.
+fn remove_redundant_casts(ops: &mut Vec<MicroOperation>) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+
+    while index < ops.len() {
+        if let Op::Cast { target, new, .. } = ops[index] {
+            if target.0 == new {
+                ops.remove(index);
+                changed = true;
+                continue;
+            }
+
+            // Look for the next operation touching the temporary.
+            if new > target.0 {
+                let next = (index + 1 .. ops.len()).find(|&i| touches(&ops[i], target.1));
+                if let Some(next) = next {
+                    if let Op::Cast { target: second, new: back, .. } = ops[next] {
+                        if second.0 == new && back == target.0 {
+                            ops.remove(next);
+                            ops.remove(index);
+                            changed = true;
+                            continue;
+                        }
+                    }
+                }
+            }
+        }
+
+        index += 1;
+    }
+
+    changed
+}
+
+/// Whether the temporary with the index is overwritten before it is read
+/// again or never read again when starting at operation `from`.
+fn is_dead_after(ops: &[MicroOperation], from: usize, temp: usize, live: &HashSet<usize>) -> bool {
+    for op in &ops[from ..] {
+        if reads(op).contains(&temp) {
+            return false;
+        }
+        if writes(op) == Some(temp) {
+            return true;
+        }
+    }
+    !live.contains(&temp)
+}
+
+/// The index of the temporary written by the operation.
+fn writes(op: &MicroOperation) -> Option<usize> {
+    match op {
+        Op::Mov { dest: Location::Temp(temp), .. } => Some(temp.1),
+        Op::Mov { .. } => None,
+        Op::Const { dest, .. } => Some(dest.1),
+        Op::Cast { target, .. } => Some(target.1),
+        Op::Add { sum, .. } => Some(sum.1),
+        Op::Sub { diff, .. } => Some(diff.1),
+        Op::Mul { prod, .. } => Some(prod.1),
+        Op::And { and, .. } => Some(and.1),
+        Op::Or { or, .. } => Some(or.1),
+        Op::Not { not, .. } => Some(not.1),
+        Op::Set { target, .. } => Some(target.1),
+        Op::Jump { .. } | Op::Syscall => None,
+    }
+}
+
+/// The indices of all temporaries read by the operation.
+fn reads(op: &MicroOperation) -> Vec<usize> {
+    let mut temps = Vec::new();
+    let mut location = |loc: &Location| match *loc {
+        Location::Temp(temp) | Location::Indirect(_, _, temp) => temps.push(temp.1),
+        Location::Direct(..) => {},
+    };
+
+    match op {
+        Op::Mov { dest, src } => {
+            if let Location::Indirect(..) = dest { location(dest); }
+            location(src);
+        },
+        Op::Const { .. } | Op::Syscall => {},
+        Op::Cast { target, .. } => temps.push(target.1),
+        Op::Add { a, b, .. } | Op::Sub { a, b, .. } | Op::Mul { a, b, .. }
+        | Op::And { a, b, .. } | Op::Or { a, b, .. } => {
+            temps.push(a.1);
+            temps.push(b.1);
+        },
+        Op::Not { a, .. } => temps.push(a.1),
+        Op::Set { condition, .. } => for_each_temp(condition, |temp| temps.push(temp.1)),
+        Op::Jump { target, condition, .. } => {
+            temps.push(target.1);
+            for_each_temp(condition, |temp| temps.push(temp.1));
+        },
+    }
+
+    temps
+}
+
+/// Whether the operation reads or writes the temporary with the index.
+fn touches(op: &MicroOperation, temp: usize) -> bool {
+    writes(op) == Some(temp) || reads(op).contains(&temp)
+}
+
+/// Whether the location uses the temporary with the index as an address.
+fn reads_location(location: Location, temp: usize) -> bool {
+    match location {
+        Location::Indirect(_, _, t) => t.1 == temp,
+        _ => false,
+    }
+}
+
+/// Whether the operation has no other effect than writing its temporary.
+fn is_pure(op: &MicroOperation) -> bool {
+    match op {
+        Op::Mov { dest: Location::Temp(_), .. } => true,
+        Op::Mov { .. } | Op::Jump { .. } | Op::Syscall => false,
+        _ => true,
+    }
+}
+
+/// Replace all temporaries that are read by the operation.
+fn rename_reads<F>(op: &mut MicroOperation, rename: &F) where F: Fn(Temporary) -> Temporary {
+    let location = |loc: &mut Location| match loc {
+        Location::Temp(temp) | Location::Indirect(_, _, temp) => *temp = rename(*temp),
+        Location::Direct(..) => {},
+    };
+    let condition = |cond: &mut SymCondition| cond.replace_symbols(&|sym| match sym {
+        Symbol(data_type, "T", index) => rename(Temporary(data_type, index)).to_expr(),
+        sym => SymExpr::Sym(sym),
+    });
+
+    match op {
+        Op::Mov { dest, src } => {
+            if let Location::Indirect(..) = dest { location(dest); }
+            location(src);
+        },
+        Op::Const { .. } | Op::Cast { .. } | Op::Syscall => {},
+        Op::Add { a, b, .. } | Op::Sub { a, b, .. } | Op::Mul { a, b, .. }
+        | Op::And { a, b, .. } | Op::Or { a, b, .. } => {
+            *a = rename(*a);
+            *b = rename(*b);
+        },
+        Op::Not { a, .. } => *a = rename(*a),
+        Op::Set { condition: cond, .. } => condition(cond),
+        Op::Jump { target, condition: cond, .. } => {
+            *target = rename(*target);
+            condition(cond);
+        },
+    }
+}
+
+/// Call a function for every temporary appearing in the condition.
+fn for_each_temp<F>(condition: &SymCondition, mut f: F) where F: FnMut(Temporary) {
+    condition.traverse(&mut |node| {
+        if let Traversed::Expr(&SymExpr::Sym(Symbol(data_type, "T", index))) = node {
+            f(Temporary(data_type, index));
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::x86_64::*;
+    use crate::math::DataType::*;
+    use super::super::MicroEncoder;
+    use super::super::tests::codify;
+    use super::*;
+
+    /// Check the optimized microcode of the instruction, which has to be
+    /// shorter than the one the plain encoder produces.
+    fn test(bytes: &[u8], display: &str) {
+        let instruction = Instruction::decode(bytes).unwrap();
+        let plain = MicroEncoder::new().encode(&instruction).unwrap();
+
+        let mut encoder = MicroEncoder::with_optimizer(MicroOptimizer::new());
+        let code = encoder.encode(&instruction).unwrap();
+        assert_eq!(code.to_string(), codify(display));
+        assert!(code.ops.len() < plain.ops.len(), "not optimized: {}", plain);
+    }
+
+    #[test]
+    fn constant_folding() {
+        // Instruction: mov rax, 0x3c
+        // The immediate is extended at compile time.
+        test(&[0x48, 0xc7, 0xc0, 0x3c, 0x00, 0x00, 0x00], "
+            const T0:n64 = 0x3c:n64
+            mov [m1][0x0:n64] = T0:n64
+        ");
+
+        // Instruction: lea rbx, qword ptr [rdx+rax*1]
+        // The scaling by one and its constant are removed.
+        test(&[0x48, 0x8d, 0x1c, 0x02], "
+            mov T0:n64 = [m1][0x10:n64]
+            mov T1:n64 = [m1][0x0:n64]
+            add T0:n64 = T0:n64 + T1:n64
+            mov [m1][0x18:n64] = T0:n64
+        ");
+    }
+
+    #[test]
+    fn live_flag_operands() {
+        // Instruction: sub rsp, 0x10
+        // Both operands are kept because later jumps depend on them.
+        test(&[0x48, 0x83, 0xec, 0x10], "
+            mov T0:n64 = [m1][0x20:n64]
+            const T1:n64 = 0x10:n64
+            sub T2:n64 = T0:n64 - T1:n64
+            mov [m1][0x20:n64] = T2:n64
+        ");
+
+        // Instruction: add rax, 0x0
+        // The addition of zero is removed, but not the zero itself.
+        test(&[0x48, 0x83, 0xc0, 0x00], "
+            mov T0:n64 = [m1][0x0:n64]
+            const T1:n64 = 0x0:n64
+            mov [m1][0x0:n64] = T0:n64
+        ");
+    }
+
+    #[test]
+    fn dead_temporaries() {
+        let mut code = Microcode { ops: vec![
+            Op::Const { dest: Temporary(N8, 0), constant: Integer(N8, 5) },
+            Op::Cast { target: Temporary(N8, 0), new: N32, signed: false },
+            Op::Cast { target: Temporary(N32, 0), new: N8, signed: false },
+            Op::Mov {
+                dest: Location::Temp(Temporary(N8, 1)),
+                src: Location::Direct(N8, 1, 0x0),
+            },
+            Op::Mov {
+                dest: Location::Direct(N8, 1, 0x8),
+                src: Location::Temp(Temporary(N8, 1)),
+            },
+        ]};
+
+        MicroOptimizer::new().optimize(&mut code, &HashSet::new());
+        assert_eq!(code.to_string(), codify("mov [m1][0x8:n8] = [m1][0x0:n8]"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Type checking of microcode.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::math::{SymExpr, SymCondition, DataType, Symbol, Traversed};
+use crate::x86_64::Instruction;
+use super::{Microcode, MicroOperation, Location, Temporary};
+use MicroOperation as Op;
+
+
+/// Checks that microcode is well-typed before it is executed.
+///
+/// Temporaries keep their value across instructions (the conditions of jumps
+/// read temporaries of earlier instructions), so a verifier should be fed the
+/// microcode of all instructions in the order they were encoded.
+#[derive(Debug, Clone)]
+pub struct MicroVerifier {
+    temps: HashMap<usize, DataType>,
+}
+
+type VerifierResult<T> = Result<T, String>;
+
+impl MicroVerifier {
+    /// Create a new verifier where no temporaries are defined yet.
+    pub fn new() -> MicroVerifier {
+        MicroVerifier { temps: HashMap::new() }
+    }
+
+    /// Verify the microcode of an instruction.
+    pub fn verify(&mut self, inst: &Instruction, microcode: &Microcode) -> VerifyResult<()> {
+        for op in &microcode.ops {
+            self.verify_operation(op)
+                .map_err(|msg| VerificationError::new(inst.clone(), format!("{} in `{}`", msg, op)))?;
+        }
+        Ok(())
+    }
+
+    /// Verify a single operation and define the temporaries it writes.
+    fn verify_operation(&mut self, op: &MicroOperation) -> VerifierResult<()> {
+        match op {
+            Op::Mov { dest, src } => {
+                self.use_location(*src)?;
+                if let Location::Indirect(_, _, temp) = dest {
+                    self.use_temp(*temp)?;
+                }
+                if dest.data_type() != src.data_type() {
+                    return Err(format!("move between different widths: {} and {}",
+                        dest.data_type(), src.data_type()));
+                }
+                if let Location::Temp(temp) = dest {
+                    self.define(*temp);
+                }
+            },
+
+            Op::Const { dest, constant } => {
+                if dest.0 != constant.0 {
+                    return Err(format!("constant of type {} stored in {}", constant.0, dest));
+                }
+                self.define(*dest);
+            },
+
+            Op::Cast { target, new, .. } => {
+                self.use_temp(*target)?;
+                if target.0 == *new {
+                    return Err(format!("cast of {} to its own type", target));
+                }
+                self.define(Temporary(*new, target.1));
+            },
+
+            Op::Add { sum: target, a, b }
+            | Op::Sub { diff: target, a, b }
+            | Op::Mul { prod: target, a, b }
+            | Op::And { and: target, a, b }
+            | Op::Or { or: target, a, b } => {
+                self.use_temp(*a)?;
+                self.use_temp(*b)?;
+                if a.0 != b.0 || a.0 != target.0 {
+                    return Err(format!("operation on different types: {}, {} and {}",
+                        target, a, b));
+                }
+                self.define(*target);
+            },
+
+            Op::Not { not, a } => {
+                self.use_temp(*a)?;
+                if not.0 != a.0 {
+                    return Err(format!("operation on different types: {} and {}", not, a));
+                }
+                self.define(*not);
+            },
+
+            Op::Set { target, condition } => {
+                self.use_condition(condition)?;
+                self.define(*target);
+            },
+
+            Op::Jump { target, condition, .. } => {
+                self.use_temp(*target)?;
+                self.use_condition(condition)?;
+                if target.0 != DataType::N64 {
+                    return Err(format!("jump target {} is not 64-bit", target));
+                }
+            },
+
+            Op::Syscall => {},
+        }
+
+        Ok(())
+    }
+
+    /// Check that the location can be read.
+    fn use_location(&self, location: Location) -> VerifierResult<()> {
+        match location {
+            Location::Temp(temp) => self.use_temp(temp),
+            Location::Direct(..) => Ok(()),
+            Location::Indirect(_, _, temp) => {
+                self.use_temp(temp)?;
+                if temp.0 != DataType::N64 {
+                    return Err(format!("address {} is not 64-bit", temp));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Check that the temporary is defined with the same type.
+    fn use_temp(&self, temp: Temporary) -> VerifierResult<()> {
+        match self.temps.get(&temp.1) {
+            Some(&data_type) if data_type == temp.0 => Ok(()),
+            Some(&data_type) => Err(format!("{} is used but has type {}", temp, data_type)),
+            None => Err(format!("{} is used before it is defined", temp)),
+        }
+    }
+
+    /// Check that all temporaries in the condition are defined with the types
+    /// they are used with.
+    fn use_condition(&self, condition: &SymCondition) -> VerifierResult<()> {
+        let mut result = Ok(());
+        condition.traverse(&mut |node| {
+            if let Traversed::Expr(&SymExpr::Sym(Symbol(data_type, "T", index))) = node {
+                if result.is_ok() {
+                    result = self.use_temp(Temporary(data_type, index));
+                }
+            }
+        });
+        result
+    }
+
+    /// Define the temporary with its type.
+    fn define(&mut self, temp: Temporary) {
+        self.temps.insert(temp.1, temp.0);
+    }
+}
+
+
+/// The error type for microcode verification.
+#[derive(Eq, PartialEq)]
+pub struct VerificationError(Instruction, String);
+pub(in super) type VerifyResult<T> = Result<T, VerificationError>;
+
+impl VerificationError {
+    /// Create a new verification error with a message.
+    fn new<S: Into<String>>(inst: Instruction, message: S) -> VerificationError {
+        VerificationError(inst, message.into())
+    }
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Invalid microcode: {} [{}]", self.1, self.0)
+    }
+}
+
+impl std::error::Error for VerificationError {}
+debug_display!(VerificationError);
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Integer;
+    use crate::x86_64::*;
+    use super::super::{MicroEncoder, MicroOptimizer};
+    use super::*;
+    use DataType::*;
+
+    fn verify(ops: Vec<MicroOperation>) -> VerifierResult<()> {
+        let mut verifier = MicroVerifier::new();
+        for op in &ops {
+            verifier.verify_operation(op)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn verify_encoded() {
+        let code = [
+            &[0x55][..], &[0x48, 0x89, 0xe5], &[0x48, 0x0f, 0xbe, 0x45, 0xf7],
+            &[0x80, 0x7d, 0xf7, 0x60], &[0x7e, 0x0a], &[0x0f, 0x9c, 0xc0],
+            &[0xc9], &[0xc3],
+        ];
+
+        for encoder in &mut [MicroEncoder::new(),
+                             MicroEncoder::with_optimizer(MicroOptimizer::new())] {
+            let mut verifier = MicroVerifier::new();
+            for bytes in &code {
+                let instruction = Instruction::decode(bytes).unwrap();
+                let microcode = encoder.encode(&instruction).unwrap();
+                verifier.verify(&instruction, &microcode).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn verify_invalid() {
+        let t0 = Temporary(N64, 0);
+        let t1 = Temporary(N32, 1);
+
+        assert_eq!(verify(vec![Op::Not { not: t0, a: t0 }]),
+            Err("T0:n64 is used before it is defined".to_string()));
+
+        assert_eq!(verify(vec![
+            Op::Const { dest: t1, constant: Integer(N32, 5) },
+            Op::Mov { dest: Location::Direct(N64, 1, 0), src: Location::Temp(t1) },
+        ]), Err("move between different widths: n64 and n32".to_string()));
+
+        assert_eq!(verify(vec![
+            Op::Const { dest: t1, constant: Integer(N32, 5) },
+            Op::Cast { target: t1, new: N64, signed: false },
+            Op::Not { not: t1, a: t1 },
+        ]), Err("T1:n32 is used but has type n64".to_string()));
+
+        assert_eq!(verify(vec![
+            Op::Const { dest: t1, constant: Integer(N32, 5) },
+            Op::Cast { target: t1, new: N32, signed: true },
+        ]), Err("cast of T1:n32 to its own type".to_string()));
+
+        assert_eq!(verify(vec![
+            Op::Const { dest: t1, constant: Integer(N32, 5) },
+            Op::Jump { target: t1, condition: SymCondition::TRUE, relative: true },
+        ]), Err("jump target T1:n32 is not 64-bit".to_string()));
+
+        assert_eq!(verify(vec![
+            Op::Set { target: t0, condition: t1.to_expr().equal(SymExpr::from_int(N32, 0)) },
+        ]), Err("T1:n32 is used before it is defined".to_string()));
+    }
+}
@@ -4,8 +4,8 @@ use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::path::Path;
 
-use crate::elf::ElfFile;
-use crate::ir::{Microcode, MicroEncoder};
+use crate::elf::{ElfFile, SHF_ALLOC, SHF_EXECINSTR, SHF_TLS, SHT_NOBITS, SHT_PROGBITS};
+use crate::ir::{Microcode, MicroEncoder, MicroOptimizer, MicroVerifier};
 use crate::x86_64::Instruction;
 
 
@@ -29,8 +29,6 @@ mod helper {
         if s { " signed" } else { "" }
     }
 
-    pub fn boxed<T>(value: T) -> Box<T> { Box::new(value) }
-
     /// Make sure operations only happen on same expressions.
     pub fn check_compatible(a: DataType, b: DataType, operation: &str) {
         assert_eq!(a, b, "incompatible data types for {}", operation);
@@ -50,6 +48,9 @@ mod helper {
 pub mod flow;
 pub mod math;
 pub mod sym;
+pub mod concrete;
+pub mod concolic;
+pub mod validate;
 pub mod elf;
 pub mod ir;
 pub mod x86_64;
@@ -59,6 +60,7 @@ pub mod timings;
 #[cfg(not(feature = "timings"))]
 mod timings {
     pub(crate) fn with<S: Into<String>, F, T>(_: S, f: F) -> T where F: FnOnce() -> T { f() }
+    pub(crate) fn count<S>(_: S) {}
     pub(crate) fn start<S>(_: S) {}
     pub(crate) fn stop() {}
 }
@@ -71,6 +73,9 @@ pub struct Program {
     pub binary: Vec<u8>,
     pub code: Vec<(u64, u64, Instruction, Microcode)>,
     pub symbols: HashMap<u64, String>,
+    /// The addresses and contents of the data sections that are loaded
+    /// into memory, like `.data`, `.rodata` and the zeroed `.bss`.
+    pub data: Vec<(u64, Vec<u8>)>,
 }
 
 impl Program {
@@ -86,7 +91,8 @@ impl Program {
 
         let mut index = 0;
         let mut code = Vec::new();
-        let mut encoder = MicroEncoder::new();
+        let mut encoder = MicroEncoder::with_optimizer(MicroOptimizer::new());
+        let mut verifier = MicroVerifier::new();
 
         // Decode the whole text section.
         while index < binary.len() as u64 {
@@ -94,6 +100,7 @@ impl Program {
             let bytes = &binary[index as usize .. (index + len) as usize];
             let instruction = Instruction::decode(bytes).unwrap();
             let microcode = encoder.encode(&instruction).unwrap();
+            verifier.verify(&instruction, &microcode).unwrap();
             code.push((base + index, len, instruction, microcode));
             index += len;
         }
@@ -108,6 +115,25 @@ impl Program {
             }
         }
 
+        // Load the data sections that occupy memory. The unwinding tables
+        // are left out since the programs never read them.
+        let mut data = Vec::new();
+        for header in file.section_headers.clone() {
+            let loaded = header.flags & SHF_ALLOC != 0
+                && header.flags & (SHF_EXECINSTR | SHF_TLS) == 0
+                && (header.section_type == SHT_PROGBITS || header.section_type == SHT_NOBITS);
+            if !loaded || header.addr == 0 || header.name.starts_with(".eh_frame") {
+                continue;
+            }
+
+            let bytes = if header.section_type == SHT_NOBITS {
+                vec![0; header.size as usize]
+            } else {
+                file.get_section(&header.name).unwrap().data
+            };
+            data.push((header.addr, bytes));
+        }
+
         crate::timings::stop();
 
         Program {
@@ -115,7 +141,8 @@ impl Program {
             entry: file.header.entry,
             binary,
             code,
-            symbols
+            symbols,
+            data,
         }
     }
 
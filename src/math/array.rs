@@ -0,0 +1,143 @@
+//! Symbolic byte arrays.
+
+use std::fmt::{self, Display, Formatter};
+use z3::{Context as Z3Context, Sort as Z3Sort};
+use z3::ast::{Array as Z3Array};
+
+use super::{SymExpr, Symbol, Integer, DataType, Traversed, Node, node, array_space};
+use SymArray::*;
+use SymExpr::*;
+
+
+/// A symbolic array mapping 64-bit addresses to bytes.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum SymArray {
+    /// An array with unknown contents, named after its symbol space.
+    Base(&'static str),
+    /// The inner array with the byte at the address replaced by the value.
+    Store(Node<SymArray>, Node<SymExpr>, Node<SymExpr>),
+}
+
+impl SymArray {
+    /// Convert this array into a Z3-solver Ast.
+    pub fn to_z3_ast<'ctx>(&self, ctx: &'ctx Z3Context) -> Z3Array<'ctx> {
+        match self {
+            Base(name) => Z3Array::new_const(ctx, *name,
+                &Z3Sort::bitvector(ctx, 64), &Z3Sort::bitvector(ctx, 8)),
+            Store(array, addr, value) => array.to_z3_ast(ctx)
+                .store(&addr.to_z3_ast(ctx), &value.to_z3_ast(ctx)),
+        }
+    }
+
+    /// Convert this array into an SMT-LIB2 array term.
+    pub fn to_smtlib(&self) -> String {
+        match self {
+            Base(name) => name.to_string(),
+            Store(array, addr, value) => format!("(store {} {} {})",
+                array.to_smtlib(), addr.to_smtlib(), value.to_smtlib()),
+        }
+    }
+
+    /// Replace the byte at the address.
+    ///
+    /// A store to the same address as the newest one replaces it.
+    pub fn store(self, addr: SymExpr, value: SymExpr) -> SymArray {
+        assert_eq!(addr.data_type(), DataType::N64, "store: address has to be 64-bit");
+        assert_eq!(value.data_type(), DataType::N8, "store: value has to be a byte");
+        match self {
+            Store(array, old, _) if *old == addr => Store(array, node(addr), node(value)),
+            array => Store(node(array), node(addr), node(value)),
+        }
+    }
+
+    /// Read the byte at the address.
+    ///
+    /// Stores at a known distance from the address are resolved right away,
+    /// the first one that possibly aliases stays part of the selection.
+    pub fn select(&self, addr: SymExpr) -> SymExpr {
+        assert_eq!(addr.data_type(), DataType::N64, "select: address has to be 64-bit");
+        let mut array = self;
+        while let Store(inner, stored, value) = array {
+            let distance = if **stored == addr {
+                Some(0)
+            } else {
+                match addr.clone().sub(SymExpr::clone(stored)).simplify() {
+                    Int(distance) => Some(distance.1),
+                    _ => None,
+                }
+            };
+
+            match distance {
+                Some(0) => return SymExpr::clone(value),
+                Some(_) => array = inner.as_ref(),
+                None => break,
+            }
+        }
+
+        Select(node(array.clone()), node(addr))
+    }
+
+    /// The name of the base array below all stores.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Base(name) => name,
+            Store(array, _, _) => array.name(),
+        }
+    }
+
+    /// Evaluate the byte at the address with the given values for the symbols.
+    ///
+    /// The unknown bytes of a base array are the symbols of its array space
+    /// indexed by their address.
+    pub fn evaluate<S>(&self, addr: Integer, symbols: &S) -> Integer
+    where S: Fn(Symbol) -> Option<Integer> {
+        match self {
+            Base(name) => {
+                let symbol = Symbol(DataType::N8, array_space(name), addr.1 as usize);
+                symbols(symbol).unwrap_or_else(|| {
+                    panic!("evaluate: missing symbol: {}", symbol);
+                })
+            },
+            Store(array, stored, value) => if stored.evaluate(symbols) == addr {
+                value.evaluate(symbols)
+            } else {
+                array.evaluate(addr, symbols)
+            },
+        }
+    }
+
+    /// Call a function for every node in the expression/condition tree.
+    pub fn traverse<F>(&self, f: &mut F) where F: FnMut(Traversed) {
+        f(Traversed::Array(self));
+        if let Store(array, addr, value) = self {
+            array.traverse(f);
+            addr.traverse(f);
+            value.traverse(f);
+        }
+    }
+
+    /// Replace the symbols with new expressions.
+    pub fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
+        if let Store(array, addr, value) = self {
+            array.replace_symbols(symbols);
+            addr.replace_symbols(symbols);
+            value.replace_symbols(symbols);
+        }
+    }
+}
+
+impl Node<SymArray> {
+    /// Replace the symbols in the shared array.
+    pub fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
+        self.update(|array| array.replace_symbols(symbols));
+    }
+}
+
+impl Display for SymArray {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Base(name) => write!(f, "{}", name),
+            Store(array, addr, value) => write!(f, "{}[{} := {}]", array, addr, value),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! Solver backends besides the in-process Z3 library.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::{SymExpr, SymCondition, SymArray, Symbol, Integer, Traversed};
+use super::{SolverBackend, PathBackend, SatResult, Z3Backend};
+use super::smt::Z3Parser;
+
+
+/// Which solver backend to use.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SolverConfig {
+    /// The Z3 library linked into this crate.
+    Z3,
+    /// An external solver binary reading SMT-LIB2 from stdin.
+    External { program: String, args: Vec<String> },
+    /// The pure-Rust fallback for ground conditions.
+    Native,
+}
+
+impl SolverConfig {
+    /// Read the configuration from the `SYMFLOW_SOLVER` environment variable.
+    ///
+    /// Valid values are `z3`, `native` and `external:<command>`, for example
+    /// `external:cvc5 --lang smt2`. Defaults to Z3 if the variable is not set.
+    pub fn from_env() -> SolverConfig {
+        match std::env::var("SYMFLOW_SOLVER") {
+            Ok(value) => value.parse().unwrap_or_else(|err| panic!("{}", err)),
+            Err(_) => SolverConfig::Z3,
+        }
+    }
+
+    /// Create the backend described by this configuration.
+    pub fn backend(&self) -> Box<dyn SolverBackend> {
+        match self {
+            SolverConfig::Z3 => Box::new(Z3Backend::new()),
+            SolverConfig::External { program, args } => {
+                Box::new(ExternalBackend::new(program.clone(), args.clone()))
+            },
+            SolverConfig::Native => Box::new(NativeBackend::new()),
+        }
+    }
+}
+
+impl FromStr for SolverConfig {
+    type Err = SolverConfigError;
+
+    fn from_str(s: &str) -> Result<SolverConfig, SolverConfigError> {
+        match s.trim() {
+            "z3" => Ok(SolverConfig::Z3),
+            "native" => Ok(SolverConfig::Native),
+            s if s.starts_with("external:") => {
+                let mut parts = s["external:".len() ..].split_whitespace();
+                let program = parts.next().ok_or_else(|| SolverConfigError(s.to_string()))?;
+                Ok(SolverConfig::External {
+                    program: program.to_string(),
+                    args: parts.map(|arg| arg.to_string()).collect(),
+                })
+            },
+            s => Err(SolverConfigError(s.to_string())),
+        }
+    }
+}
+
+/// The error type for invalid solver configurations.
+pub struct SolverConfigError(String);
+
+impl Display for SolverConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Invalid solver configuration: {:?} (expected `z3`, `native` \
+                   or `external:<command>`)", self.0)
+    }
+}
+
+impl std::error::Error for SolverConfigError {}
+debug_display!(SolverConfigError);
+
+
+/// Memoizes the results of another backend.
+///
+/// Conditions and expressions are keyed structurally, so the same query built
+/// on different paths is only solved once. Hits and misses are counted
+/// through the timings.
+pub struct CachedBackend {
+    backend: Box<dyn SolverBackend>,
+    exprs: RefCell<HashMap<SymExpr, SymExpr>>,
+    conditions: RefCell<HashMap<SymCondition, SymCondition>>,
+    sat: RefCell<HashMap<SymCondition, SatResult>>,
+    models: RefCell<HashMap<SymCondition, Option<HashMap<Symbol, Integer>>>>,
+}
+
+impl CachedBackend {
+    /// Create a new caching layer around a backend.
+    pub fn new(backend: Box<dyn SolverBackend>) -> CachedBackend {
+        CachedBackend {
+            backend,
+            exprs: RefCell::new(HashMap::new()),
+            conditions: RefCell::new(HashMap::new()),
+            sat: RefCell::new(HashMap::new()),
+            models: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// Look up the value in the cache or compute and insert it, counting the
+/// hit or miss under the given names.
+fn cached<K, V, F>(
+    cache: &RefCell<HashMap<K, V>>,
+    key: &K,
+    (hit, miss): (&'static str, &'static str),
+    f: F,
+) -> V
+where K: Clone + Eq + Hash, V: Clone, F: FnOnce() -> V {
+    if let Some(value) = cache.borrow().get(key) {
+        crate::timings::count(hit);
+        return value.clone();
+    }
+
+    crate::timings::count(miss);
+    let value = f();
+    cache.borrow_mut().insert(key.clone(), value.clone());
+    value
+}
+
+impl SolverBackend for CachedBackend {
+    fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+        cached(&self.exprs, expr, ("simplify-expr-hit", "simplify-expr-miss"), || {
+            self.backend.simplify_expr(expr)
+        })
+    }
+
+    fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
+        cached(&self.conditions, cond, ("simplify-condition-hit", "simplify-condition-miss"), || {
+            let simplified = self.backend.simplify_condition(cond);
+            // Simplifying to false already decides unsatisfiability.
+            if simplified == SymCondition::FALSE {
+                self.sat.borrow_mut().insert(cond.clone(), SatResult::Unsat);
+            }
+            simplified
+        })
+    }
+
+    fn check_sat(&self, cond: &SymCondition) -> SatResult {
+        cached(&self.sat, cond, ("check-sat-hit", "check-sat-miss"), || self.backend.check_sat(cond))
+    }
+
+    fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+        cached(&self.models, cond, ("get-model-hit", "get-model-miss"), || {
+            self.backend.get_model(cond)
+        })
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        // Results found with a different timeout may not be valid anymore.
+        self.conditions.borrow_mut().clear();
+        self.sat.borrow_mut().clear();
+        self.models.borrow_mut().clear();
+        self.backend.set_timeout(timeout);
+    }
+
+    fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+        self.backend.path_backend()
+    }
+
+    fn name(&self) -> String {
+        format!("cached {}", self.backend.name())
+    }
+}
+
+
+/// Pipes SMT-LIB2 scripts into an external solver process.
+///
+/// A new process is started for every query and killed if it runs into the
+/// timeout. External solvers cannot hand simplified terms back, so
+/// simplification only detects unsatisfiable conditions.
+#[derive(Debug, Clone)]
+pub struct ExternalBackend {
+    program: String,
+    args: Vec<String>,
+    timeout: Option<Duration>,
+}
+
+impl ExternalBackend {
+    /// Create a new backend running `program` with `args`, e.g. `z3 -in`.
+    pub fn new(program: String, args: Vec<String>) -> ExternalBackend {
+        ExternalBackend { program, args, timeout: None }
+    }
+
+    /// Run the solver on a script and return its output or `None` if it
+    /// timed out.
+    fn run(&self, script: &str) -> Option<String> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|err| panic!("external solver: failed to start {}: {}", self.program, err));
+
+        // Dropping stdin closes it, which makes the solver start working.
+        child.stdin.take().unwrap().write_all(script.as_bytes())
+            .unwrap_or_else(|err| panic!("external solver: failed to write script: {}", err));
+
+        let start = Instant::now();
+        loop {
+            let exited = child.try_wait()
+                .unwrap_or_else(|err| panic!("external solver: failed to wait: {}", err));
+
+            if exited.is_some() {
+                break;
+            }
+
+            if self.timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+                child.kill().ok();
+                child.wait().ok();
+                crate::timings::count("solver-timeout");
+                return None;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut output = Vec::new();
+        child.stdout.take().unwrap().read_to_end(&mut output)
+            .unwrap_or_else(|err| panic!("external solver: failed to read output: {}", err));
+
+        Some(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Run a script that starts with `check-sat` and return the result and
+    /// the remaining output.
+    fn run_check(&self, script: &str) -> (SatResult, String) {
+        let output = match self.run(script) {
+            Some(output) => output,
+            None => return (SatResult::Unknown, String::new()),
+        };
+
+        let mut lines = output.splitn(2, '\n');
+        let sat = match lines.next().map(str::trim) {
+            Some("sat") => SatResult::Sat,
+            Some("unsat") => SatResult::Unsat,
+            Some("unknown") => SatResult::Unknown,
+            _ => panic!("external solver: unexpected response: {}", output),
+        };
+
+        (sat, lines.next().unwrap_or("").to_string())
+    }
+}
+
+impl SolverBackend for ExternalBackend {
+    fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+        expr.clone()
+    }
+
+    fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
+        match self.check_sat(cond) {
+            SatResult::Unsat => SymCondition::FALSE,
+            _ => cond.clone(),
+        }
+    }
+
+    fn check_sat(&self, cond: &SymCondition) -> SatResult {
+        self.run_check(&cond.to_smtlib_script()).0
+    }
+
+    fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+        let symbols = cond.symbols();
+
+        let mut script = String::from("(set-option :produce-models true)\n");
+        script.push_str(&cond.to_smtlib_script());
+        if !symbols.is_empty() {
+            let names: Vec<_> = symbols.iter().map(|s| format!("|{}|", s)).collect();
+            script.push_str(&format!("(get-value ({}))\n", names.join(" ")));
+        }
+
+        let (sat, values) = self.run_check(&script);
+        if sat != SatResult::Sat {
+            return None;
+        }
+
+        if symbols.is_empty() {
+            return Some(HashMap::new());
+        }
+
+        let values = Z3Parser::new(&values).parse_values().unwrap_or_else(|err| {
+            panic!("external solver: failed to parse model: {}", err);
+        });
+
+        Some(values.into_iter().collect())
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+        Box::new(StackPathBackend::new(self))
+    }
+
+    fn name(&self) -> String {
+        format!("external {}", self.program)
+    }
+}
+
+
+/// Decides conditions without any symbols by evaluating them.
+///
+/// Conditions with symbols are only simplified natively and their
+/// satisfiability is unknown unless that decides them. Models are only
+/// found if setting all symbols to zero works, so a missing model never
+/// means that the condition is unsatisfiable.
+#[derive(Debug, Clone)]
+pub struct NativeBackend;
+
+impl NativeBackend {
+    /// Create a new native backend.
+    pub fn new() -> NativeBackend {
+        NativeBackend
+    }
+}
+
+impl SolverBackend for NativeBackend {
+    fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+        let mut ground = true;
+        expr.traverse(&mut |node| match node {
+            Traversed::Expr(SymExpr::Sym(_)) | Traversed::Array(SymArray::Base(_)) => ground = false,
+            _ => {},
+        });
+
+        if ground {
+            SymExpr::Int(expr.evaluate(&|_| None))
+        } else {
+            expr.simplify()
+        }
+    }
+
+    fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
+        if cond.symbols().is_empty() && cond.arrays().is_empty() {
+            SymCondition::Bool(cond.evaluate(&|_| None))
+        } else {
+            cond.simplify()
+        }
+    }
+
+    fn check_sat(&self, cond: &SymCondition) -> SatResult {
+        match self.simplify_condition(cond) {
+            SymCondition::Bool(true) => SatResult::Sat,
+            SymCondition::Bool(false) => SatResult::Unsat,
+            _ => SatResult::Unknown,
+        }
+    }
+
+    /// Try the model with all symbols zero. `None` means that it fails, not
+    /// that the condition is unsatisfiable.
+    fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+        let model: HashMap<_, _> = cond.symbols().into_iter()
+            .map(|symbol| (symbol, Integer(symbol.0, 0)))
+            .collect();
+
+        // Unknown array bytes are zero, too.
+        if cond.evaluate(&|symbol| Some(model.get(&symbol).copied().unwrap_or(Integer(symbol.0, 0)))) {
+            Some(model)
+        } else {
+            None
+        }
+    }
+
+    fn set_timeout(&mut self, _: Option<Duration>) {}
+
+    fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+        Box::new(StackPathBackend::new(self))
+    }
+
+    fn name(&self) -> String {
+        "native".to_string()
+    }
+}
+
+
+/// Emulates incremental solving for backends without native support by
+/// conjoining all path constraints into every query.
+pub struct StackPathBackend<'a> {
+    backend: &'a dyn SolverBackend,
+    path: Vec<SymCondition>,
+}
+
+impl<'a> StackPathBackend<'a> {
+    /// Create a new path backend answering queries with `backend`.
+    pub fn new(backend: &'a dyn SolverBackend) -> StackPathBackend<'a> {
+        StackPathBackend { backend, path: Vec::new() }
+    }
+}
+
+impl PathBackend for StackPathBackend<'_> {
+    fn push(&mut self, cond: &SymCondition) {
+        self.path.push(cond.clone());
+    }
+
+    fn pop(&mut self) {
+        self.path.pop();
+    }
+
+    fn check_sat(&mut self, cond: &SymCondition) -> SatResult {
+        let full = self.path.iter().cloned().fold(cond.clone(), SymCondition::and);
+        self.backend.check_sat(&full)
+    }
+}
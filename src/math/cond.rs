@@ -1,11 +1,12 @@
 //! Symbolic boolean expressions.
 
+use std::collections::BTreeSet;
 use std::fmt::{self, Display, Formatter};
 use z3::Context as Z3Context;
 use z3::ast::{Ast, Bool as Z3Bool};
 
-use crate::helper::{check_compatible, boxed};
-use super::{SymExpr, Symbol, Integer, DataType, Traversed};
+use crate::helper::check_compatible;
+use super::{SymExpr, SymArray, Symbol, Integer, DataType, Traversed, Node, node};
 use super::smt::{Z3Parser, FromAstError};
 use SymCondition::*;
 use SymExpr::*;
@@ -15,15 +16,15 @@ use SymExpr::*;
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum SymCondition {
     Bool(bool),
-    And(Box<SymCondition>, Box<SymCondition>),
-    Or(Box<SymCondition>, Box<SymCondition>),
-    Not(Box<SymCondition>),
-    Equal(Box<SymExpr>, Box<SymExpr>),
+    And(Node<SymCondition>, Node<SymCondition>),
+    Or(Node<SymCondition>, Node<SymCondition>),
+    Not(Node<SymCondition>),
+    Equal(Node<SymExpr>, Node<SymExpr>),
     /// If the bool is true, the operation is signed.
-    LessThan(Box<SymExpr>, Box<SymExpr>, bool),
-    LessEqual(Box<SymExpr>, Box<SymExpr>, bool),
-    GreaterThan(Box<SymExpr>, Box<SymExpr>, bool),
-    GreaterEqual(Box<SymExpr>, Box<SymExpr>, bool),
+    LessThan(Node<SymExpr>, Node<SymExpr>, bool),
+    LessEqual(Node<SymExpr>, Node<SymExpr>, bool),
+    GreaterThan(Node<SymExpr>, Node<SymExpr>, bool),
+    GreaterEqual(Node<SymExpr>, Node<SymExpr>, bool),
 }
 
 macro_rules! bin_cond  {
@@ -33,7 +34,7 @@ macro_rules! bin_cond  {
                 (Bool(a), Bool(b)) => Bool(a $op b),
                 (_, Bool($del)) | (Bool($del), _) => Bool($del),
                 (a, Bool($neutral)) | (Bool($neutral), a) => a,
-                (a, b) => $variant(Box::new(a), Box::new(b)),
+                (a, b) => $variant(node(a), node(b)),
             }
         }
     };
@@ -87,19 +88,77 @@ impl SymCondition {
         }
     }
 
+    /// Parse an SMT-LIB2 boolean term into a condition.
+    pub fn from_smtlib(term: &str) -> Result<SymCondition, FromAstError> {
+        Z3Parser::new(term).parse_condition()
+    }
+
+    /// Convert this condition into an SMT-LIB2 boolean term.
+    pub fn to_smtlib(&self) -> String {
+        let bin = |op: &str, a: &SymExpr, b: &SymExpr| {
+            format!("({} {} {})", op, a.to_smtlib(), b.to_smtlib())
+        };
+
+        match self {
+            Bool(b) => b.to_string(),
+
+            And(a, b) => format!("(and {} {})", a.to_smtlib(), b.to_smtlib()),
+            Or(a, b) => format!("(or {} {})", a.to_smtlib(), b.to_smtlib()),
+            Not(a) => format!("(not {})", a.to_smtlib()),
+
+            Equal(a, b) => bin("=", a, b),
+            LessThan(a, b, false) => bin("bvult", a, b),
+            LessEqual(a, b, false) => bin("bvule", a, b),
+            GreaterThan(a, b, false) => bin("bvugt", a, b),
+            GreaterEqual(a, b, false) => bin("bvuge", a, b),
+            LessThan(a, b, true) => bin("bvslt", a, b),
+            LessEqual(a, b, true) => bin("bvsle", a, b),
+            GreaterThan(a, b, true) => bin("bvsgt", a, b),
+            GreaterEqual(a, b, true) => bin("bvsge", a, b),
+        }
+    }
+
+    /// Read the conjunction of all assertions in an SMT-LIB2 script.
+    ///
+    /// Declarations and other commands are skipped since the types of the
+    /// symbols are part of their names.
+    pub fn from_smtlib_script(script: &str) -> Result<SymCondition, FromAstError> {
+        Z3Parser::new(script).parse_script()
+    }
+
+    /// Create a self-contained SMT-LIB2 script that declares all symbols of
+    /// this condition as bit-vectors and all arrays as byte arrays and
+    /// asserts it.
+    pub fn to_smtlib_script(&self) -> String {
+        let arrays = self.arrays();
+        let logic = if arrays.is_empty() { "QF_BV" } else { "QF_ABV" };
+        let mut script = format!("(set-logic {})\n", logic);
+        for symbol in self.symbols() {
+            script.push_str(&format!("(declare-fun |{}| () (_ BitVec {}))\n",
+                symbol, symbol.0.bits()));
+        }
+        for name in arrays {
+            script.push_str(&format!(
+                "(declare-fun {} () (Array (_ BitVec 64) (_ BitVec 8)))\n", name));
+        }
+        script.push_str(&format!("(assert {})\n", self.to_smtlib()));
+        script.push_str("(check-sat)\n");
+        script
+    }
+
     bin_cond!(and, &&, And, true, false);
     bin_cond!(or, ||, Or, false, true);
 
     pub fn not(self) -> SymCondition {
         match self {
             Bool(x) => Bool(!x),
-            x => Not(Box::new(x)),
+            x => Not(node(x)),
         }
     }
 
     pub fn if_then_else(self, a: SymExpr, b: SymExpr) -> SymExpr {
         check_compatible(a.data_type(), b.data_type(), "if-then-else");
-        IfThenElse(boxed(self), boxed(a), boxed(b))
+        IfThenElse(node(self), node(a), node(b))
     }
 
     /// Convert this condition into an expression, where `true` is represented by
@@ -107,7 +166,7 @@ impl SymCondition {
     pub fn as_expr(self, data_type: DataType) -> SymExpr {
         match self {
             Bool(b) => Int(Integer::from_bool(b, data_type)),
-            c => AsExpr(Box::new(c), data_type),
+            c => AsExpr(node(c), data_type),
         }
     }
 
@@ -132,12 +191,41 @@ impl SymCondition {
         forward!(self, traverse, f);
     }
 
+    /// The set of all symbols appearing in the condition.
+    pub fn symbols(&self) -> BTreeSet<Symbol> {
+        let mut symbols = BTreeSet::new();
+        self.traverse(&mut |node| {
+            if let Traversed::Expr(&Sym(symbol)) = node {
+                symbols.insert(symbol);
+            }
+        });
+        symbols
+    }
+
+    /// The names of all base arrays appearing in the condition.
+    pub fn arrays(&self) -> BTreeSet<&'static str> {
+        let mut arrays = BTreeSet::new();
+        self.traverse(&mut |node| {
+            if let Traversed::Array(&SymArray::Base(name)) = node {
+                arrays.insert(name);
+            }
+        });
+        arrays
+    }
+
     /// Replace the symbols with new expressions.
     pub fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
         forward!(self, replace_symbols, symbols);
     }
 }
 
+impl Node<SymCondition> {
+    /// Replace the symbols in the shared condition.
+    pub fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
+        self.update(|cond| cond.replace_symbols(symbols));
+    }
+}
+
 impl Display for SymCondition {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         use crate::helper::signed_name;
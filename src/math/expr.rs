@@ -4,8 +4,9 @@ use std::fmt::{self, Display, Formatter};
 use z3::Context as Z3Context;
 use z3::ast::{BV as Z3BitVec};
 
-use crate::helper::{check_compatible, boxed};
-use super::{Symbol, SymCondition, Integer, DataType, Traversed};
+use crate::helper::check_compatible;
+use super::{Symbol, SymCondition, SymArray, Integer, DataType, Traversed, Node, node};
+use super::num::{extract_type, concat_type};
 use super::smt::{Z3Parser, FromAstError};
 use SymExpr::*;
 use SymCondition::*;
@@ -16,15 +17,21 @@ use SymCondition::*;
 pub enum SymExpr {
     Int(Integer),
     Sym(Symbol),
-    Add(Box<SymExpr>, Box<SymExpr>),
-    Sub(Box<SymExpr>, Box<SymExpr>),
-    Mul(Box<SymExpr>, Box<SymExpr>),
-    BitAnd(Box<SymExpr>, Box<SymExpr>),
-    BitOr(Box<SymExpr>, Box<SymExpr>),
-    BitNot(Box<SymExpr>),
-    Cast(Box<SymExpr>, DataType, bool),
-    AsExpr(Box<SymCondition>, DataType),
-    IfThenElse(Box<SymCondition>, Box<SymExpr>, Box<SymExpr>),
+    Add(Node<SymExpr>, Node<SymExpr>),
+    Sub(Node<SymExpr>, Node<SymExpr>),
+    Mul(Node<SymExpr>, Node<SymExpr>),
+    BitAnd(Node<SymExpr>, Node<SymExpr>),
+    BitOr(Node<SymExpr>, Node<SymExpr>),
+    BitNot(Node<SymExpr>),
+    Cast(Node<SymExpr>, DataType, bool),
+    /// The bits from the high to the low index (both inclusive).
+    Extract(Node<SymExpr>, usize, usize),
+    /// The high part followed by the low part.
+    Concat(Node<SymExpr>, Node<SymExpr>),
+    AsExpr(Node<SymCondition>, DataType),
+    IfThenElse(Node<SymCondition>, Node<SymExpr>, Node<SymExpr>),
+    /// The byte of the array at the address.
+    Select(Node<SymArray>, Node<SymExpr>),
 }
 
 macro_rules! bin_expr {
@@ -33,7 +40,7 @@ macro_rules! bin_expr {
             check_compatible(self.data_type(), other.data_type(), "operation");
             match (self, other) {
                 (Int(a), Int(b)) => Int(a.$func(b)),
-                (a, b) => $variant(Box::new(a), Box::new(b)),
+                (a, b) => $variant(node(a), node(b)),
             }
         }
     };
@@ -45,9 +52,9 @@ macro_rules! bin_expr_simplifying {
             check_compatible(self.data_type(), other.data_type(), "operation");
             fn add_or_sub(expr: SymExpr, a: Integer, b: Integer) -> SymExpr {
                 if a.flagged_sub(b).1.sign {
-                    Sub(boxed(expr), boxed(Int(b.sub(a))))
+                    Sub(node(expr), node(Int(b.sub(a))))
                 } else {
-                    Add(boxed(expr), boxed(Int(a.sub(b))))
+                    Add(node(expr), node(Int(a.sub(b))))
                 }
             }
             let $a = self;
@@ -63,7 +70,7 @@ macro_rules! cmp_signed {
             check_compatible(self.data_type(), other.data_type(), "comparison");
             match (self, other) {
                 (Int(a), Int(b)) => Bool(a.$func(b, signed)),
-                (a, b) => $variant(Box::new(a), Box::new(b), signed),
+                (a, b) => $variant(node(a), node(b), signed),
             }
         }
     };
@@ -81,8 +88,11 @@ macro_rules! forward {
             BitOr(a, b) => { a.$func($arg); b.$func($arg); },
             BitNot(a) => a.$func($arg),
             Cast(a, _, _) => a.$func($arg),
+            Extract(a, _, _) => a.$func($arg),
+            Concat(a, b) => { a.$func($arg); b.$func($arg); },
             AsExpr(a, _) => a.$func($arg),
             IfThenElse(c, a, b) => { a.$func($arg); b.$func($arg); c.$func($arg); },
+            Select(a, x) => { a.$func($arg); x.$func($arg); },
         }
     };
 }
@@ -131,11 +141,13 @@ impl SymExpr {
                         x_ast.zero_ext(extra_bits)
                     }
                 } else if src_len > dest_len {
-                    x_ast.extract(dest_len, 0)
+                    x_ast.extract(dest_len - 1, 0)
                 } else {
                     x_ast
                 }
             },
+            Extract(x, hi, lo) => x.to_z3_ast(ctx).extract(*hi as u32, *lo as u32),
+            Concat(a, b) => z3_binop!(ctx, a, b, concat),
             AsExpr(x, new) => {
                 x.to_z3_ast(ctx).ite(
                     &Z3BitVec::from_u64(ctx, 1, new.bits() as u32),
@@ -143,6 +155,48 @@ impl SymExpr {
                 )
             },
             IfThenElse(c, a, b) => c.to_z3_ast(ctx).ite(&a.to_z3_ast(ctx), &b.to_z3_ast(ctx)),
+            Select(a, x) => a.to_z3_ast(ctx).select(&x.to_z3_ast(ctx)),
+        }
+    }
+
+    /// Parse an SMT-LIB2 bit-vector term into an expression.
+    pub fn from_smtlib(term: &str) -> Result<SymExpr, FromAstError> {
+        Z3Parser::new(term).parse_expr()
+    }
+
+    /// Convert this expression into an SMT-LIB2 bit-vector term.
+    pub fn to_smtlib(&self) -> String {
+        match self {
+            Int(int) => format!("#x{:01$x}", int.1, int.0.bits() / 4),
+            Sym(sym) => format!("|{}|", sym),
+
+            Add(a, b) => format!("(bvadd {} {})", a.to_smtlib(), b.to_smtlib()),
+            Sub(a, b) => format!("(bvsub {} {})", a.to_smtlib(), b.to_smtlib()),
+            Mul(a, b) => format!("(bvmul {} {})", a.to_smtlib(), b.to_smtlib()),
+            BitAnd(a, b) => format!("(bvand {} {})", a.to_smtlib(), b.to_smtlib()),
+            BitOr(a, b) => format!("(bvor {} {})", a.to_smtlib(), b.to_smtlib()),
+            BitNot(a) => format!("(bvnot {})", a.to_smtlib()),
+
+            Cast(x, new, signed) => {
+                let src_len = x.data_type().bits();
+                let dest_len = new.bits();
+
+                if src_len < dest_len {
+                    let kind = if *signed { "sign_extend" } else { "zero_extend" };
+                    format!("((_ {} {}) {})", kind, dest_len - src_len, x.to_smtlib())
+                } else if src_len > dest_len {
+                    format!("((_ extract {} 0) {})", dest_len - 1, x.to_smtlib())
+                } else {
+                    x.to_smtlib()
+                }
+            },
+            Extract(x, hi, lo) => format!("((_ extract {} {}) {})", hi, lo, x.to_smtlib()),
+            Concat(a, b) => format!("(concat {} {})", a.to_smtlib(), b.to_smtlib()),
+            AsExpr(x, new) => format!("(ite {} {} {})", x.to_smtlib(),
+                Int(Integer(*new, 1)).to_smtlib(), Int(Integer(*new, 0)).to_smtlib()),
+            IfThenElse(c, a, b) => format!("(ite {} {} {})",
+                c.to_smtlib(), a.to_smtlib(), b.to_smtlib()),
+            Select(a, x) => format!("(select {} {})", a.to_smtlib(), x.to_smtlib()),
         }
     }
 
@@ -150,42 +204,42 @@ impl SymExpr {
     bin_expr_simplifying!(add, a, b, match (a, b) {
         (a, Int(Integer(_, 0))) | (Int(Integer(_, 0)), a) => a,
         (Int(a), Int(b)) => Int(a.add(b)),
-        (Int(a), Add(b, c)) | (Add(b, c), Int(a)) => match (*b, *c) {
-            (Int(b), c) | (c, Int(b)) => Add(boxed(c), boxed(Int(a.add(b)))),
-            (b, c) => Add(boxed(Int(a)), boxed(Add(boxed(b), boxed(c)))),
+        (Int(a), Add(b, c)) | (Add(b, c), Int(a)) => match (b.into_inner(), c.into_inner()) {
+            (Int(b), c) | (c, Int(b)) => Add(node(c), node(Int(a.add(b)))),
+            (b, c) => Add(node(Int(a)), node(Add(node(b), node(c)))),
         },
-        (Int(a), Sub(b, c)) | (Sub(b, c), Int(a)) => match (*b, *c) {
+        (Int(a), Sub(b, c)) | (Sub(b, c), Int(a)) => match (b.into_inner(), c.into_inner()) {
             (b, Int(c)) => add_or_sub(b, a, c),
-            (Int(b), c) => Sub(boxed(Int(a.add(b))), boxed(c)),
-            (b, c) => Add(boxed(Int(a)), boxed(Sub(boxed(b), boxed(c))))
+            (Int(b), c) => Sub(node(Int(a.add(b))), node(c)),
+            (b, c) => Add(node(Int(a)), node(Sub(node(b), node(c))))
         }
-        (a, b) => Add(boxed(a), boxed(b)),
+        (a, b) => Add(node(a), node(b)),
     });
 
     // Subtract and simplify.
     bin_expr_simplifying!(sub, a, b, match (a, b) {
         (a, Int(Integer(_, 0))) | (Int(Integer(_, 0)), a) => a,
         (Int(a), Int(b)) => Int(a.sub(b)),
-        (Int(a), Sub(b, c)) => match (*b, *c) {
+        (Int(a), Sub(b, c)) => match (b.into_inner(), c.into_inner()) {
             (Int(b), c) => add_or_sub(c, a, b),
-            (b, Int(c)) => Sub(boxed(Int(a.add(c))), boxed(b)),
-            (b, c) => Sub(boxed(Int(a)), boxed(Sub(boxed(b), boxed(c)))),
+            (b, Int(c)) => Sub(node(Int(a.add(c))), node(b)),
+            (b, c) => Sub(node(Int(a)), node(Sub(node(b), node(c)))),
         },
-        (Sub(a, b), Int(c)) => match (*a, *b) {
-            (Int(a), b) => Sub(boxed(Int(a.sub(c))), boxed(b)),
-            (a, Int(b)) => Sub(boxed(a), boxed(Int(b.add(c)))),
-            (a, b) => Sub(boxed(Sub(boxed(a), boxed(b))), boxed(Int(c))),
+        (Sub(a, b), Int(c)) => match (a.into_inner(), b.into_inner()) {
+            (Int(a), b) => Sub(node(Int(a.sub(c))), node(b)),
+            (a, Int(b)) => Sub(node(a), node(Int(b.add(c)))),
+            (a, b) => Sub(node(Sub(node(a), node(b))), node(Int(c))),
         },
-        (Int(a), Add(b, c)) => match (*b, *c) {
-            (Int(b), c) => Sub(boxed(Int(a.sub(b))), boxed(c)),
-            (b, Int(c)) => Sub(boxed(Int(a.add(c))), boxed(b)),
-            (b, c) => Sub(boxed(Int(a)), boxed(Add(boxed(b), boxed(c)))),
+        (Int(a), Add(b, c)) => match (b.into_inner(), c.into_inner()) {
+            (Int(b), c) => Sub(node(Int(a.sub(b))), node(c)),
+            (b, Int(c)) => Sub(node(Int(a.add(c))), node(b)),
+            (b, c) => Sub(node(Int(a)), node(Add(node(b), node(c)))),
         },
-        (Add(a, b), Int(c)) => match (*a, *b) {
+        (Add(a, b), Int(c)) => match (a.into_inner(), b.into_inner()) {
             (Int(a), b) | (b, Int(a)) => add_or_sub(b, a, c),
-            (a, b) => Sub(boxed(Add(boxed(a), boxed(b))), boxed(Int(c))),
+            (a, b) => Sub(node(Add(node(a), node(b))), node(Int(c))),
         }
-        (a, b) => Sub(boxed(a), boxed(b)),
+        (a, b) => Sub(node(a), node(b)),
     });
 
     bin_expr!(mul, Mul);
@@ -195,7 +249,7 @@ impl SymExpr {
     pub fn bitnot(self) -> SymExpr {
         match self {
             Int(x) => Int(x.bitnot()),
-            x => BitNot(Box::new(x)),
+            x => BitNot(node(x)),
         }
     }
 
@@ -203,7 +257,7 @@ impl SymExpr {
         check_compatible(self.data_type(), other.data_type(), "comparison");
         match (self, other) {
             (Int(a), Int(b)) => Bool(a.equal(b)),
-            (a, b) => Equal(Box::new(a), Box::new(b)),
+            (a, b) => Equal(node(a), node(b)),
         }
     }
 
@@ -217,14 +271,59 @@ impl SymExpr {
             Int(x) => Int(x.cast(new, signed)),
             Cast(x, t, false) => {
                 if x.data_type() == new {
-                    *x
+                    x.into_inner()
                 } else if t.bytes() < new.bytes() {
                     Cast(x, new, false)
                 } else {
-                    Cast(boxed(Cast(x, t, false)), new, signed)
+                    Cast(node(Cast(x, t, false)), new, signed)
                 }
             },
-            s => if s.data_type() == new { s } else { Cast(boxed(s), new, signed) },
+            // Truncating a concatenation into its low part.
+            Concat(_, b) if new.bits() <= b.data_type().bits() => b.into_inner().cast(new, false),
+            s => if s.data_type() == new { s } else { Cast(node(s), new, signed) },
+        }
+    }
+
+    /// Extract the bits from `hi` down to `lo` (both inclusive).
+    ///
+    /// Extracting the low bits is a truncating cast. Panics if the extracted
+    /// range is out of bounds or has no matching data type.
+    pub fn extract(self, hi: usize, lo: usize) -> SymExpr {
+        let new = extract_type(self.data_type(), hi, lo);
+        match self {
+            s if lo == 0 => s.cast(new, false),
+            Int(x) => Int(x.extract(hi, lo)),
+            Concat(a, b) => {
+                let low_bits = b.data_type().bits();
+                if lo >= low_bits {
+                    a.into_inner().extract(hi - low_bits, lo - low_bits)
+                } else if hi < low_bits {
+                    b.into_inner().extract(hi, lo)
+                } else {
+                    Extract(node(Concat(a, b)), hi, lo)
+                }
+            },
+            // The extended bits of a zero extension are known to be zero.
+            Cast(x, _, false) if lo >= x.data_type().bits() => Int(Integer(new, 0)),
+            Cast(x, _, _) if hi < x.data_type().bits() => x.into_inner().extract(hi, lo),
+            s => Extract(node(s), hi, lo),
+        }
+    }
+
+    /// Concatenate this expression as the high part with the low part.
+    ///
+    /// Panics if there is no data type with the combined width.
+    pub fn concat(self, low: SymExpr) -> SymExpr {
+        let new = concat_type(self.data_type(), low.data_type());
+        match (self, low) {
+            (Int(a), Int(b)) => Int(a.concat(b)),
+            (Int(Integer(_, 0)), b) => b.cast(new, false),
+            // Adjacent bits of the same value are merged again.
+            (Extract(a, hi, mid), Extract(b, mid_low, lo))
+                if a == b && mid == mid_low + 1 => a.into_inner().extract(hi, lo),
+            (Extract(a, hi, mid), Cast(b, t, _))
+                if a == b && mid == t.bits() => a.into_inner().extract(hi, 0),
+            (a, b) => Concat(node(a), node(b)),
         }
     }
 
@@ -240,8 +339,11 @@ impl SymExpr {
             BitOr(a, _)  => a.data_type(),
             BitNot(a)    => a.data_type(),
             Cast(_, new, _) => *new,
+            Extract(x, hi, lo) => extract_type(x.data_type(), *hi, *lo),
+            Concat(a, b) => concat_type(a.data_type(), b.data_type()),
             AsExpr(_, new)  => *new,
             IfThenElse(_, a, _) => a.data_type(),
+            Select(_, _) => DataType::N8,
         }
     }
 
@@ -259,12 +361,15 @@ impl SymExpr {
             BitOr(a, b) => a.evaluate(symbols).bitor(b.evaluate(symbols)),
             BitNot(a) => a.evaluate(symbols).bitnot(),
             Cast(a, data_type, signed) => a.evaluate(symbols).cast(*data_type, *signed),
+            Extract(a, hi, lo) => a.evaluate(symbols).extract(*hi, *lo),
+            Concat(a, b) => a.evaluate(symbols).concat(b.evaluate(symbols)),
             AsExpr(a, data_type) => Integer::from_bool(a.evaluate(symbols), *data_type),
             IfThenElse(c, a, b) => if c.evaluate(symbols) {
                 a.evaluate(symbols)
             } else {
                 b.evaluate(symbols)
-            }
+            },
+            Select(a, x) => a.evaluate(x.evaluate(symbols), symbols),
         }
     }
 
@@ -283,6 +388,13 @@ impl SymExpr {
     }
 }
 
+impl Node<SymExpr> {
+    /// Replace the symbols in the shared expression.
+    pub fn replace_symbols<S>(&mut self, symbols: &S) where S: Fn(Symbol) -> SymExpr {
+        self.update(|expr| expr.replace_symbols(symbols));
+    }
+}
+
 impl Display for SymExpr {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         use crate::helper::signed_name;
@@ -297,8 +409,11 @@ impl Display for SymExpr {
             BitOr(a, b) => write!(f, "({} | {})", a, b),
             BitNot(a) => write!(f, "(!{})", a),
             Cast(x, new, signed) => write!(f, "({} as {}{})", x, new, signed_name(*signed)),
+            Extract(x, hi, lo) => write!(f, "{}[{}:{}]", x, hi, lo),
+            Concat(a, b) => write!(f, "({} ++ {})", a, b),
             AsExpr(c, data_type) => write!(f, "({} as {})", c, data_type),
             IfThenElse(c, a, b) => write!(f, "if {} then {} else {}", c, a, b),
+            Select(a, x) => write!(f, "{}[{}]", a, x),
         }
     }
 }
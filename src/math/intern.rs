@@ -0,0 +1,179 @@
+//! Hash-consed sharing of expression nodes.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+use super::{SymExpr, SymCondition, SymArray};
+
+
+/// A shared, interned child of a symbolic expression tree.
+///
+/// Structurally equal nodes share the same allocation, so cloning, comparing
+/// and hashing a node is O(1) regardless of the size of the tree below it.
+pub struct Node<T: Intern>(Rc<Entry<T>>);
+
+struct Entry<T> {
+    value: T,
+    hash: u64,
+}
+
+/// Intern a value into a shared node.
+pub fn node<T: Intern>(value: T) -> Node<T> {
+    Node::new(value)
+}
+
+impl<T: Intern> Node<T> {
+    /// Intern a value, reusing the existing node if an equal one is alive.
+    pub fn new(value: T) -> Node<T> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        T::with_table(|table| table.intern(value, hash))
+    }
+
+    /// Take the value out of the node, cloning it if it is shared.
+    ///
+    /// This is cheap since the children of the value are nodes themselves.
+    pub fn into_inner(self) -> T where T: Clone {
+        match Rc::try_unwrap(self.0) {
+            Ok(entry) => entry.value,
+            Err(rc) => rc.value.clone(),
+        }
+    }
+
+    /// Change the value, interning the result as a new node.
+    pub fn update<F>(&mut self, f: F) where T: Clone, F: FnOnce(&mut T) {
+        let mut value = self.0.value.clone();
+        f(&mut value);
+        *self = Node::new(value);
+    }
+}
+
+impl<T: Intern> Deref for Node<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T: Intern> AsRef<T> for Node<T> {
+    fn as_ref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T: Intern> Clone for Node<T> {
+    fn clone(&self) -> Node<T> {
+        Node(self.0.clone())
+    }
+}
+
+impl<T: Intern> PartialEq for Node<T> {
+    fn eq(&self, other: &Node<T>) -> bool {
+        // Interning guarantees that equal values are the same allocation.
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: Intern> Eq for Node<T> {}
+
+impl<T: Intern> Hash for Node<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl<T: Intern + Debug> Debug for Node<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(&self.0.value, f)
+    }
+}
+
+impl<T: Intern + Display> Display for Node<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.0.value, f)
+    }
+}
+
+
+/// Types whose values can be interned into nodes.
+pub trait Intern: Hash + Eq + Sized + 'static {
+    /// Run a function with the interning table of this type.
+    fn with_table<R, F>(f: F) -> R where F: FnOnce(&mut Table<Self>) -> R;
+}
+
+/// Weakly references all alive nodes of a type by their hash.
+///
+/// Nodes are freed as soon as the last expression using them is dropped, the
+/// dead references are purged whenever the table doubled in size.
+pub struct Table<T> {
+    buckets: HashMap<u64, Vec<Weak<Entry<T>>>>,
+    len: usize,
+    threshold: usize,
+}
+
+impl<T: Intern> Table<T> {
+    fn new() -> Table<T> {
+        Table {
+            buckets: HashMap::new(),
+            len: 0,
+            threshold: 1024,
+        }
+    }
+
+    fn intern(&mut self, value: T, hash: u64) -> Node<T> {
+        let bucket = self.buckets.entry(hash).or_default();
+
+        // Comparing the values is cheap as their children are nodes.
+        for weak in bucket.iter() {
+            if let Some(rc) = weak.upgrade() {
+                if rc.value == value {
+                    return Node(rc);
+                }
+            }
+        }
+
+        let rc = Rc::new(Entry { value, hash });
+        bucket.push(Rc::downgrade(&rc));
+
+        self.len += 1;
+        if self.len > self.threshold {
+            self.purge();
+        }
+
+        Node(rc)
+    }
+
+    fn purge(&mut self) {
+        for bucket in self.buckets.values_mut() {
+            bucket.retain(|weak| weak.strong_count() > 0);
+        }
+        self.buckets.retain(|_, bucket| !bucket.is_empty());
+        self.len = self.buckets.values().map(Vec::len).sum();
+        self.threshold = (2 * self.len).max(1024);
+    }
+}
+
+macro_rules! intern_table {
+    ($type:ty, $table:ident) => {
+        thread_local! {
+            static $table: RefCell<Table<$type>> = RefCell::new(Table::new());
+        }
+
+        impl Intern for $type {
+            fn with_table<R, F>(f: F) -> R where F: FnOnce(&mut Table<Self>) -> R {
+                $table.with(|table| f(&mut table.borrow_mut()))
+            }
+        }
+    };
+}
+
+intern_table!(SymExpr, EXPR_TABLE);
+intern_table!(SymCondition, CONDITION_TABLE);
+intern_table!(SymArray, ARRAY_TABLE);
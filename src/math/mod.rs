@@ -2,7 +2,10 @@
 
 #![macro_use]
 
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use lazy_static::lazy_static;
 
 macro_rules! z3_binop {
     ($ctx:expr, $a:expr, $b:expr, $op:ident) => {
@@ -13,12 +16,20 @@ macro_rules! z3_binop {
 mod num;
 mod expr;
 mod cond;
+mod array;
 mod smt;
+mod backend;
+mod simplify;
+mod intern;
 
 pub use num::*;
 pub use expr::*;
 pub use cond::*;
-pub use smt::{Solver, SharedSolver, FromAstError};
+pub use array::*;
+pub use smt::{Solver, SharedSolver, SolverBackend, Z3Backend, FromAstError, stdin_from_model, MAX_MODEL_INPUT};
+pub use smt::{PathSolver, PathBackend, SatResult};
+pub use backend::*;
+pub use intern::{Node, Intern, Table, node};
 
 
 /// A dynamically typed symbolic value.
@@ -26,6 +37,7 @@ pub use smt::{Solver, SharedSolver, FromAstError};
 pub enum SymDynamic {
     Expr(SymExpr),
     Condition(SymCondition),
+    Array(SymArray),
 }
 
 impl From<SymExpr> for SymDynamic {
@@ -36,6 +48,10 @@ impl From<SymCondition> for SymDynamic {
     fn from(cond: SymCondition) -> SymDynamic { SymDynamic::Condition(cond) }
 }
 
+impl From<SymArray> for SymDynamic {
+    fn from(array: SymArray) -> SymDynamic { SymDynamic::Array(array) }
+}
+
 /// A symbol value identified by an index.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Symbol(pub DataType, pub &'static str, pub usize);
@@ -46,18 +62,58 @@ impl Display for Symbol {
     }
 }
 
-/// A reference to an expression or condition node in the traversed tree.
+lazy_static! {
+    /// The names of the numbered symbol spaces created so far.
+    static ref NUMBERED_SPACES: Mutex<HashMap<(&'static str, usize), &'static str>>
+        = Mutex::new(HashMap::new());
+    /// The array names and array symbol spaces created so far.
+    static ref ARRAY_NAMES: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+/// The name of a numbered symbol space like `argv0_`, whose symbols are the
+/// bytes of the first argument.
+///
+/// The names are allocated once and live for the rest of the program.
+pub fn numbered_space(space: &'static str, number: usize) -> &'static str {
+    let mut spaces = NUMBERED_SPACES.lock().expect("numbered_space: poisoned lock");
+    spaces.entry((space, number))
+        .or_insert_with(|| Box::leak(format!("{}{}_", space, number).into_boxed_str()))
+}
+
+/// The name of a base array, like one parsed back from a solver.
+///
+/// The names are allocated once and live for the rest of the program.
+pub fn array_name(name: &str) -> &'static str {
+    let mut names = ARRAY_NAMES.lock().expect("array_name: poisoned lock");
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        },
+    }
+}
+
+/// The symbol space of the unknown bytes of a base array, like `memarr` for
+/// `mem`. Its symbols are indexed by address, unlike the default symbols of a
+/// memory, which are numbered in the order they are generated.
+pub fn array_space(name: &str) -> &'static str {
+    array_name(&format!("{}arr", name))
+}
+
+/// A reference to an expression, condition or array node in the traversed tree.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Traversed<'a> {
     Expr(&'a SymExpr),
     Condition(&'a SymCondition),
+    Array(&'a SymArray),
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::helper::boxed;
     use crate::math::Integer;
     use crate::math::DataType::*;
     use SymExpr::*;
@@ -66,15 +122,25 @@ mod tests {
     fn x() -> SymExpr { Sym(Symbol(N64, "stdin", 0)) }
     fn y() -> SymExpr { Sym(Symbol(N8, "stdin", 1)) }
 
+    /// The solver configurations to test, which include the external
+    /// backend only if a `z3` binary is installed.
+    fn solver_configs() -> Vec<&'static str> {
+        let installed = std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| dir.join("z3").is_file())
+        });
+
+        if installed { vec!["z3", "external:z3 -in"] } else { vec!["z3"] }
+    }
+
     #[test]
     fn calculations() {
         assert_eq!(x().add(n(0)), x());
         assert_eq!(n(10).add(n(0)), n(10));
-        assert_eq!(x().add(n(5)).add(n(10)), Add(boxed(x()), boxed(n(15))));
-        assert_eq!(x().sub(n(5)).add(n(10)), Add(boxed(x()), boxed(n(5))));
-        assert_eq!(x().sub(n(10)).sub(n(5)), Sub(boxed(x()), boxed(n(15))));
-        assert_eq!(x().add(n(10)).sub(n(5)), Add(boxed(x()), boxed(n(5))));
-        assert_eq!(x().sub(n(8)).sub(n(8)).add(n(8)), Sub(boxed(x()), boxed(n(8))));
+        assert_eq!(x().add(n(5)).add(n(10)), Add(node(x()), node(n(15))));
+        assert_eq!(x().sub(n(5)).add(n(10)), Add(node(x()), node(n(5))));
+        assert_eq!(x().sub(n(10)).sub(n(5)), Sub(node(x()), node(n(15))));
+        assert_eq!(x().add(n(10)).sub(n(5)), Add(node(x()), node(n(5))));
+        assert_eq!(x().sub(n(8)).sub(n(8)).add(n(8)), Sub(node(x()), node(n(8))));
 
         assert_ne!(n(10).add(x()).add(x()).add(n(5)), n(10).add(x()).add(x()));
 
@@ -90,4 +156,315 @@ mod tests {
         let expr = n(10).add(x()).add(x()).add(n(5));
         assert_eq!(solver.simplify_expr(&expr), n(15).add(n(2).mul(x())));
     }
+
+    #[test]
+    fn interning() {
+        use std::collections::HashSet;
+
+        let build = || n(10).add(x()).mul(y().cast(N64, true)).sub(x().bitnot());
+        let (a, b) = (build(), build());
+
+        // Independently built trees share their nodes.
+        assert_eq!(node(a.clone()), node(b.clone()));
+        assert_ne!(node(a.clone()), node(x().bitnot()));
+        assert_eq!(node(a.clone().equal(n(3))), node(b.clone().equal(n(3))));
+
+        let mut replaced = node(a.clone());
+        replaced.replace_symbols(&|symbol| Int(Integer(symbol.0, 0)));
+        assert_ne!(replaced, node(a.clone()));
+        assert_eq!(replaced.evaluate(&|_| None), Integer(N64, 1));
+
+        let set: HashSet<_> = vec![a, b].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn simplify() {
+        let z = Sym(Symbol(N64, "stdin", 2));
+
+        // Linear terms are merged and constants folded across nested chains.
+        let expr = n(10).add(x()).sub(n(3).mul(z.clone())).add(x()).add(n(5));
+        assert_eq!(expr.simplify(), n(15).add(n(2).mul(x())).sub(n(3).mul(z.clone())));
+        assert_eq!(x().add(z.clone()).sub(x()).sub(z.clone()).simplify(), n(0));
+        assert_eq!(n(4).mul(x().sub(n(1))).simplify(), Sub(node(n(4).mul(x())), node(n(4))).simplify());
+
+        // Casts of extensions back to the original type vanish.
+        assert_eq!(y().cast(N64, true).cast(N8, false).simplify(), y());
+        assert_eq!(y().cast(N64, true).cast(N32, false).simplify(), y().cast(N32, true));
+
+        // Constant conditions select a branch.
+        let decided = x().add(n(1)).equal(n(1).add(x()));
+        assert_eq!(decided.simplify(), SymCondition::TRUE);
+        assert_eq!(decided.clone().if_then_else(x(), n(7)).simplify(), x());
+        assert_eq!(decided.clone().not().as_expr(N8).simplify(), Int(Integer(N8, 0)));
+        assert_eq!(x().less_equal(x(), true).simplify(), SymCondition::TRUE);
+
+        // Boolean absorption and negated comparisons.
+        let a = x().less_than(n(10), false);
+        let b = z.clone().equal(n(3));
+        assert_eq!(a.clone().and(a.clone().or(b.clone())).simplify(), a);
+        assert_eq!(a.clone().or(b.clone().and(a.clone())).simplify(), a);
+        assert_eq!(a.clone().and(a.clone().not()).simplify(), SymCondition::FALSE);
+        assert_eq!(a.clone().not().simplify(), x().greater_equal(n(10), false));
+
+        // Only what the native simplifier cannot decide reaches the backend.
+        let native = Solver::with_config(&"native".parse().unwrap());
+        assert_eq!(native.check_sat(&decided), SatResult::Sat);
+        assert_eq!(native.simplify_expr(&expr), expr.simplify());
+    }
+
+    #[test]
+    fn bit_slices() {
+        let a = Sym(Symbol(N8, "stdin", 0));
+        let b = Sym(Symbol(N8, "stdin", 1));
+        let word = a.clone().concat(b.clone());
+
+        assert_eq!(word.data_type(), N16);
+        assert_eq!(word.clone().extract(15, 8), a);
+        assert_eq!(word.clone().extract(7, 0), b);
+        assert_eq!(x().extract(47, 40).concat(x().extract(39, 32)), x().extract(47, 32));
+        assert_eq!(x().extract(31, 16).concat(x().cast(N16, false)), x().cast(N32, false));
+        assert_eq!(Int(Integer(N8, 0x12)).concat(Int(Integer(N8, 0x34))), Int(Integer(N16, 0x1234)));
+        assert_eq!(n(0xdead_beef_0000).extract(47, 16), Int(Integer(N32, 0xdead_beef)));
+        assert_eq!(y().cast(N64, false).extract(63, 32), Int(Integer(N32, 0)));
+
+        let solver = Solver::new();
+        let shifted = x().extract(39, 8);
+        assert_eq!(SymExpr::from_smtlib(&shifted.to_smtlib()).unwrap(), shifted);
+        assert_eq!(SymExpr::from_smtlib(&word.to_smtlib()).unwrap(), word);
+        assert_eq!(solver.check_sat(&word.clone().equal(Int(Integer(N16, 0x1234)))
+            .and(a.equal(Int(Integer(N8, 0x13))))), SatResult::Unsat);
+        assert_eq!(solver.simplify_expr(&word.extract(15, 8).add(Int(Integer(N8, 0)))),
+            Sym(Symbol(N8, "stdin", 0)));
+    }
+
+    #[test]
+    fn arrays() {
+        let a = Sym(Symbol(N8, "stdin", 0));
+        let mem = SymArray::Base("mem").store(x(), a.clone()).store(x().add(n(1)), y());
+
+        // Stores at known distances are skipped, others stay selections.
+        assert_eq!(mem.select(x()), a);
+        assert_eq!(mem.select(n(1).add(x())), y());
+        let aliasing = mem.select(n(0x1000));
+        assert_eq!(aliasing, Select(node(mem.clone()), node(n(0x1000))));
+        assert_eq!(SymExpr::from_smtlib(&aliasing.to_smtlib()).unwrap(), aliasing);
+
+        let condition = aliasing.equal(a.clone()).and(x().equal(n(0xfff)));
+        assert!(condition.to_smtlib_script().contains(
+            "(declare-fun mem () (Array (_ BitVec 64) (_ BitVec 8)))"));
+
+        for config in solver_configs() {
+            let solver = Solver::with_config(&config.parse().unwrap());
+            assert_eq!(solver.check_sat(&condition), SatResult::Sat);
+            assert_eq!(solver.check_sat(&condition.clone()
+                .and(y().equal(a.clone().bitnot()))), SatResult::Unsat);
+        }
+
+        let solver = Solver::new();
+        assert_eq!(solver.simplify_condition(&condition.clone().and(x().equal(n(0x1000)))),
+            SymCondition::FALSE);
+        assert_eq!(solver.simplify_condition(&condition).arrays().len(), 1);
+        let model = solver.get_model(&condition).unwrap();
+        assert!(condition.evaluate(&|symbol| model.get(&symbol).copied()));
+
+        // The unknown bytes are indexed by address in a space of their own.
+        assert_eq!(model[&Symbol(N8, "memarr", 0x1000)], model[&Symbol(N8, "stdin", 0)]);
+        assert!(model.keys().all(|symbol| symbol.1 != "mem"));
+
+        // Arrays of any name are read back.
+        let heap = SymArray::Base("heap").store(x(), y()).select(n(0x1000));
+        assert_eq!(SymExpr::from_smtlib(&heap.to_smtlib()).unwrap(), heap);
+    }
+
+    #[test]
+    fn smtlib() {
+        let a = y().cast(N64, true);
+        let b = x().cast(N16, false).cast(N8, false);
+        let condition = a.clone().add(n(5)).less_than(x(), true)
+            .and(b.clone().equal(y()).not())
+            .or(a.greater_equal(x().equal(n(3)).as_expr(N64), false));
+
+        let script = condition.to_smtlib_script();
+        assert_eq!(script.lines().take(3).collect::<Vec<_>>(), [
+            "(set-logic QF_BV)",
+            "(declare-fun |stdin1:n8| () (_ BitVec 8))",
+            "(declare-fun |stdin0:n64| () (_ BitVec 64))",
+        ]);
+
+        assert_eq!(SymCondition::from_smtlib_script(&script).unwrap(), condition);
+        assert_eq!(SymCondition::from_smtlib(&condition.to_smtlib()).unwrap(), condition);
+        assert_eq!(SymExpr::from_smtlib(&b.to_smtlib()).unwrap(), b);
+        assert_eq!(b.to_smtlib(), "((_ extract 7 0) ((_ extract 15 0) |stdin0:n64|))");
+        let file = SymExpr::Sym(Symbol(N8, numbered_space("filein", 2), 7))
+            .add(SymExpr::Sym(Symbol(N8, "stderr", 1)));
+        assert_eq!(SymExpr::from_smtlib(&file.to_smtlib()).unwrap(), file);
+
+        let byte = |i| Sym(Symbol(N8, "stdin", i));
+        assert_eq!(SymExpr::from_smtlib("(concat |stdin0:n8| |stdin1:n8| |stdin2:n8| |stdin3:n8|)").unwrap(),
+            byte(0).concat(byte(1)).concat(byte(2).concat(byte(3))));
+        assert!(SymExpr::from_smtlib("(concat |stdin0:n8| |stdin1:n8| |stdin2:n8|)").is_err());
+    }
+
+    #[test]
+    fn backends() {
+        let a = Sym(Symbol(N8, "stdin", 0));
+        let condition = a.clone().add(Int(Integer(N8, 3))).equal(Int(Integer(N8, 10)));
+        let unsat = a.clone().equal(a.clone().bitnot());
+        let ground = n(3).mul(n(4)).less_than(n(10), false);
+
+        let native = Solver::with_config(&"native".parse().unwrap());
+        assert_eq!(native.simplify_expr(&n(3).mul(n(4))), n(12));
+        assert_eq!(native.simplify_condition(&ground), SymCondition::FALSE);
+        assert_eq!(native.check_sat(&unsat), SatResult::Unknown);
+
+        // Without a model, a satisfiable condition is still not unsatisfiable.
+        assert!(native.get_model(&condition).is_none());
+        assert_eq!(native.check_sat(&condition), SatResult::Unknown);
+        let zero = a.clone().equal(Int(Integer(N8, 0)));
+        assert_eq!(native.get_model(&zero), Some([(Symbol(N8, "stdin", 0), Integer(N8, 0))]
+            .iter().copied().collect()));
+
+        for config in solver_configs() {
+            let solver = Solver::with_config(&config.parse().unwrap());
+            assert_eq!(solver.check_sat(&ground), SatResult::Unsat);
+            assert_eq!(solver.check_sat(&unsat), SatResult::Unsat);
+            assert_eq!(solver.check_sat(&condition), SatResult::Sat);
+            assert_eq!(solver.simplify_condition(&unsat), SymCondition::FALSE);
+
+            let model = solver.get_model(&condition).unwrap();
+            assert_eq!(stdin_from_model(&model), b"\x07");
+        }
+
+        assert!("cvc5".parse::<SolverConfig>().is_err());
+    }
+
+    #[test]
+    fn cache() {
+        use std::cell::Cell;
+        use std::collections::HashMap;
+        use std::rc::Rc;
+
+        struct Counting(Rc<Cell<usize>>);
+        impl SolverBackend for Counting {
+            fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+                self.0.set(self.0.get() + 1);
+                expr.clone()
+            }
+            fn simplify_condition(&self, _: &SymCondition) -> SymCondition {
+                self.0.set(self.0.get() + 1);
+                SymCondition::FALSE
+            }
+            fn check_sat(&self, _: &SymCondition) -> SatResult {
+                self.0.set(self.0.get() + 1);
+                SatResult::Sat
+            }
+            fn get_model(&self, _: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+                self.0.set(self.0.get() + 1);
+                None
+            }
+            fn set_timeout(&mut self, _: Option<std::time::Duration>) {}
+            fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+                Box::new(StackPathBackend::new(self))
+            }
+            fn name(&self) -> String {
+                "counting".to_string()
+            }
+        }
+
+        let calls = Rc::new(Cell::new(0));
+        let solver = Solver::with_backend(Box::new(CachedBackend::new(
+            Box::new(Counting(calls.clone())))));
+
+        let expr = x().add(n(5)).mul(y().cast(N64, false));
+        let condition = expr.clone().equal(n(3));
+        for _ in 0 .. 3 {
+            solver.simplify_expr(&expr);
+            solver.simplify_condition(&condition);
+            assert_eq!(solver.check_sat(&condition), SatResult::Unsat);
+            solver.get_model(&condition);
+            assert_eq!(solver.check_equal_sat(&x(), &n(1)), SatResult::Sat);
+        }
+
+        // The first simplification already decided satisfiability.
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn path() {
+        let less = x().less_than(n(10), false);
+        let greater = x().greater_than(n(5), false);
+        let equal = x().equal(n(3));
+
+        let solver = Solver::new();
+        let mut path = solver.path_solver();
+
+        path.enter(&[less.clone(), greater.clone()]);
+        assert!(path.feasible());
+        assert_eq!(path.check_sat(&x().equal(n(7))), SatResult::Sat);
+
+        path.enter(&[less.clone(), equal.clone()]);
+        assert!(path.feasible());
+        assert_eq!(path.check_sat(&greater), SatResult::Unsat);
+
+        path.enter(&[greater.clone(), equal]);
+        assert!(!path.feasible());
+
+        path.enter(&[]);
+        assert_eq!(path.check_sat(&greater.and(less)), SatResult::Sat);
+    }
+
+    #[test]
+    fn timeout() {
+        use std::time::Duration;
+
+        // Proving that the prime 2^62 - 57 has no factors is hard for the
+        // solver. Depending on the machine, it gives up or finds the proof.
+        let a = Sym(Symbol(N32, "stdin", 0)).cast(N64, false);
+        let b = Sym(Symbol(N32, "stdin", 1)).cast(N64, false);
+        let hard = a.clone().mul(b.clone()).equal(n(0x3fff_ffff_ffff_ffc7))
+            .and(a.greater_than(n(1), false))
+            .and(b.greater_than(n(1), false));
+
+        for config in solver_configs() {
+            let solver = Solver::with_config(&config.parse().unwrap())
+                .with_timeout(Duration::from_millis(50));
+
+            assert_ne!(solver.check_sat(&hard), SatResult::Sat);
+            let simplified = solver.simplify_condition(&hard);
+            assert!(simplified == hard || simplified == SymCondition::FALSE);
+            assert!(solver.get_model(&hard).is_none());
+            assert_eq!(solver.check_sat(&x().equal(n(7))), SatResult::Sat);
+
+            // Giving up right away is not mistaken for a proof.
+            let solver = Solver::with_config(&config.parse().unwrap())
+                .with_timeout(Duration::from_micros(1));
+            assert_ne!(solver.check_sat(&hard), SatResult::Unsat);
+            assert_eq!(solver.simplify_condition(&hard), hard);
+        }
+
+        // Conditions that are false by simplification are still decided.
+        let solver = Solver::new().with_timeout(Duration::from_micros(1));
+        assert_eq!(solver.check_sat(&x().equal(x().add(n(1)))), SatResult::Unsat);
+    }
+
+    #[test]
+    fn model() {
+        let solver = Solver::new();
+
+        let a = Sym(Symbol(N8, "stdin", 0));
+        let b = Sym(Symbol(N8, "stdin", 2));
+        let condition = a.clone().sub(b.clone()).equal(Int(Integer(N8, 64)))
+            .and(b.equal(Int(Integer(N8, 'a' as u64))));
+
+        let model = solver.get_model(&condition).unwrap();
+        assert!(condition.evaluate(&|symbol| model.get(&symbol).copied()));
+        assert_eq!(stdin_from_model(&model), b"\xa1\0a");
+
+        let size = Sym(Symbol(N64, "size", 0)).equal(Int(Integer::from_ptr(1)));
+        let model = solver.get_model(&condition.clone().and(size)).unwrap();
+        assert_eq!(stdin_from_model(&model), b"\xa1");
+
+        assert!(solver.get_model(&a.clone().equal(a.bitnot())).is_none());
+    }
 }
@@ -140,6 +140,18 @@ impl Integer {
     cmp_maybe_signed!(greater_than, gt);
     cmp_maybe_signed!(greater_equal, ge);
 
+    /// Extract the bits from `hi` down to `lo` (both inclusive).
+    pub fn extract(self, hi: usize, lo: usize) -> Integer {
+        let new = extract_type(self.0, hi, lo);
+        Integer(self.0, self.1 >> lo).cast(new, false)
+    }
+
+    /// Concatenate this integer as the high part with the low part.
+    pub fn concat(self, low: Integer) -> Integer {
+        let new = concat_type(self.0, low.0);
+        Integer(new, (self.1 << low.0.bits()) | low.1)
+    }
+
     /// Cast the integer to another type.
     /// - If the target type is smaller, it will get truncated.
     /// - If the target type is bigger, if signed is true the value will be
@@ -205,6 +217,33 @@ impl DataType {
     pub fn bits(&self) -> usize {
         self.bytes() * 8
     }
+
+    /// The data type with the given number of bits if there is one.
+    pub fn from_bits(bits: usize) -> Option<DataType> {
+        match bits {
+            8 => Some(N8),
+            16 => Some(N16),
+            32 => Some(N32),
+            64 => Some(N64),
+            _ => None,
+        }
+    }
+}
+
+/// The data type of the bits from `hi` down to `lo` of a value.
+pub(crate) fn extract_type(data_type: DataType, hi: usize, lo: usize) -> DataType {
+    assert!(lo <= hi && hi < data_type.bits(), "extract: bits {}..{} out of bounds", hi, lo);
+    DataType::from_bits(hi - lo + 1).unwrap_or_else(|| {
+        panic!("extract: no data type with {} bits", hi - lo + 1);
+    })
+}
+
+/// The data type of two concatenated values.
+pub(crate) fn concat_type(high: DataType, low: DataType) -> DataType {
+    let bits = high.bits() + low.bits();
+    DataType::from_bits(bits).unwrap_or_else(|| {
+        panic!("concat: no data type with {} bits", bits);
+    })
 }
 
 impl Display for DataType {
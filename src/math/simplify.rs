@@ -0,0 +1,262 @@
+//! Native algebraic simplification.
+
+use super::{SymExpr, SymCondition, Integer, DataType, node};
+use SymExpr::*;
+use SymCondition::*;
+
+
+impl SymExpr {
+    /// Simplify the expression with algebraic rewrite rules.
+    ///
+    /// Sums, differences and products with constants are normalized into a
+    /// constant plus a sum of scaled terms, so equal linear expressions end up
+    /// in the same form.
+    pub fn simplify(&self) -> SymExpr {
+        match self {
+            Int(_) | Sym(_) => self.clone(),
+
+            Add(..) | Sub(..) | Mul(..) => {
+                let mut linear = Linear::new(self.data_type());
+                linear.collect(self, Integer(self.data_type(), 1));
+                linear.into_expr()
+            },
+
+            BitAnd(a, b) => match (a.simplify(), b.simplify()) {
+                (Int(a), Int(b)) => Int(a.bitand(b)),
+                (Int(zero), _) | (_, Int(zero)) if zero.1 == 0 => Int(zero),
+                (Int(ones), x) | (x, Int(ones)) if ones == all_ones(ones.0) => x,
+                (a, b) if a == b => a,
+                (a, b) => BitAnd(node(a), node(b)),
+            },
+
+            BitOr(a, b) => match (a.simplify(), b.simplify()) {
+                (Int(a), Int(b)) => Int(a.bitor(b)),
+                (Int(zero), x) | (x, Int(zero)) if zero.1 == 0 => x,
+                (Int(ones), _) | (_, Int(ones)) if ones == all_ones(ones.0) => Int(ones),
+                (a, b) if a == b => a,
+                (a, b) => BitOr(node(a), node(b)),
+            },
+
+            BitNot(a) => match a.simplify() {
+                BitNot(x) => x.into_inner(),
+                x => x.bitnot(),
+            },
+
+            Cast(x, new, signed) => match x.simplify() {
+                // Truncating an extended value either yields the original value
+                // or a smaller extension of it.
+                Cast(inner, wide, inner_signed) if new.bytes() < wide.bytes()
+                    && inner.data_type().bytes() < wide.bytes() => {
+                    inner.into_inner().cast(*new, inner_signed)
+                },
+                x => x.cast(*new, *signed),
+            },
+
+            Extract(x, hi, lo) => x.simplify().extract(*hi, *lo),
+            Concat(a, b) => a.simplify().concat(b.simplify()),
+
+            AsExpr(c, data_type) => c.simplify().as_expr(*data_type),
+
+            IfThenElse(c, a, b) => {
+                let (a, b) = (a.simplify(), b.simplify());
+                match c.simplify() {
+                    Bool(true) => a,
+                    Bool(false) => b,
+                    _ if a == b => a,
+                    c => match (a, b) {
+                        (Int(Integer(data_type, 1)), Int(Integer(_, 0))) => c.as_expr(data_type),
+                        (a, b) => c.if_then_else(a, b),
+                    },
+                }
+            },
+
+            Select(a, x) => a.select(x.simplify()),
+        }
+    }
+}
+
+impl SymCondition {
+    /// Simplify the condition with algebraic rewrite rules.
+    ///
+    /// Comparisons of equal linear expressions are decided, negations are
+    /// pushed into comparisons and boolean absorption is applied.
+    pub fn simplify(&self) -> SymCondition {
+        match self {
+            Bool(_) => self.clone(),
+
+            And(a, b) => match (a.simplify(), b.simplify()) {
+                (a, b) if a == b => a,
+                (a, b) if a == negated(b.clone()) => Bool(false),
+                (a, Or(x, y)) | (Or(x, y), a) if *x == a || *y == a => a,
+                (a, b) => a.and(b),
+            },
+
+            Or(a, b) => match (a.simplify(), b.simplify()) {
+                (a, b) if a == b => a,
+                (a, b) if a == negated(b.clone()) => Bool(true),
+                (a, And(x, y)) | (And(x, y), a) if *x == a || *y == a => a,
+                (a, b) => a.or(b),
+            },
+
+            Not(a) => negated(a.simplify()),
+
+            Equal(a, b) => {
+                let (a, b) = (a.simplify(), b.simplify());
+                match difference(&a, &b) {
+                    Some(diff) => Bool(diff.1 == 0),
+                    None => a.equal(b),
+                }
+            },
+
+            LessThan(a, b, s) => compare(a, b, false, |a, b| a.less_than(b, *s)),
+            LessEqual(a, b, s) => compare(a, b, true, |a, b| a.less_equal(b, *s)),
+            GreaterThan(a, b, s) => compare(a, b, false, |a, b| a.greater_than(b, *s)),
+            GreaterEqual(a, b, s) => compare(a, b, true, |a, b| a.greater_equal(b, *s)),
+        }
+    }
+}
+
+/// Negate an already simplified condition, flipping comparisons instead of
+/// wrapping them.
+fn negated(cond: SymCondition) -> SymCondition {
+    match cond {
+        Not(x) => x.into_inner(),
+        LessThan(a, b, s) => GreaterEqual(a, b, s),
+        LessEqual(a, b, s) => GreaterThan(a, b, s),
+        GreaterThan(a, b, s) => LessEqual(a, b, s),
+        GreaterEqual(a, b, s) => LessThan(a, b, s),
+        x => x.not(),
+    }
+}
+
+/// Simplify a comparison, deciding it if both sides are the same.
+fn compare<F>(a: &SymExpr, b: &SymExpr, reflexive: bool, cmp: F) -> SymCondition
+where F: FnOnce(SymExpr, SymExpr) -> SymCondition {
+    let (a, b) = (a.simplify(), b.simplify());
+    if a == b {
+        Bool(reflexive)
+    } else {
+        cmp(a, b)
+    }
+}
+
+/// The constant difference of two expressions if the symbolic parts cancel out.
+fn difference(a: &SymExpr, b: &SymExpr) -> Option<Integer> {
+    let data_type = a.data_type();
+    let mut linear = Linear::new(data_type);
+    linear.collect(a, Integer(data_type, 1));
+    linear.collect(b, negate(Integer(data_type, 1)));
+    linear.prune();
+
+    if linear.terms.is_empty() {
+        Some(linear.constant)
+    } else {
+        None
+    }
+}
+
+/// Whether the expression only consists of symbols, constants and the
+/// linear operations the native simplifier normalizes completely.
+pub(super) fn is_linear(expr: &SymExpr) -> bool {
+    match expr {
+        Int(_) | Sym(_) => true,
+        Add(a, b) | Sub(a, b) => is_linear(a) && is_linear(b),
+        Mul(a, b) => match (&**a, &**b) {
+            (Int(_), x) | (x, Int(_)) => is_linear(x),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// A linear combination of terms with a constant offset.
+struct Linear {
+    constant: Integer,
+    terms: Vec<(SymExpr, Integer)>,
+}
+
+impl Linear {
+    fn new(data_type: DataType) -> Linear {
+        Linear {
+            constant: Integer(data_type, 0),
+            terms: Vec::new(),
+        }
+    }
+
+    /// Add `factor * expr` to the combination.
+    fn collect(&mut self, expr: &SymExpr, factor: Integer) {
+        match expr {
+            Int(x) => self.constant = self.constant.add(x.mul(factor)),
+            Sym(_) => self.add_term(expr.clone(), factor),
+
+            Add(a, b) => {
+                self.collect(a, factor);
+                self.collect(b, factor);
+            },
+
+            Sub(a, b) => {
+                self.collect(a, factor);
+                self.collect(b, negate(factor));
+            },
+
+            Mul(a, b) => match (a.simplify(), b.simplify()) {
+                (Int(k), x) | (x, Int(k)) => self.collect(&x, factor.mul(k)),
+                (a, b) => self.add_term(Mul(node(a), node(b)), factor),
+            },
+
+            _ => match expr.simplify() {
+                simplified @ Int(_) | simplified @ Add(..)
+                | simplified @ Sub(..) | simplified @ Mul(..) => self.collect(&simplified, factor),
+                simplified => self.add_term(simplified, factor),
+            },
+        }
+    }
+
+    /// Add `factor * term` for a term that is not further decomposed.
+    fn add_term(&mut self, term: SymExpr, factor: Integer) {
+        match self.terms.iter_mut().find(|(t, _)| *t == term) {
+            Some((_, coefficient)) => *coefficient = coefficient.add(factor),
+            None => self.terms.push((term, factor)),
+        }
+    }
+
+    /// Remove the terms that cancelled out.
+    fn prune(&mut self) {
+        self.terms.retain(|(_, coefficient)| coefficient.1 != 0);
+    }
+
+    /// Build the normalized expression: The constant comes first, followed by
+    /// the terms in the order they first appeared. Negative coefficients are
+    /// turned into subtractions.
+    fn into_expr(mut self) -> SymExpr {
+        self.prune();
+
+        let mut expr = if self.constant.1 != 0 { Some(Int(self.constant)) } else { None };
+
+        for (term, coefficient) in self.terms {
+            let negative = coefficient.less_than(Integer(coefficient.0, 0), true);
+            let magnitude = if negative { negate(coefficient) } else { coefficient };
+            let scaled = |value: Integer| {
+                if value.1 == 1 { term.clone() } else { Mul(node(Int(value)), node(term.clone())) }
+            };
+
+            expr = Some(match expr {
+                Some(expr) if negative => Sub(node(expr), node(scaled(magnitude))),
+                Some(expr) => Add(node(expr), node(scaled(magnitude))),
+                None => scaled(coefficient),
+            });
+        }
+
+        expr.unwrap_or(Int(self.constant))
+    }
+}
+
+/// The two's complement negation of an integer.
+fn negate(x: Integer) -> Integer {
+    Integer(x.0, 0).sub(x)
+}
+
+/// The integer with all bits set.
+fn all_ones(data_type: DataType) -> Integer {
+    Integer(data_type, 0).bitnot()
+}
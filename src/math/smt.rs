@@ -1,33 +1,132 @@
 //! Functionality for using the Z3 SMT solver.
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt::{self, Display, Debug, Formatter};
+use std::time::Duration;
 use z3::Context as Z3Context;
 use z3::ast::Ast;
 
-use super::{SymExpr, SymCondition, SymDynamic, Symbol, Integer, DataType};
+use super::{SymExpr, SymCondition, SymArray, SymDynamic, Traversed, Symbol, Integer, DataType};
+use super::{numbered_space, array_name, array_space, SolverConfig, CachedBackend};
+use super::simplify;
 use SymExpr::*;
 use SymCondition::*;
 use DataType::*;
 
 
-/// Solves and simplifies conditions and expressions using Z3.
+/// Solves and simplifies conditions and expressions using a solver backend.
 pub struct Solver {
-    ctx: Z3Context,
+    backend: Box<dyn SolverBackend>,
+    /// The number of queries handed to the backend so far.
+    queries: Cell<usize>,
 }
 
 /// A reference-counted condition solver.
 pub type SharedSolver = std::rc::Rc<Solver>;
 
+/// The result of a satisfiability check.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SatResult {
+    Sat,
+    Unsat,
+    /// The solver gave up, e.g. because the query timed out.
+    Unknown,
+}
+
+impl SatResult {
+    /// Whether the condition may be satisfiable, treating unknown results
+    /// conservatively.
+    pub fn possible(self) -> bool {
+        self != SatResult::Unsat
+    }
+}
+
+/// The decision procedures a solver needs to provide.
+pub trait SolverBackend {
+    /// Simplify an expression.
+    fn simplify_expr(&self, expr: &SymExpr) -> SymExpr;
+
+    /// Simplify a condition, returning `false` if and only if it is
+    /// unsatisfiable.
+    fn simplify_condition(&self, cond: &SymCondition) -> SymCondition;
+
+    /// Check whether the condition is satisfiable.
+    fn check_sat(&self, cond: &SymCondition) -> SatResult;
+
+    /// Find values for all symbols in the condition such that it is satisfied.
+    ///
+    /// `None` only proves unsatisfiability for backends that decide every
+    /// condition, whether it is unsatisfiable is up to `check_sat`.
+    fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>>;
+
+    /// Limit the time a single query may take. Queries running into the
+    /// limit have an unknown result.
+    fn set_timeout(&mut self, timeout: Option<Duration>);
+
+    /// Create a solver for incrementally asserting path constraints.
+    fn path_backend(&self) -> Box<dyn PathBackend + '_>;
+
+    /// A short name of the backend for debugging purposes.
+    fn name(&self) -> String;
+}
+
+/// Keeps a stack of asserted constraints that queries are checked against.
+pub trait PathBackend {
+    /// Open a new scope asserting the condition.
+    fn push(&mut self, cond: &SymCondition);
+
+    /// Close the last scope, retracting its condition.
+    fn pop(&mut self);
+
+    /// Check whether the condition is satisfiable together with all
+    /// asserted constraints.
+    fn check_sat(&mut self, cond: &SymCondition) -> SatResult;
+}
+
 impl Solver {
-    /// Create a new condition solver with it's own Z3 context.
+    /// Create a new condition solver with the backend configured through the
+    /// `SYMFLOW_SOLVER` environment variable (Z3 if it is not set).
+    ///
+    /// A per-query timeout in milliseconds can be set through
+    /// `SYMFLOW_SOLVER_TIMEOUT`.
     pub fn new() -> Solver {
-        let config = z3::Config::new();
-        let ctx = Z3Context::new(&config);
-        Solver { ctx }
+        let solver = Solver::with_config(&SolverConfig::from_env());
+        match std::env::var("SYMFLOW_SOLVER_TIMEOUT") {
+            Ok(value) => {
+                let millis = value.parse().unwrap_or_else(|_| {
+                    panic!("invalid solver timeout: {:?}", value);
+                });
+                solver.with_timeout(Duration::from_millis(millis))
+            },
+            Err(_) => solver,
+        }
+    }
+
+    /// Create a new condition solver with the configured backend.
+    ///
+    /// The results of the backend are cached.
+    pub fn with_config(config: &SolverConfig) -> Solver {
+        Solver::with_backend(Box::new(CachedBackend::new(config.backend())))
+    }
+
+    /// Create a new condition solver with a custom backend.
+    pub fn with_backend(backend: Box<dyn SolverBackend>) -> Solver {
+        Solver { backend, queries: Cell::new(0) }
+    }
+
+    /// Limit the time a single query may take. With the Z3 library, this
+    /// means that unsatisfiable conditions are only recognized as such if
+    /// simplification reduces them to false, others are unknown.
+    pub fn with_timeout(mut self, timeout: Duration) -> Solver {
+        self.backend.set_timeout(Some(timeout));
+        self
     }
 
     /// Simplify an expression.
+    ///
+    /// The expression is first simplified natively and only handed to the
+    /// backend if that leaves more than a linear combination of symbols.
     pub fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
         // No need to symplify atomic expressions.
         match expr {
@@ -36,77 +135,364 @@ impl Solver {
             _ => {},
         }
 
-        crate::timings::start("simplify");
-
-        let z3_expr = expr.to_z3_ast(&self.ctx);
-        let params = self.params();
-        let z3_simplified = z3_expr.simplify_ex(&params).simplify_ex(&params);
-
-        let res = SymExpr::from_z3_ast(&z3_simplified).unwrap_or_else(|err| {
-            panic!("condition solver: failed to simplify expression: {}\n{}", expr, err);
-        });
+        let simplified = crate::timings::with("simplify-native", || expr.simplify());
+        if simplify::is_linear(&simplified) {
+            return simplified;
+        }
 
-        crate::timings::stop();
-        res
+        self.count_query();
+        crate::timings::with("simplify", || self.backend.simplify_expr(&simplified))
     }
 
     /// Simplify a condition.
+    ///
+    /// The condition is first simplified natively and only handed to the
+    /// backend if that does not decide it.
     pub fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
         // Just a bool has no need to be simplified.
         if let Bool(x) = cond {
             return Bool(*x);
         }
 
-        crate::timings::start("simplify");
+        match crate::timings::with("simplify-native", || cond.simplify()) {
+            Bool(x) => Bool(x),
+            simplified => crate::timings::with("simplify", || {
+                self.count_query();
+                self.backend.simplify_condition(&simplified)
+            }),
+        }
+    }
+
+    /// Check whether a condition is satisfiable.
+    pub fn check_sat(&self, cond: &SymCondition) -> SatResult {
+        match cond {
+            Bool(true) => SatResult::Sat,
+            Bool(false) => SatResult::Unsat,
+            _ => match crate::timings::with("simplify-native", || cond.simplify()) {
+                Bool(true) => SatResult::Sat,
+                Bool(false) => SatResult::Unsat,
+                simplified => crate::timings::with("check-sat", || {
+                    self.count_query();
+                    self.backend.check_sat(&simplified)
+                }),
+            },
+        }
+    }
+
+    /// Check whether two expressions can be equal.
+    pub fn check_equal_sat(&self, a: &SymExpr, b: &SymExpr) -> SatResult {
+        crate::timings::with("check-equal-sat", || {
+            self.check_sat(&a.clone().equal(b.clone()))
+        })
+    }
+
+    /// Find values for all symbols in the condition such that it is satisfied.
+    ///
+    /// Returns `None` if the condition is unsatisfiable or the solver could
+    /// not decide it.
+    pub fn get_model(&self, condition: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+        self.count_query();
+        crate::timings::with("get-model", || self.backend.get_model(condition))
+    }
+
+    /// Create a solver which keeps the preconditions of a path asserted.
+    pub fn path_solver(&self) -> PathSolver<'_> {
+        PathSolver {
+            backend: self.backend.path_backend(),
+            path: Vec::new(),
+            queries: &self.queries,
+        }
+    }
+
+    /// The number of queries that were handed to the backend, including
+    /// those of path solvers created from this one. Queries decided by
+    /// native simplification are not counted.
+    pub fn queries(&self) -> usize {
+        self.queries.get()
+    }
+
+    fn count_query(&self) {
+        self.queries.set(self.queries.get() + 1);
+    }
+}
+
+impl Debug for Solver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Solver({})", self.backend.name())
+    }
+}
+
+/// Checks conditions against the preconditions of the explored path.
+///
+/// The preconditions are asserted once when the path is entered and are
+/// popped again when exploration backtracks to a shorter path.
+pub struct PathSolver<'a> {
+    backend: Box<dyn PathBackend + 'a>,
+    path: Vec<SymCondition>,
+    queries: &'a Cell<usize>,
+}
+
+impl PathSolver<'_> {
+    /// Make the preconditions the active path, keeping the longest common
+    /// prefix with the previous path asserted.
+    pub fn enter(&mut self, preconditions: &[SymCondition]) {
+        let common = self.path.iter().zip(preconditions)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        for _ in common .. self.path.len() {
+            self.backend.pop();
+        }
+        self.path.truncate(common);
+
+        for pre in &preconditions[common ..] {
+            self.backend.push(pre);
+            self.path.push(pre.clone());
+        }
+    }
+
+    /// Whether the active path can possibly be taken.
+    pub fn feasible(&mut self) -> bool {
+        self.check_sat(&SymCondition::TRUE).possible()
+    }
+
+    /// Check whether a condition is satisfiable on the active path.
+    pub fn check_sat(&mut self, cond: &SymCondition) -> SatResult {
+        if *cond == SymCondition::FALSE {
+            return SatResult::Unsat;
+        }
+
+        self.queries.set(self.queries.get() + 1);
+        crate::timings::with("check-sat-path", || self.backend.check_sat(cond))
+    }
+}
+
+impl Debug for PathSolver<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "PathSolver({} constraints)", self.path.len())
+    }
+}
+
+/// Solves conditions in-process with the Z3 library.
+pub struct Z3Backend {
+    ctx: Z3Context,
+    timeout: Option<Duration>,
+}
+
+impl Z3Backend {
+    /// Create a new backend with it's own Z3 context.
+    pub fn new() -> Z3Backend {
+        let config = z3::Config::new();
+        let ctx = Z3Context::new(&config);
+        Z3Backend { ctx, timeout: None }
+    }
+
+    /// Run the solver on the asserted condition, detecting whether it gave
+    /// up because of the timeout.
+    fn check(&self, solver: &z3::Solver, cond: &SymCondition) -> SatResult {
+        check_timed(self.timeout, || solver.check(), || refuted(&self.ctx, cond))
+    }
+
+    /// Builds the default simplifaction params.
+    fn params(&self) -> z3::Params {
+        let mut params = z3::Params::new(&self.ctx);
+        params.set_bool("elim_sign_ext", false);
+        params
+    }
+}
+
+impl SolverBackend for Z3Backend {
+    fn simplify_expr(&self, expr: &SymExpr) -> SymExpr {
+        let z3_expr = expr.to_z3_ast(&self.ctx);
+        let params = self.params();
+        let z3_simplified = z3_expr.simplify_ex(&params).simplify_ex(&params);
+
+        SymExpr::from_z3_ast(&z3_simplified).unwrap_or_else(|err| {
+            panic!("condition solver: failed to simplify expression: {}\n{}", expr, err);
+        })
+    }
 
+    fn simplify_condition(&self, cond: &SymCondition) -> SymCondition {
         let z3_cond = cond.to_z3_ast(&self.ctx);
         let solver = z3::Solver::new(&self.ctx);
 
         crate::timings::start("check-sat");
         solver.assert(&z3_cond);
-        let sat = solver.check();
+        let sat = self.check(&solver, cond);
         crate::timings::stop();
 
-        let res = if sat {
-            let params = self.params();
-            let z3_simplified = z3_cond.simplify_ex(&params).simplify_ex(&params);
+        match sat {
+            SatResult::Sat => {
+                let params = self.params();
+                let z3_simplified = z3_cond.simplify_ex(&params).simplify_ex(&params);
 
-            SymCondition::from_z3_ast(&z3_simplified).unwrap_or_else(|err| {
-                panic!("warning: condition solver: failed to simplify condition: {}\n{}", cond, err);
-            })
-        } else {
-            SymCondition::FALSE
-        };
+                SymCondition::from_z3_ast(&z3_simplified).unwrap_or_else(|err| {
+                    panic!("warning: condition solver: failed to simplify condition: {}\n{}", cond, err);
+                })
+            },
+            SatResult::Unsat => SymCondition::FALSE,
+            // Simplification is only trusted for decided conditions.
+            SatResult::Unknown => cond.clone(),
+        }
+    }
 
-        crate::timings::stop();
-        res
+    fn check_sat(&self, cond: &SymCondition) -> SatResult {
+        let solver = z3::Solver::new(&self.ctx);
+        solver.assert(&cond.to_z3_ast(&self.ctx));
+        self.check(&solver, cond)
     }
 
-    /// Check whether two expressions are possibly equal.
-    pub fn check_equal_sat(&self, a: &SymExpr, b: &SymExpr) -> bool {
-        crate::timings::with("check-equal-sat", || {
-        crate::timings::with("check-sat", || {
-            let z3_a = a.to_z3_ast(&self.ctx);
-            let z3_b = b.to_z3_ast(&self.ctx);
-            let solver = z3::Solver::new(&self.ctx);
-            solver.assert(&z3_a._eq(&z3_b));
-            solver.check()
-        })
+    fn get_model(&self, cond: &SymCondition) -> Option<HashMap<Symbol, Integer>> {
+        let solver = z3::Solver::new(&self.ctx);
+        solver.assert(&cond.to_z3_ast(&self.ctx));
+
+        if crate::timings::with("check-sat", || self.check(&solver, cond)) != SatResult::Sat {
+            return None;
+        }
+
+        let model = solver.get_model();
+        let mut values = HashMap::new();
+        for symbol in cond.symbols() {
+            let z3_sym = Sym(symbol).to_z3_ast(&self.ctx);
+            let value = model.eval(&z3_sym)
+                .and_then(|value| value.as_u64())
+                .unwrap_or_else(|| {
+                    panic!("condition solver: model has no value for {}", symbol);
+                });
+            values.insert(symbol, Integer(symbol.0, value));
+        }
+
+        // The selected bytes of base arrays are the symbols of their array
+        // spaces indexed by address.
+        let mut selects = Vec::new();
+        cond.traverse(&mut |node| {
+            if let Traversed::Expr(Select(array, addr)) = node {
+                selects.push((array.name(), addr.clone()));
+            }
+        });
+
+        for (name, addr) in selects {
+            let index = model.eval(&addr.to_z3_ast(&self.ctx)).and_then(|index| index.as_u64());
+            if let Some(index) = index {
+                let byte = SymArray::Base(name).select(SymExpr::from_ptr(index));
+                if let Some(value) = model.eval(&byte.to_z3_ast(&self.ctx)).and_then(|v| v.as_u64()) {
+                    values.insert(Symbol(N8, array_space(name), index as usize), Integer(N8, value));
+                }
+            }
+        }
+
+        Some(values)
+    }
+
+    fn path_backend(&self) -> Box<dyn PathBackend + '_> {
+        Box::new(Z3PathBackend {
+            ctx: &self.ctx,
+            solver: z3::Solver::new(&self.ctx),
+            path: Vec::new(),
+            timeout: self.timeout,
         })
     }
 
-    /// Builds the default simplifaction params.
-    fn params(&self) -> z3::Params {
-        let mut params = z3::Params::new(&self.ctx);
-        params.set_bool("elim_sign_ext", false);
-        params
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        let mut config = z3::Config::new();
+        if let Some(timeout) = timeout {
+            // Z3 counts in whole milliseconds and zero means no timeout.
+            config.set_timeout_msec((timeout.as_millis() as u64).max(1));
+        }
+        self.ctx = Z3Context::new(&config);
+        self.timeout = timeout;
+    }
+
+    fn name(&self) -> String {
+        "z3".to_string()
     }
 }
 
-impl Debug for Solver {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Solver")
+/// Asserts path constraints into a single incremental Z3 solver.
+struct Z3PathBackend<'ctx> {
+    ctx: &'ctx Z3Context,
+    solver: z3::Solver<'ctx>,
+    /// The asserted constraints.
+    path: Vec<SymCondition>,
+    timeout: Option<Duration>,
+}
+
+impl PathBackend for Z3PathBackend<'_> {
+    fn push(&mut self, cond: &SymCondition) {
+        self.solver.push();
+        self.solver.assert(&cond.to_z3_ast(self.ctx));
+        self.path.push(cond.clone());
+    }
+
+    fn pop(&mut self) {
+        self.solver.pop(1);
+        self.path.pop();
+    }
+
+    fn check_sat(&mut self, cond: &SymCondition) -> SatResult {
+        let solver = &self.solver;
+        let all = || self.path.iter().fold(cond.clone(), |all, c| all.and(c.clone()));
+        if *cond == SymCondition::TRUE {
+            return check_timed(self.timeout, || solver.check(), || refuted(self.ctx, &all()));
+        }
+
+        solver.push();
+        solver.assert(&cond.to_z3_ast(self.ctx));
+        let sat = check_timed(self.timeout, || solver.check(), || refuted(self.ctx, &all()));
+        solver.pop(1);
+        sat
+    }
+}
+
+/// Turn the boolean result of a solver check into a three-valued one.
+///
+/// Z3 only reports whether the assertions are satisfiable, so with a timeout
+/// a negative answer may also mean that it gave up, no matter how fast. It is
+/// then only trusted if `refute` shows the assertions to be unsatisfiable
+/// without the solver.
+fn check_timed<F, R>(timeout: Option<Duration>, check: F, refute: R) -> SatResult
+where F: FnOnce() -> bool, R: FnOnce() -> bool {
+    if check() {
+        SatResult::Sat
+    } else if timeout.is_none() || refute() {
+        SatResult::Unsat
+    } else {
+        crate::timings::count("solver-timeout");
+        SatResult::Unknown
+    }
+}
+
+/// Whether simplification alone, which does not search and thus needs no
+/// timeout, reduces the condition to false.
+fn refuted(ctx: &Z3Context, cond: &SymCondition) -> bool {
+    cond.to_z3_ast(ctx).simplify().as_bool() == Some(false)
+}
+
+/// The largest input built from a model.
+pub const MAX_MODEL_INPUT: u64 = 1 << 16;
+
+/// Build the standard input bytes described by a model.
+///
+/// The input is just long enough to contain all `stdin` symbols of the model
+/// unless the model fixes the size of the input, which is capped at
+/// `MAX_MODEL_INPUT` bytes. Bytes that are not part of the model are zero.
+pub fn stdin_from_model(model: &HashMap<Symbol, Integer>) -> Vec<u8> {
+    let mut stdin = Vec::new();
+    for (symbol, value) in model {
+        if let Symbol(N8, "stdin", index) = *symbol {
+            if index >= stdin.len() {
+                stdin.resize(index + 1, 0);
+            }
+            stdin[index] = value.1 as u8;
+        }
+    }
+
+    // The input has exactly the size from the model if it has one.
+    if let Some(size) = model.get(&Symbol(N64, "size", 0)) {
+        stdin.resize(size.1.min(MAX_MODEL_INPUT) as usize, 0);
     }
+
+    stdin
 }
 
 /// Parses Z3 string representations.
@@ -139,6 +525,88 @@ impl<'a> Z3Parser<'a> {
             .map_err(|message| FromAstError::new(self.ast, self.index(), message))
     }
 
+    /// Parse an SMT-LIB2 script into the conjunction of its assertions.
+    pub fn parse_script(&mut self) -> Result<SymCondition, FromAstError> {
+        self.parse_commands()
+            .map_err(|message| FromAstError::new(self.ast, self.index(), message))
+    }
+
+    /// Parse the response to an SMT-LIB2 `get-value` command for symbols.
+    pub fn parse_values(&mut self) -> Result<Vec<(Symbol, Integer)>, FromAstError> {
+        self.parse_value_pairs()
+            .map_err(|message| FromAstError::new(self.ast, self.index(), message))
+    }
+
+    /// Parse a list of symbol-value pairs.
+    fn parse_value_pairs(&mut self) -> ParseResult<Vec<(Symbol, Integer)>> {
+        let mut values = Vec::new();
+
+        self.skip_white();
+        self.expect('(')?;
+        self.skip_white();
+
+        while self.peek() == Some('(') {
+            self.expect('(')?;
+            self.skip_white();
+            let symbol = match self.parse_bv_symbol()? {
+                Sym(symbol) => symbol,
+                _ => return err("expected symbol in value pair"),
+            };
+            let value = match self.parse_bitvec()? {
+                Int(value) => value,
+                _ => return err("expected immediate in value pair"),
+            };
+            self.skip_white();
+            self.expect(')')?;
+            self.skip_white();
+            values.push((symbol, value));
+        }
+
+        self.expect(')')?;
+        Ok(values)
+    }
+
+    /// Parse script commands, skipping everything except assertions.
+    fn parse_commands(&mut self) -> ParseResult<SymCondition> {
+        let mut condition = SymCondition::TRUE;
+
+        loop {
+            self.skip_white();
+            match self.peek() {
+                Some(';') => { self.parse_word_while(|c| c != '\n'); },
+                Some(_) => {
+                    self.expect('(')?;
+                    match self.parse_ident() {
+                        "assert" => {
+                            condition = condition.and(self.parse_bool()?);
+                            self.skip_white();
+                            self.expect(')')?;
+                        },
+                        _ => self.skip_until_closed()?,
+                    }
+                },
+                None => break,
+            }
+        }
+
+        Ok(condition)
+    }
+
+    /// Skip everything until the currently open parenthesis is closed.
+    fn skip_until_closed(&mut self) -> ParseResult<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some('(') => depth += 1,
+                Some(')') => depth -= 1,
+                Some('|') => { self.parse_word_while(|c| c != '|'); self.expect('|')?; },
+                Some(_) => {},
+                None => return err("unclosed parenthesis"),
+            }
+        }
+        Ok(())
+    }
+
     /// Parse a bitvector expression.
     fn parse_bitvec(&mut self) -> ParseResult<SymExpr> {
         self.skip_white();
@@ -158,8 +626,7 @@ impl<'a> Z3Parser<'a> {
             Some('|') => self.parse_bv_symbol(),
             Some(_) => match self.parse_variable()? {
                 SymDynamic::Expr(expr) => Ok(expr),
-                SymDynamic::Condition(_) => err("let-binding has wrong type,
-                    expected expression, found condition"),
+                _ => err("let-binding has wrong type, expected expression"),
             },
             p => return err(format!("expected expression while parsing bitvec, found {:?}", p)),
         }
@@ -185,6 +652,25 @@ impl<'a> Z3Parser<'a> {
 
                         bit_extend(bits, right, kind == "sign_extend")?
                     },
+                    "extract" => {
+                        self.skip_white();
+                        let high = self.parse_number()?;
+                        self.skip_white();
+                        let low = self.parse_number()?;
+
+                        self.expect(')')?;
+                        self.skip_white();
+                        let right = self.parse_bitvec()?;
+
+                        if low == 0 {
+                            bit_truncate(high + 1, right)?
+                        } else if low <= high && high < right.data_type().bits()
+                            && DataType::from_bits(high - low + 1).is_some() {
+                            right.extract(high, low)
+                        } else {
+                            return err(format!("unhandled extract: bits {}..{}", high, low));
+                        }
+                    },
                     _ => return err("unknown _ function kind"),
                 }
             },
@@ -198,22 +684,22 @@ impl<'a> Z3Parser<'a> {
 
             "ite" => {
                 let condition = self.parse_bool()?;
-                condition.if_then_else(self.parse_bitvec()?, self.parse_bitvec()?)
-            },
-
-            "concat" => {
-                self.skip_white();
-                if self.peek() != Some('#') {
-                    return err("can only use in concat with left-hand zero immediate");
+                match (self.parse_bitvec()?, self.parse_bitvec()?) {
+                    (Int(Integer(data_type, 1)), Int(Integer(_, 0))) => {
+                        condition.as_expr(data_type)
+                    },
+                    (a, b) => condition.if_then_else(a, b),
                 }
+            },
 
-                let (bits, value) = self.parse_bv_immediate()?;
-                if value != 0 {
-                    return err("unhandled concat: non-zero left-hand value");
+            "concat" => self.parse_concat()?,
+            "select" => {
+                let array = self.parse_array()?;
+                let addr = self.parse_bitvec()?;
+                if addr.data_type() != N64 {
+                    return err("unhandled select: address has to be 64-bit");
                 }
-
-                let right = self.parse_bitvec()?;
-                bit_extend(bits, right, false)?
+                array.select(addr)
             },
 
             _ => return err(format!("unknown bitvec function: {:?}", func)),
@@ -223,6 +709,89 @@ impl<'a> Z3Parser<'a> {
         Ok(expr)
     }
 
+    /// Parse the arguments of a bitvector concatenation.
+    fn parse_concat(&mut self) -> ParseResult<SymExpr> {
+        self.skip_white();
+
+        let mut extension = None;
+        let mut parts = Vec::new();
+        if self.peek() == Some('#') {
+            let (bits, value) = self.parse_bv_immediate()?;
+            match DataType::from_bits(bits) {
+                Some(data_type) => parts.push(Int(Integer(data_type, value))),
+                // Z3 expresses zero extensions as concatenations with zero
+                // immediates, which do not need to have a data type size.
+                None if value == 0 => extension = Some(bits),
+                None => return err("unhandled concat: non-zero left-hand value"),
+            }
+        }
+
+        // Z3 merges nested concatenations, so there may be more than two parts.
+        self.skip_white();
+        while self.peek() != Some(')') {
+            parts.push(self.parse_bitvec()?);
+            self.skip_white();
+        }
+
+        // Merge neighbours pairwise to keep all intermediate widths valid.
+        while parts.len() > 1 {
+            let mut merged = Vec::new();
+            for pair in parts.chunks(2) {
+                merged.push(match pair {
+                    [high, low] => {
+                        let bits = high.data_type().bits() + low.data_type().bits();
+                        if DataType::from_bits(bits).is_none() {
+                            return err(format!("unhandled concat: invalid target size {}", bits));
+                        }
+                        high.clone().concat(low.clone())
+                    },
+                    [part] => part.clone(),
+                    _ => unreachable!(),
+                });
+            }
+            parts = merged;
+        }
+
+        match (parts.pop(), extension) {
+            (Some(expr), Some(bits)) => bit_extend(bits, expr, false),
+            (Some(expr), None) => Ok(expr),
+            (None, _) => err("unhandled concat: no operands"),
+        }
+    }
+
+    /// Parse a byte array.
+    fn parse_array(&mut self) -> ParseResult<SymArray> {
+        self.skip_white();
+        if self.peek() != Some('(') {
+            // Unbound names are base arrays.
+            let var = self.parse_ident();
+            return match self.bindings.get(var) {
+                Some(SymDynamic::Array(array)) => Ok(array.clone()),
+                Some(_) => err(format!("expected array, found {:?}", var)),
+                None => Ok(SymArray::Base(array_name(var))),
+            };
+        }
+
+        self.expect('(')?;
+        let func = self.parse_func_name();
+        let array = match func {
+            "let" => { self.parse_let_bindings()?; self.parse_array()? },
+            "store" => {
+                let array = self.parse_array()?;
+                let addr = self.parse_bitvec()?;
+                let value = self.parse_bitvec()?;
+                if addr.data_type() != N64 || value.data_type() != N8 {
+                    return err("unhandled store: expected 64-bit address and byte");
+                }
+                array.store(addr, value)
+            },
+            _ => return err(format!("unknown array function: {:?}", func)),
+        };
+        self.skip_white();
+        self.expect(')')?;
+        Ok(array)
+    }
+
     /// Parse a bitvector function with variable number of arguments.
     fn parse_bv_varop<F>(&mut self, op: F) -> ParseResult<SymExpr>
     where F: Fn(SymExpr, SymExpr) -> SymExpr {
@@ -268,7 +837,22 @@ impl<'a> Z3Parser<'a> {
             "mem" => "mem",
             "reg" => "reg",
             "stdin" => "stdin",
+            "stdout" => "stdout",
+            "stderr" => "stderr",
+            "size" => "size",
             "T" => "T",
+            "auxv" => "auxv",
+            s @ "argv" | s @ "env" | s @ "filein" | s @ "fileout" => {
+                let number = self.parse_number()?;
+                self.expect('_')?;
+                let space = match s {
+                    "argv" => "argv",
+                    "env" => "env",
+                    "filein" => "filein",
+                    _ => "fileout",
+                };
+                numbered_space(space, number)
+            },
             s => return err(format!("invalid space name for symbol: {:?}", s)),
         };
 
@@ -298,8 +882,7 @@ impl<'a> Z3Parser<'a> {
             Some('(') => self.parse_bool_func(),
             Some(_) => match self.parse_variable()? {
                 SymDynamic::Condition(condition) => Ok(condition),
-                SymDynamic::Expr(_) => err("let-binding has wrong type,
-                    expected condition, found expression"),
+                _ => err("let-binding has wrong type, expected condition"),
             },
             p => return err(format!("expected expression while parsing bool, found {:?}", p)),
         }
@@ -349,7 +932,14 @@ impl<'a> Z3Parser<'a> {
                 Err(_) => {
                     self.active = active;
                     self.ast = ast;
-                    self.parse_bool()?.into()
+                    match self.parse_bool() {
+                        Ok(condition) => condition.into(),
+                        Err(_) => {
+                            self.active = active;
+                            self.ast = ast;
+                            self.parse_array()?.into()
+                        },
+                    }
                 },
             };
             self.bindings.insert(name.to_string(), value);
@@ -457,6 +1047,16 @@ fn bit_extend(bits: usize, right: SymExpr, signed: bool) -> ParseResult<SymExpr>
     }
 }
 
+/// Truncate `right` to its lowest `bits` bits.
+fn bit_truncate(bits: usize, right: SymExpr) -> ParseResult<SymExpr> {
+    match bits {
+        8 => Ok(right.cast(N8, false)),
+        16 => Ok(right.cast(N16, false)),
+        32 => Ok(right.cast(N32, false)),
+        s => err(format!("unhandled bit truncation: invalid target size {}", s)),
+    }
+}
+
 /// Fast way to make an error.
 fn err<T, S: Into<String>>(message: S) -> ParseResult<T> {
     Err(message.into())
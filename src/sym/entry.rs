@@ -0,0 +1,213 @@
+//! The process state at the program entry.
+
+use crate::concrete::STACK_BASE;
+use crate::flow::StorageLocation;
+use crate::math::{SymExpr, Integer, DataType, Symbol, numbered_space};
+use crate::x86_64::Register;
+use super::{SymState, Event, StdioKind, TypedMemoryAccess, Region};
+use DataType::*;
+
+/// The size of the stack region below the stack base.
+pub const STACK_SIZE: u64 = 0x80_0000;
+
+/// The auxiliary vector entry with the size of a page.
+pub const AT_PAGESZ: u64 = 6;
+
+
+/// Describes what the kernel puts on the stack before entering the program.
+///
+/// The argument and environment strings consist of symbolic bytes and are
+/// terminated by a null byte. The symbols of the argument with index `i` are
+/// in the space `argv{i}_`, those of environment strings in `env{i}_`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EntryState {
+    /// The address right above the stack.
+    pub stack_base: u64,
+    /// The lengths of the argument strings, including the program name.
+    pub args: Vec<usize>,
+    /// The lengths of the environment strings.
+    pub env: Vec<usize>,
+    /// The types and values of the auxiliary vector entries before the
+    /// terminating `AT_NULL` entry. Entries without a value are symbolic.
+    pub auxv: Vec<(u64, Option<u64>)>,
+}
+
+impl EntryState {
+    /// Create an entry state without arguments and environment at the
+    /// default stack base.
+    pub fn new() -> EntryState {
+        EntryState {
+            stack_base: STACK_BASE,
+            args: Vec::new(),
+            env: Vec::new(),
+            auxv: vec![(AT_PAGESZ, Some(0x1000))],
+        }
+    }
+}
+
+impl SymState {
+    /// Lay out the stack like the kernel does at the program entry.
+    ///
+    /// From the stack pointer upwards, the stack contains `argc`, the
+    /// null-terminated `argv` and `envp` pointer arrays, the auxiliary vector
+    /// and finally the strings. Returns the symbolic inputs as events, which
+    /// are located relative to the stack pointer at the current address.
+    ///
+    /// Panics if the layout does not fit below the stack base.
+    pub fn enter(&mut self, entry: &EntryState) -> Vec<Event> {
+        const OVERFLOW: &str = "enter: entry state does not fit below the stack base";
+
+        // The strings are right below the stack base.
+        let mut top = entry.stack_base;
+        let mut place = |lens: &[usize]| -> Vec<u64> {
+            lens.iter().map(|&len| {
+                top = top.checked_sub(len as u64 + 1).expect(OVERFLOW);
+                top
+            }).collect()
+        };
+        let arg_ptrs = place(&entry.args);
+        let env_ptrs = place(&entry.env);
+
+        // The vectors are below them, with the stack pointer aligned to 16 bytes.
+        let words = 1 + (arg_ptrs.len() + 1) + (env_ptrs.len() + 1) + 2 * (entry.auxv.len() + 1);
+        let rsp = top.checked_sub(8 * words as u64).expect(OVERFLOW) & !0xf;
+
+        let mut words = vec![SymExpr::from_ptr(arg_ptrs.len() as u64)];
+        words.extend(arg_ptrs.iter().chain(&[0]).map(|&ptr| SymExpr::from_ptr(ptr)));
+        words.extend(env_ptrs.iter().chain(&[0]).map(|&ptr| SymExpr::from_ptr(ptr)));
+
+        let mut auxv = Vec::new();
+        for (index, &(kind, value)) in entry.auxv.iter().chain(&[(0, Some(0))]).enumerate() {
+            words.push(SymExpr::from_ptr(kind));
+            words.push(match value {
+                Some(value) => SymExpr::from_ptr(value),
+                None => {
+                    let addr = rsp + 8 * words.len() as u64;
+                    let symbol = Symbol(N64, "auxv", index);
+                    auxv.push(self.insert_input(rsp, addr, symbol));
+                    SymExpr::Sym(symbol)
+                },
+            });
+        }
+
+        for (i, word) in words.into_iter().enumerate() {
+            self.memory[0].write_direct(rsp + 8 * i as u64, word);
+        }
+
+        let args = self.write_strings(rsp, "argv", &arg_ptrs, &entry.args);
+        let env = self.write_strings(rsp, "env", &env_ptrs, &entry.env);
+
+        self.set_reg(Register::RSP, SymExpr::from_ptr(rsp));
+        self.memory[0].set_region_range(
+            entry.stack_base.saturating_sub(STACK_SIZE), entry.stack_base, Region::Stack);
+
+        vec![
+            Event::Stdio(StdioKind::Args, args),
+            Event::Stdio(StdioKind::Env, env),
+            Event::Stdio(StdioKind::Auxv, auxv),
+        ]
+    }
+
+    /// Write null-terminated strings of symbolic bytes.
+    fn write_strings(
+        &mut self,
+        rsp: u64,
+        space: &'static str,
+        ptrs: &[u64],
+        lens: &[usize],
+    ) -> Vec<(Symbol, TypedMemoryAccess)> {
+        let mut inputs = Vec::new();
+        for (number, (&ptr, &len)) in ptrs.iter().zip(lens).enumerate() {
+            let space = numbered_space(space, number);
+            for index in 0 .. len {
+                let addr = ptr + index as u64;
+                let symbol = Symbol(N8, space, index);
+                inputs.push(self.insert_input(rsp, addr, symbol));
+                self.memory[0].write_direct(addr, SymExpr::Sym(symbol));
+            }
+            self.memory[0].write_direct(ptr + len as u64, SymExpr::Int(Integer(N8, 0)));
+        }
+        inputs
+    }
+
+    /// Map an input symbol to its location relative to the stack pointer.
+    fn insert_input(&mut self, rsp: u64, addr: u64, symbol: Symbol) -> (Symbol, TypedMemoryAccess) {
+        let displacement = (addr - rsp) as i64;
+        let location = self.abstract_location(self.ip, StorageLocation::Indirect {
+            data_type: symbol.0,
+            base: Register::RSP,
+            scaled_offset: None,
+            displacement: if displacement != 0 { Some(displacement) } else { None },
+        });
+
+        self.symbol_map.insert(symbol, location);
+        (symbol, TypedMemoryAccess(SymExpr::from_ptr(addr), symbol.0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::math::Solver;
+    use super::super::MemoryStrategy;
+    use super::*;
+
+    #[test]
+    fn entry_stack() {
+        let mut state = SymState::new(MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        let mut entry = EntryState::new();
+        entry.args = vec![3, 1];
+        entry.auxv.push((25, None));
+        let events = state.enter(&entry);
+
+        let rsp = match state.get_reg(Register::RSP) {
+            SymExpr::Int(Integer(N64, rsp)) => rsp,
+            rsp => panic!("enter: symbolic stack pointer {}", rsp),
+        };
+        assert_eq!(rsp % 16, 0);
+
+        let mem = &state.memory[0];
+        let word = |i: u64| mem.read_direct(rsp + 8 * i, N64);
+        assert_eq!(word(0), SymExpr::from_ptr(2));
+        assert_eq!(word(1), SymExpr::from_ptr(entry.stack_base - 4));
+        assert_eq!(word(2), SymExpr::from_ptr(entry.stack_base - 6));
+        assert_eq!(word(3), SymExpr::from_ptr(0));
+        assert_eq!(word(4), SymExpr::from_ptr(0));
+        assert_eq!(word(5), SymExpr::from_ptr(AT_PAGESZ));
+        assert_eq!(word(7), SymExpr::from_ptr(25));
+        assert_eq!(word(8), SymExpr::Sym(Symbol(N64, "auxv", 1)));
+        assert_eq!(word(9), SymExpr::from_ptr(0));
+
+        let argv0 = numbered_space("argv", 0);
+        assert_eq!(mem.read_direct(entry.stack_base - 3, N8), SymExpr::Sym(Symbol(N8, argv0, 1)));
+        assert_eq!(mem.read_direct(entry.stack_base - 1, N8), SymExpr::Int(Integer(N8, 0)));
+        assert_eq!(mem.regions().region_of(&word(1)), Some(Region::Stack));
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(state.symbol_map[&Symbol(N64, "auxv", 1)].storage, StorageLocation::Indirect {
+            data_type: N64,
+            base: Register::RSP,
+            scaled_offset: None,
+            displacement: Some(64),
+        });
+    }
+
+    #[test]
+    fn entry_stack_bounds() {
+        let new_state = || SymState::new(MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+
+        // A stack base below the stack size still covers the stack from zero.
+        let mut state = new_state();
+        let mut entry = EntryState::new();
+        entry.stack_base = 0x1000;
+        state.enter(&entry);
+        assert_eq!(state.memory[0].regions().region_of(&SymExpr::from_ptr(0)), Some(Region::Stack));
+
+        // Strings which do not fit below the stack base are rejected.
+        entry.stack_base = 0x10;
+        entry.args = vec![0x20];
+        let result = std::panic::catch_unwind(move || new_state().enter(&entry));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! File descriptors and the byte streams behind them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+use crate::math::{numbered_space, SymExpr, Symbol, Integer, DataType};
+use super::StdioKind;
+
+/// Bad file descriptor.
+pub const EBADF: u64 = 9;
+/// Out of memory.
+pub const ENOMEM: u64 = 12;
+/// Invalid argument.
+pub const EINVAL: u64 = 22;
+/// Illegal seek on a pipe or terminal.
+pub const ESPIPE: u64 = 29;
+
+
+/// The open file descriptors of the process.
+///
+/// Files are named symbolic byte streams: the byte at offset `i` of the `n`-th
+/// opened file is the symbol `filein{n}_{i}` and bytes written to it are
+/// `fileout{n}_{i}`. Written bytes are not read back, the contents of a file
+/// are always unknown input.
+///
+/// Each input stream has a symbolic size, `size0` for standard input and
+/// `size{n+1}` for the `n`-th opened file, and reads stop at the end.
+///
+/// Positions become symbolic after transferring a symbolic number of bytes.
+/// Reads that stop early at the end of the input still move the position by
+/// the full count, which makes no difference since all following reads
+/// start behind the end as well.
+#[derive(Debug, Clone)]
+pub struct FileTable {
+    descriptors: BTreeMap<u64, OpenFile>,
+    /// The positions in streams that cannot seek, shared by all descriptors.
+    positions: HashMap<StdioKind, StreamPosition>,
+    /// The number of files opened so far.
+    opened: usize,
+}
+
+/// What a file descriptor refers to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpenFile {
+    /// The path the file was opened with if it is known.
+    pub path: Option<String>,
+    /// The kind of data read through the descriptor if it is readable.
+    pub input: Option<StdioKind>,
+    /// The kind of data written through the descriptor if it is writable.
+    pub output: Option<StdioKind>,
+    /// The position in the file or `None` if the descriptor cannot seek.
+    pub offset: Option<StreamPosition>,
+}
+
+/// A possibly symbolic position in a byte stream.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StreamPosition {
+    /// The value of the position.
+    pub expr: SymExpr,
+    /// The smallest and largest value the position can have.
+    pub range: (u64, u64),
+}
+
+impl StreamPosition {
+    /// Create a concrete position.
+    pub fn new(position: u64) -> StreamPosition {
+        StreamPosition { expr: SymExpr::from_ptr(position), range: (position, position) }
+    }
+
+    /// The values the position can have.
+    pub fn candidates(&self) -> RangeInclusive<u64> {
+        self.range.0 ..= self.range.1
+    }
+
+    /// The position after `count` more bytes, where the count is at most
+    /// `bound`.
+    pub fn advance(&self, count: &SymExpr, bound: u64) -> StreamPosition {
+        let least = match count {
+            SymExpr::Int(Integer(_, count)) => *count,
+            _ => 0,
+        };
+
+        StreamPosition {
+            expr: self.expr.clone().add(count.clone()),
+            range: (self.range.0 + least, self.range.1 + bound),
+        }
+    }
+}
+
+impl FileTable {
+    /// Create a file table with the standard descriptors.
+    ///
+    /// Like on a terminal, all three are open for reading and writing. Reads
+    /// consume standard input and writes to any but descriptor 2 go to
+    /// standard output.
+    pub fn new() -> FileTable {
+        let terminal = |output| OpenFile {
+            path: None,
+            input: Some(StdioKind::Stdin),
+            output: Some(output),
+            offset: None,
+        };
+
+        let mut descriptors = BTreeMap::new();
+        descriptors.insert(0, terminal(StdioKind::Stdout));
+        descriptors.insert(1, terminal(StdioKind::Stdout));
+        descriptors.insert(2, terminal(StdioKind::Stderr));
+
+        FileTable { descriptors, positions: HashMap::new(), opened: 0 }
+    }
+
+    /// The file behind a descriptor.
+    pub fn get(&self, fd: u64) -> Option<&OpenFile> {
+        self.descriptors.get(&fd)
+    }
+
+    /// Open a file with the access mode from the flags and return the lowest
+    /// free descriptor. Opening always succeeds since nothing is known about
+    /// which files exist.
+    pub fn open(&mut self, path: Option<String>, flags: u64) -> Result<u64, u64> {
+        let (read, write) = match flags & 3 {
+            0 => (true, false),
+            1 => (false, true),
+            2 => (true, true),
+            _ => return Err(EINVAL),
+        };
+
+        let number = self.opened;
+        self.opened += 1;
+
+        let fd = (0 ..).find(|fd| !self.descriptors.contains_key(fd)).unwrap();
+        self.descriptors.insert(fd, OpenFile {
+            path,
+            input: if read { Some(StdioKind::FileIn(number)) } else { None },
+            output: if write { Some(StdioKind::FileOut(number)) } else { None },
+            offset: Some(StreamPosition::new(0)),
+        });
+
+        Ok(fd)
+    }
+
+    /// Close a descriptor.
+    pub fn close(&mut self, fd: u64) -> Result<u64, u64> {
+        self.descriptors.remove(&fd).map(|_| 0).ok_or(EBADF)
+    }
+
+    /// Move the offset of a descriptor relative to the start (`whence` 0) or
+    /// the current offset (`whence` 1) and return the new offset. Seeking
+    /// relative to the end is invalid as the file sizes are unknown, and so
+    /// is seeking relative to a symbolic offset that may become negative.
+    pub fn seek(&mut self, fd: u64, offset: i64, whence: u64) -> Result<StreamPosition, u64> {
+        let file = self.descriptors.get_mut(&fd).ok_or(EBADF)?;
+        let current = file.offset.as_mut().ok_or(ESPIPE)?;
+
+        let target = match whence {
+            0 if offset >= 0 => StreamPosition::new(offset as u64),
+            1 => {
+                let shift = |position: u64| {
+                    (position as i64).checked_add(offset).filter(|&target| target >= 0)
+                };
+                let range = (shift(current.range.0).ok_or(EINVAL)?, shift(current.range.1).ok_or(EINVAL)?);
+                StreamPosition {
+                    expr: current.expr.clone().add(SymExpr::from_ptr(offset as u64)),
+                    range: (range.0 as u64, range.1 as u64),
+                }
+            },
+            _ => return Err(EINVAL),
+        };
+
+        *current = target;
+        Ok(current.clone())
+    }
+
+    /// The symbol for the size of the input if the kind is a stream whose
+    /// size is unknown.
+    pub fn size_symbol(kind: StdioKind) -> Option<Symbol> {
+        match kind {
+            StdioKind::Stdin => Some(Symbol(DataType::N64, "size", 0)),
+            StdioKind::FileIn(number) => Some(Symbol(DataType::N64, "size", number + 1)),
+            _ => None,
+        }
+    }
+
+    /// Transfer `count` bytes, at most `bound`, through a descriptor. Returns
+    /// the kind of the transfer, the symbol space of the bytes and the
+    /// position of the first one.
+    pub fn transfer(&mut self, fd: u64, write: bool, count: &SymExpr, bound: u64)
+    -> Result<(StdioKind, &'static str, StreamPosition), u64> {
+        let file = self.descriptors.get_mut(&fd).ok_or(EBADF)?;
+        let kind = if write { file.output } else { file.input }.ok_or(EBADF)?;
+
+        let position = match &mut file.offset {
+            Some(offset) => offset,
+            None => self.positions.entry(kind).or_insert_with(|| StreamPosition::new(0)),
+        };
+
+        let start = position.clone();
+        *position = start.advance(count, bound);
+
+        let space = match kind {
+            StdioKind::Stdin => "stdin",
+            StdioKind::Stdout => "stdout",
+            StdioKind::Stderr => "stderr",
+            StdioKind::FileIn(number) => numbered_space("filein", number),
+            StdioKind::FileOut(number) => numbered_space("fileout", number),
+            kind => panic!("transfer: {:?} is not a stream", kind),
+        };
+
+        Ok((kind, space, start))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors() {
+        let mut files = FileTable::new();
+        let transfer = |files: &mut FileTable, fd, write, count| {
+            files.transfer(fd, write, &SymExpr::from_ptr(count), count)
+                .map(|(kind, space, start)| (kind, space, start.expr))
+        };
+        let at = SymExpr::from_ptr;
+
+        assert_eq!(transfer(&mut files, 0, false, 4), Ok((StdioKind::Stdin, "stdin", at(0))));
+        assert_eq!(transfer(&mut files, 1, false, 2), Ok((StdioKind::Stdin, "stdin", at(4))));
+        assert_eq!(transfer(&mut files, 0, true, 1), Ok((StdioKind::Stdout, "stdout", at(0))));
+        assert_eq!(transfer(&mut files, 2, true, 1), Ok((StdioKind::Stderr, "stderr", at(0))));
+        assert_eq!(files.seek(0, 0, 0), Err(ESPIPE));
+
+        assert_eq!(files.open(Some("in.txt".to_string()), 0), Ok(3));
+        assert_eq!(files.open(None, 2), Ok(4));
+        assert_eq!(transfer(&mut files, 3, true, 1), Err(EBADF));
+        assert_eq!(transfer(&mut files, 3, false, 8), Ok((StdioKind::FileIn(0), "filein0_", at(0))));
+        assert_eq!(transfer(&mut files, 3, false, 8), Ok((StdioKind::FileIn(0), "filein0_", at(8))));
+        assert_eq!(files.seek(3, -4, 1), Ok(StreamPosition::new(12)));
+        assert_eq!(files.seek(3, -1, 0), Err(EINVAL));
+        assert_eq!(files.seek(3, 0, 2), Err(EINVAL));
+        assert_eq!(transfer(&mut files, 3, false, 1), Ok((StdioKind::FileIn(0), "filein0_", at(12))));
+        assert_eq!(transfer(&mut files, 4, true, 3), Ok((StdioKind::FileOut(1), "fileout1_", at(0))));
+
+        // Symbolic counts make the position symbolic.
+        let count = SymExpr::Sym(Symbol(DataType::N64, "reg", 0));
+        let (_, _, start) = files.transfer(3, false, &count, 4).unwrap();
+        assert_eq!(start, StreamPosition::new(13));
+        let after = files.seek(3, 2, 1).unwrap();
+        assert_eq!(after.range, (15, 19));
+        assert_eq!(after.candidates().count(), 5);
+        assert_eq!(after.expr.evaluate(&|_| Some(Integer::from_ptr(3))), Integer::from_ptr(18));
+
+        assert_eq!(files.close(3), Ok(0));
+        assert_eq!(files.close(3), Err(EBADF));
+        assert_eq!(transfer(&mut files, 3, false, 1), Err(EBADF));
+        assert_eq!(files.open(None, 0), Ok(3));
+        assert_eq!(files.get(3).unwrap().input, Some(StdioKind::FileIn(2)));
+        assert_eq!(FileTable::size_symbol(StdioKind::FileIn(2)), Some(Symbol(DataType::N64, "size", 3)));
+        assert_eq!(FileTable::size_symbol(StdioKind::Stdout), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! Memory allocated through `brk` and `mmap`.
+
+use super::{Region, EINVAL, ENOMEM};
+
+/// The initial program break.
+pub const BRK_BASE: u64 = 0x1000_0000;
+/// The highest address the program break can grow to.
+pub const BRK_END: u64 = 0x4000_0000;
+/// The address of the first mapping.
+pub const MMAP_BASE: u64 = 0x7ff0_0000_0000;
+/// The end of the area for mappings, which is far below the stack.
+pub const MMAP_END: u64 = 0x7ff8_0000_0000;
+/// The granularity of mappings.
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Place the mapping exactly at the given address.
+const MAP_FIXED: u64 = 0x10;
+
+
+/// The allocated heap objects of the process.
+///
+/// Allocations get fresh concrete addresses and each of them is a region of
+/// its own. The area behind the program break is one object that grows and
+/// shrinks with the break. The contents of new memory are unknown.
+#[derive(Debug, Clone)]
+pub struct Heap {
+    /// The current program break.
+    brk: u64,
+    /// The region of the area behind the program break once it grew.
+    brk_region: Option<Region>,
+    /// Where the next mapping without a fixed address is placed.
+    next_mapping: u64,
+    /// The live mappings with their start and end addresses.
+    mappings: Vec<(u64, u64, Region)>,
+    /// The number of heap regions created so far.
+    regions: usize,
+    /// The instruction and target addresses of reads from memory that is in
+    /// the heap areas but not mapped.
+    pub unmapped_reads: Vec<(u64, u64)>,
+}
+
+impl Heap {
+    /// Create a heap without any allocations.
+    pub fn new() -> Heap {
+        Heap {
+            brk: BRK_BASE,
+            brk_region: None,
+            next_mapping: MMAP_BASE,
+            mappings: Vec::new(),
+            regions: 0,
+            unmapped_reads: Vec::new(),
+        }
+    }
+
+    /// Move the program break and return the new one. Invalid breaks leave
+    /// it unchanged like in the kernel, which is how `brk(0)` queries it.
+    ///
+    /// If the break grew, the new memory is returned as the second value.
+    pub fn brk(&mut self, addr: u64) -> (u64, Option<(u64, u64, Region)>) {
+        if !(BRK_BASE ..= BRK_END).contains(&addr) {
+            return (self.brk, None);
+        }
+
+        let grown = if addr > self.brk {
+            let region = match self.brk_region {
+                Some(region) => region,
+                None => self.new_region(),
+            };
+            self.brk_region = Some(region);
+            Some((self.brk, addr, region))
+        } else {
+            None
+        };
+
+        self.brk = addr;
+        (addr, grown)
+    }
+
+    /// Map `len` bytes and return the start and end address with the region
+    /// of the new mapping. Fixed mappings replace what was mapped there before.
+    pub fn mmap(&mut self, addr: u64, len: u64, flags: u64) -> Result<(u64, u64, Region), u64> {
+        if len == 0 || len > MMAP_END - MMAP_BASE {
+            return Err(EINVAL);
+        }
+
+        let len = round_up(len);
+        let start = if flags & MAP_FIXED != 0 {
+            if addr & (PAGE_SIZE - 1) != 0 || addr < MMAP_BASE || addr > MMAP_END - len {
+                return Err(EINVAL);
+            }
+            self.munmap(addr, len)?;
+            addr
+        } else {
+            if self.next_mapping > MMAP_END - len {
+                return Err(ENOMEM);
+            }
+            let start = self.next_mapping;
+            self.next_mapping += len;
+            start
+        };
+
+        let region = self.new_region();
+        self.mappings.push((start, start + len, region));
+        Ok((start, start + len, region))
+    }
+
+    /// Unmap all pages in the range, splitting mappings that overlap it
+    /// partially.
+    pub fn munmap(&mut self, addr: u64, len: u64) -> Result<u64, u64> {
+        if addr & (PAGE_SIZE - 1) != 0 || len == 0 {
+            return Err(EINVAL);
+        }
+
+        let end = addr.saturating_add(round_up(len));
+        let mut mappings = Vec::new();
+        for &(start, stop, region) in &self.mappings {
+            if start < addr {
+                mappings.push((start, stop.min(addr), region));
+            }
+            if stop > end {
+                mappings.push((start.max(end), stop, region));
+            }
+        }
+
+        self.mappings = mappings;
+        Ok(0)
+    }
+
+    /// Whether the address is in one of the heap areas, but not allocated.
+    pub fn is_unmapped(&self, addr: u64) -> bool {
+        if (BRK_BASE .. BRK_END).contains(&addr) {
+            addr >= self.brk
+        } else if (MMAP_BASE .. MMAP_END).contains(&addr) {
+            !self.mappings.iter().any(|&(start, end, _)| start <= addr && addr < end)
+        } else {
+            false
+        }
+    }
+
+    fn new_region(&mut self) -> Region {
+        let region = Region::Heap(self.regions);
+        self.regions += 1;
+        region
+    }
+}
+
+/// Round a length up to whole pages.
+pub(super) fn round_up(len: u64) -> u64 {
+    len.saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation() {
+        let mut heap = Heap::new();
+        assert_eq!(heap.brk(0), (BRK_BASE, None));
+        assert_eq!(heap.brk(BRK_BASE + 0x100),
+            (BRK_BASE + 0x100, Some((BRK_BASE, BRK_BASE + 0x100, Region::Heap(0)))));
+        assert_eq!(heap.brk(BRK_BASE + 0x200).1,
+            Some((BRK_BASE + 0x100, BRK_BASE + 0x200, Region::Heap(0))));
+        assert_eq!(heap.brk(BRK_BASE + 0x80), (BRK_BASE + 0x80, None));
+        assert!(!heap.is_unmapped(BRK_BASE + 0x7f));
+        assert!(heap.is_unmapped(BRK_BASE + 0x80));
+
+        assert_eq!(heap.mmap(0, 10, 0x22), Ok((MMAP_BASE, MMAP_BASE + 0x1000, Region::Heap(1))));
+        assert_eq!(heap.mmap(0, 0x2001, 0x22), Ok((MMAP_BASE + 0x1000, MMAP_BASE + 0x4000, Region::Heap(2))));
+        assert_eq!(heap.mmap(0, 0, 0x22), Err(EINVAL));
+        assert!(!heap.is_unmapped(MMAP_BASE + 0x3fff));
+        assert!(heap.is_unmapped(MMAP_BASE + 0x4000));
+
+        assert_eq!(heap.munmap(MMAP_BASE + 0x2000, 1), Ok(0));
+        assert!(heap.is_unmapped(MMAP_BASE + 0x2000));
+        assert!(!heap.is_unmapped(MMAP_BASE + 0x1000));
+        assert!(!heap.is_unmapped(MMAP_BASE + 0x3000));
+        assert_eq!(heap.munmap(MMAP_BASE + 1, 1), Err(EINVAL));
+
+        assert_eq!(heap.mmap(MMAP_BASE + 0x2000, 0x1000, 0x32),
+            Ok((MMAP_BASE + 0x2000, MMAP_BASE + 0x3000, Region::Heap(3))));
+        assert!(!heap.is_unmapped(MMAP_BASE + 0x2000));
+        assert!(!heap.is_unmapped(0x601040));
+    }
+}
@@ -4,14 +4,23 @@ use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 
-use crate::math::{SymExpr, SymCondition, DataType, Symbol, SharedSolver};
+use crate::math::{SymExpr, SymCondition, SymArray, DataType, Symbol, SharedSolver, SatResult};
+use super::{Region, Regions, TypedMemoryAccess};
 
 
 /// Symbolic memory handling writes and reads involving symbolic
 /// values and addresses.
+///
+/// Memory is byte-granular: A read is assembled from the bytes of all writes
+/// overlapping it, so values can be read back with a different width or at an
+/// offset into them.
+///
+/// Writes into a different region than a read are never considered as
+/// aliasing with it.
 #[derive(Debug, Clone)]
 pub struct SymMemory {
     data: RefCell<MemoryData>,
+    regions: Regions,
     solver: SharedSolver,
     strategy: MemoryStrategy,
 }
@@ -21,8 +30,11 @@ pub struct SymMemory {
 pub enum MemoryStrategy {
     /// Build an if-then-else tree of values that could possibly match.
     ConditionalTrees,
-    /// Only return perfect matches (faster).
+    /// Only return bytes of writes at a known offset from the read (faster).
     PerfectMatches,
+    /// Store the bytes into an SMT array and select them on reads, leaving
+    /// possible aliasing to the solver.
+    SmtArrays,
 }
 
 /// The actual memory data, which is wrapped in an interior mutability type
@@ -32,6 +44,11 @@ pub enum MemoryStrategy {
 struct MemoryData {
     name: &'static str,
     writes: BTreeMap<Epoch, MemoryWrite>,
+    /// The default symbols generated for uninitialized memory, which are
+    /// older than all writes.
+    initial: Vec<MemoryWrite>,
+    /// The array holding all writes for the `SmtArrays` strategy.
+    array: SymArray,
     symbols: usize,
     epoch: Epoch,
 }
@@ -42,9 +59,21 @@ type Epoch = u32;
 #[derive(Debug, Clone)]
 struct MemoryWrite {
     addr: SymExpr,
+    /// The address split into a base and a constant displacement.
+    split: (SymExpr, u64),
     value: SymExpr,
 }
 
+/// The possible values of one byte of a read.
+#[derive(Debug, Clone)]
+struct ByteTree {
+    /// The bytes of writes which possibly wrote this byte and the conditions
+    /// under which they did, from newest to oldest.
+    branches: Vec<(SymCondition, SymExpr)>,
+    /// The value if none of the branches match (`None` if unknown so far).
+    base: Option<SymExpr>,
+}
+
 impl SymMemory {
     /// Create a new blank symbolic memory.
     pub fn new(name: &'static str, strategy: MemoryStrategy, solver: SharedSolver) -> SymMemory {
@@ -52,14 +81,55 @@ impl SymMemory {
             data: RefCell::new(MemoryData {
                 name,
                 writes: BTreeMap::new(),
+                initial: Vec::new(),
+                array: SymArray::Base(name),
                 symbols: 0,
                 epoch: 1,
             }),
+            regions: Regions::new(),
             solver,
             strategy,
         }
     }
 
+    /// The regions addresses of this memory point into.
+    pub fn regions(&self) -> &Regions {
+        &self.regions
+    }
+
+    /// Declare a symbol as the base pointer of a region.
+    pub fn set_region(&mut self, base: Symbol, region: Region) {
+        self.regions.insert(base, region);
+    }
+
+    /// Declare a range of fixed addresses as part of a region.
+    pub fn set_region_range(&mut self, start: u64, end: u64, region: Region) {
+        self.regions.insert_range(start, end, region);
+    }
+
+    /// Forget the regions of a range of fixed addresses.
+    pub fn remove_region_range(&mut self, start: u64, end: u64) {
+        self.regions.remove_range(start, end);
+    }
+
+    /// Whether the read may overlap the write, decided the same way as when
+    /// reading: Accesses into different regions or at a constant distance
+    /// are decided directly and all others by the solver, which may give up.
+    pub fn may_overlap(&self, write: &TypedMemoryAccess, read: &TypedMemoryAccess) -> SatResult {
+        if self.regions.disjoint(&write.0, &read.0) {
+            return SatResult::Unsat;
+        }
+
+        let (len, read_len) = (write.1.bytes() as u64, read.1.bytes() as u64);
+        match read.0.clone().sub(write.0.clone()).simplify() {
+            SymExpr::Int(distance) => {
+                let inside = distance.1 < len || distance.1.wrapping_neg() < read_len;
+                if inside { SatResult::Sat } else { SatResult::Unsat }
+            },
+            distance => self.solver.check_sat(&overlap(&write.0, len, &read.0, read_len, distance)),
+        }
+    }
+
     /// Read from a direct address.
     pub fn read_direct(&self, addr: u64, data_type: DataType) -> SymExpr {
         self.read_expr(SymExpr::from_ptr(addr), data_type)
@@ -71,83 +141,100 @@ impl SymMemory {
     }
 
     /// Read from a symbolic address.
+    ///
+    /// This goes through the writes from newest to oldest and collects the
+    /// possible values of every byte until all of them are known for sure.
+    /// Uninitialized bytes are taken from a new default symbol. With SMT
+    /// arrays, the bytes are simply selected from the array and uninitialized
+    /// ones are the symbols of its array space (see `array_space`).
     pub fn read_expr(&self, addr: SymExpr, data_type: DataType) -> SymExpr {
         crate::timings::with("sym-mem", || {
-            let expr = match self.strategy {
-                MemoryStrategy::PerfectMatches => self.read_perfect(addr, data_type),
-                MemoryStrategy::ConditionalTrees => self.read_conditional(addr, data_type),
-            };
-
-            if expr.data_type() == data_type { expr } else { expr.cast(data_type, false) }
-        })
-    }
+            let mut data = self.data.borrow_mut();
 
-    /// Read from memory using the perfect matches strategy.
-    ///
-    /// This will only return the value if the address expression match
-    /// perfectly. This is not sound but way faster than `read_conditional`.
-    fn read_perfect(&self, addr: SymExpr, data_type: DataType) -> SymExpr {
-        let mut data = self.data.borrow_mut();
-
-        for (_, write) in data.writes.iter().rev() {
-            if write.addr == addr {
-                return write.value.clone();
+            if self.strategy == MemoryStrategy::SmtArrays {
+                return concat_bytes((0 .. data_type.bytes())
+                    .map(|i| data.array.select(addr.clone().add(SymExpr::from_ptr(i as u64))))
+                    .collect());
             }
-        }
 
-        data.generate_default_symbol(addr, data_type)
-    }
+            let mut trees = vec![ByteTree::new(); data_type.bytes()];
+            let split = split(&addr);
 
-    /// Read from memory using the conditional trees strategy.
-    ///
-    /// This will go through the write map from newest to oldest and build
-    /// an if-then-else chain with all values that possibly match and the
-    /// conditions under which they match.
-    fn read_conditional(&self, addr: SymExpr, data_type: DataType) -> SymExpr {
-        let mut data = self.data.borrow_mut();
-
-        let default = data.get_default_value(data_type);
-        let mut tree = default.clone();
-        let mut active = &mut tree;
-        let mut used_default_symbol = true;
-
-        // We traverse the memory writes from latest to oldest. If we find
-        // a write that perfectly matches our read, we can quit early
-        // because anything before would have been overwritten for sure.
-        for (_, write) in data.writes.iter().rev() {
-            // If it matches perfectly, we can stop here.
-            if write.addr == addr {
-                *active = write.value.clone();
-                used_default_symbol = false;
-                break;
-            }
+            for write in data.writes.values().rev().chain(data.initial.iter().rev()) {
+                if trees.iter().all(|tree| tree.base.is_some()) {
+                    break;
+                }
 
-            if self.solver.check_equal_sat(&write.addr, &addr) {
-                let condition = write.addr.clone().equal(addr.clone());
-                let simplified = self.solver.simplify_condition(&condition);
+                if let Some(offset) = offset(&write.split, &split) {
+                    read_at_offset(&mut trees, write, offset);
+                    continue;
+                }
 
-                // If it didn't match perfectly but still always is the same thing
-                // we can also stop here.
-                if simplified == SymCondition::TRUE {
-                    *active = write.value.clone();
-                    used_default_symbol = false;
-                    break;
+                if self.regions.disjoint(&write.addr, &addr) {
+                    continue;
+                }
 
-                } else {
-                    *active = simplified.if_then_else(write.value.clone(), default.clone());
-                    active = match active {
-                        SymExpr::IfThenElse(_, _, ref mut b) => b,
-                        _ => panic!("read_conditional: expected if-then-else"),
-                    };
+                // The bases differ, but the distance may still be constant.
+                match addr.clone().sub(write.addr.clone()).simplify() {
+                    SymExpr::Int(distance) => read_at_offset(&mut trees, write, distance.1),
+                    distance => if self.strategy == MemoryStrategy::ConditionalTrees {
+                        self.read_aliasing(&mut trees, write, &addr, distance);
+                    },
+                }
+            }
+
+            if trees.iter().any(|tree| tree.base.is_none()) {
+                let default = data.generate_default_symbol(addr, data_type);
+                for (i, tree) in trees.iter_mut().enumerate() {
+                    if tree.base.is_none() {
+                        tree.base = Some(byte_of(&default, i));
+                    }
                 }
             }
-        }
 
-        if used_default_symbol {
-            data.generate_default_symbol(addr, data_type);
+            assemble(trees)
+        })
+    }
+
+    /// Take the bytes of a write which is at a symbolic distance from the
+    /// read under the conditions that they overlap.
+    ///
+    /// Only whether they can overlap at all is checked with the solver. Each
+    /// byte of the read then selects the byte of the write at its distance.
+    /// If the solver gives up, the write is kept (see `may_overlap`).
+    fn read_aliasing(
+        &self,
+        trees: &mut [ByteTree],
+        write: &MemoryWrite,
+        addr: &SymExpr,
+        distance: SymExpr,
+    ) {
+        let len = write.value.data_type().bytes();
+        let overlap = overlap(&write.addr, len as u64, addr, trees.len() as u64, distance.clone());
+        if self.solver.check_sat(&overlap) == SatResult::Unsat {
+            return;
         }
 
-        tree
+        for (i, tree) in trees.iter_mut().enumerate() {
+            if tree.base.is_some() {
+                continue;
+            }
+
+            // The byte of the read is the one of the write at index `at`.
+            let at = distance.clone().add(SymExpr::from_ptr(i as u64));
+            let mut byte = byte_of(&write.value, len - 1);
+            for index in (0 .. len - 1).rev() {
+                byte = at.clone().equal(SymExpr::from_ptr(index as u64))
+                    .if_then_else(byte_of(&write.value, index), byte);
+            }
+
+            let inside = if len == 1 {
+                at.equal(SymExpr::from_ptr(0))
+            } else {
+                at.less_than(SymExpr::from_ptr(len as u64), false)
+            };
+            tree.branches.push((inside, byte));
+        }
     }
 
     /// Write a value to a symbolic address.
@@ -155,32 +242,37 @@ impl SymMemory {
         crate::timings::with("sym-mem", || {
             let mut data = self.data.borrow_mut();
 
-            let new_write = MemoryWrite { addr, value };
-
-            for (_, write) in data.writes.iter_mut().rev() {
-                if write.addr == new_write.addr {
-                    *write = new_write;
-                    return;
+            if self.strategy == MemoryStrategy::SmtArrays {
+                let mut array = data.array.clone();
+                for i in 0 .. value.data_type().bytes() {
+                    let target = addr.clone().add(SymExpr::from_ptr(i as u64));
+                    array = array.store(target, byte_of(&value, i));
                 }
+                data.array = array;
+                return;
             }
 
+            // Writes that are completely overwritten are not needed anymore.
+            let len = value.data_type().bytes() as u64;
+            let split = split(&addr);
+            data.writes.retain(|_, write| match offset(&split, &write.split) {
+                Some(offset) => offset.saturating_add(write.value.data_type().bytes() as u64) > len,
+                None => true,
+            });
+
             let epoch = data.epoch;
-            data.writes.insert(epoch, new_write);
+            data.writes.insert(epoch, MemoryWrite { addr, split, value });
             data.epoch += 1;
         })
     }
 }
 
 impl MemoryData {
-    /// Get the value for the next default symbol that would be generated.
-    fn get_default_value(&self, data_type: DataType) -> SymExpr {
-        SymExpr::Sym(Symbol(data_type, self.name, self.symbols))
-    }
-
     /// Generate a default symbol for uninitialized memory.
     fn generate_default_symbol(&mut self, addr: SymExpr, data_type: DataType) -> SymExpr {
-        let value = self.get_default_value(data_type);
-        self.writes.insert(0, MemoryWrite {
+        let value = SymExpr::Sym(Symbol(data_type, self.name, self.symbols));
+        self.initial.push(MemoryWrite {
+            split: split(&addr),
             addr,
             value: value.clone(),
         });
@@ -189,14 +281,310 @@ impl MemoryData {
     }
 }
 
+impl ByteTree {
+    fn new() -> ByteTree {
+        ByteTree {
+            branches: Vec::new(),
+            base: None,
+        }
+    }
+
+    /// Build the if-then-else chain for this byte.
+    fn fold(self) -> SymExpr {
+        let mut tree = self.base.expect("fold: missing base value");
+        for (condition, value) in self.branches.into_iter().rev() {
+            tree = condition.if_then_else(value, tree);
+        }
+        tree
+    }
+}
+
+/// Take the bytes of a write that is known to start `offset` bytes before
+/// the read.
+fn read_at_offset(trees: &mut [ByteTree], write: &MemoryWrite, offset: u64) {
+    let len = write.value.data_type().bytes() as u64;
+    for (i, tree) in trees.iter_mut().enumerate() {
+        let index = offset.wrapping_add(i as u64);
+        if tree.base.is_none() && index < len {
+            tree.base = Some(byte_of(&write.value, index as usize));
+        }
+    }
+}
+
+/// The condition that a read at a symbolic distance from a write overlaps it,
+/// that is the read starts inside the write or the write inside the read.
+fn overlap(write: &SymExpr, len: u64, read: &SymExpr, read_len: u64, distance: SymExpr) -> SymCondition {
+    distance.less_than(SymExpr::from_ptr(len), false)
+        .or(write.clone().sub(read.clone()).less_than(SymExpr::from_ptr(read_len), false))
+}
+
+/// The distance from `base` to `addr` if both have the same base.
+fn offset(base: &(SymExpr, u64), addr: &(SymExpr, u64)) -> Option<u64> {
+    if base.0 == addr.0 {
+        Some(addr.1.wrapping_sub(base.1))
+    } else {
+        None
+    }
+}
+
+/// Split an address into a base expression and a constant displacement, so
+/// that addresses can be compared without simplifying their difference.
+fn split(addr: &SymExpr) -> (SymExpr, u64) {
+    match addr {
+        SymExpr::Int(int) => (SymExpr::from_ptr(0), int.1),
+        SymExpr::Add(a, b) => match (&**a, &**b) {
+            (base, SymExpr::Int(int)) | (SymExpr::Int(int), base) => {
+                let (base, displacement) = split(base);
+                (base, displacement.wrapping_add(int.1))
+            },
+            _ => (addr.clone(), 0),
+        },
+        SymExpr::Sub(a, b) => match &**b {
+            SymExpr::Int(int) => {
+                let (base, displacement) = split(a);
+                (base, displacement.wrapping_sub(int.1))
+            },
+            _ => (addr.clone(), 0),
+        },
+        _ => (addr.clone(), 0),
+    }
+}
+
+/// The byte at the index of a little-endian value.
+fn byte_of(value: &SymExpr, index: usize) -> SymExpr {
+    value.clone().extract(8 * index + 7, 8 * index)
+}
+
+/// Assemble a read from the possible values of its bytes.
+///
+/// Conditions shared by all bytes are lifted out of the concatenation, so a
+/// read of a whole write stays an if-then-else over whole values.
+fn assemble(mut trees: Vec<ByteTree>) -> SymExpr {
+    let shared = trees.iter().all(|tree| !tree.branches.is_empty())
+        && trees.windows(2).all(|pair| pair[0].branches[0].0 == pair[1].branches[0].0);
+
+    if shared {
+        let mut condition = SymCondition::TRUE;
+        let mut values = Vec::new();
+        for tree in &mut trees {
+            let (c, value) = tree.branches.remove(0);
+            condition = c;
+            values.push(value);
+        }
+
+        condition.if_then_else(concat_bytes(values), assemble(trees))
+    } else {
+        concat_bytes(trees.into_iter().map(ByteTree::fold).collect())
+    }
+}
+
+/// Concatenate little-endian bytes into one value.
+fn concat_bytes(mut pieces: Vec<SymExpr>) -> SymExpr {
+    // The byte at the lower address ends up in the lower bits. Merging
+    // neighbours pairwise keeps all intermediate widths valid data types.
+    while pieces.len() > 1 {
+        pieces = pieces.chunks(2)
+            .map(|pair| pair[1].clone().concat(pair[0].clone()))
+            .collect();
+    }
+
+    pieces.pop().expect("concat_bytes: empty read")
+}
+
 impl Display for SymMemory {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "SymMemory [")?;
         let data = self.data.borrow();
-        if !data.writes.is_empty() { writeln!(f)?; }
+        if self.strategy == MemoryStrategy::SmtArrays {
+            return writeln!(f, "\n    {}\n]", data.array);
+        }
+        if !data.writes.is_empty() || !data.initial.is_empty() { writeln!(f)?; }
+        for write in &data.initial {
+            writeln!(f, "    [0] {} => {}", write.addr, write.value)?;
+        }
         for (epoch, write) in &data.writes {
             writeln!(f, "    [{}] {} => {}", epoch, write.addr, write.value)?;
         }
         writeln!(f, "]")
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::math::{Solver, Integer};
+    use crate::math::DataType::*;
+    use super::*;
+
+    fn ptr(offset: u64) -> SymExpr {
+        SymExpr::Sym(Symbol(N64, "buf", 0)).add(SymExpr::from_ptr(offset))
+    }
+
+    fn int(data_type: DataType, value: u64) -> SymExpr {
+        SymExpr::Int(Integer(data_type, value))
+    }
+
+    #[test]
+    fn byte_granular() {
+        let byte = |i| SymExpr::Sym(Symbol(N8, "stdin", i));
+        let qword = SymExpr::Sym(Symbol(N64, "stdin", 8));
+
+        for &strategy in &[MemoryStrategy::PerfectMatches, MemoryStrategy::ConditionalTrees] {
+            let mut mem = SymMemory::new("mem", strategy, Rc::new(Solver::new()));
+            for i in 0 .. 4 {
+                mem.write_expr(ptr(i as u64), byte(i));
+            }
+
+            // Bytes are composed into wider values.
+            let dword = mem.read_expr(ptr(0), N32);
+            assert_eq!(dword, byte(3).concat(byte(2)).concat(byte(1).concat(byte(0))));
+            assert_eq!(dword.evaluate(&|s| Some(Integer(N8, 0x10 + s.2 as u64))),
+                       Integer(N32, 0x1312_1110));
+            assert_eq!(mem.read_expr(ptr(2), N16), byte(3).concat(byte(2)));
+
+            // Missing bytes come from a default symbol for the whole read.
+            let default = SymExpr::Sym(Symbol(N64, "mem", 0));
+            assert_eq!(mem.read_expr(ptr(0), N64), default.clone().extract(63, 32).concat(dword));
+            assert_eq!(mem.read_expr(ptr(4), N32), default.extract(63, 32));
+
+            // Wide values can be read back in parts and partially overwritten.
+            mem.write_expr(ptr(16), qword.clone());
+            assert_eq!(mem.read_expr(ptr(16), N64), qword);
+            assert_eq!(mem.read_expr(ptr(20), N16), qword.clone().extract(47, 32));
+            mem.write_expr(ptr(17), int(N8, 0xff));
+            assert_eq!(mem.read_expr(ptr(16), N16), int(N8, 0xff).concat(qword.clone().cast(N8, false)));
+            assert_eq!(mem.read_expr(ptr(20), N32), qword.clone().extract(63, 32));
+        }
+    }
+
+    #[test]
+    fn byte_aliasing() {
+        let index = SymExpr::Sym(Symbol(N8, "stdin", 0)).cast(N64, false);
+        let value = SymExpr::Sym(Symbol(N16, "stdin", 1));
+
+        let mut mem = SymMemory::new("mem", MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        mem.write_expr(ptr(0), int(N32, 0));
+        mem.write_expr(ptr(0).add(index.clone()), value.clone());
+
+        // The symbolic write overlaps the read for the indices 0 to 4.
+        let read = mem.read_expr(ptr(0), N32);
+        for i in 0 .. 6 {
+            let expected = (u64::from(0x1234u16) << (8 * i)) as u32 as u64;
+            let result = read.evaluate(&|s| Some(match (s.1, s.2) {
+                ("stdin", 0) => Integer(N8, i),
+                ("stdin", _) => Integer(N16, 0x1234),
+                _ => Integer(N64, 0x1000),
+            }));
+            assert_eq!(result, Integer(N32, expected));
+        }
+    }
+
+    #[test]
+    fn regions() {
+        let index = SymExpr::Sym(Symbol(N8, "stdin", 0)).cast(N64, false);
+        let stack = SymExpr::Sym(Symbol(N64, "reg", 0));
+
+        let mut mem = SymMemory::new("mem", MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        mem.set_region(Symbol(N64, "buf", 0), Region::Heap(0));
+        mem.set_region(Symbol(N64, "reg", 0), Region::Stack);
+
+        // The write into the heap cannot change the stack slot.
+        let slot = stack.sub(SymExpr::from_ptr(8));
+        mem.write_expr(slot.clone(), int(N8, 1));
+        mem.write_expr(ptr(8), int(N8, 3));
+        assert_eq!(mem.read_expr(slot.clone(), N8), int(N8, 1));
+
+        // The write at an index from the input may point anywhere.
+        mem.write_expr(ptr(0).add(index.clone()), int(N8, 2));
+        let read = mem.read_expr(ptr(4), N8);
+        assert_eq!(read, SymExpr::from_ptr(4).sub(index.clone()).equal(SymExpr::from_ptr(0))
+            .if_then_else(int(N8, 2), SymExpr::Sym(Symbol(N8, "mem", 0))));
+        assert_ne!(mem.read_expr(slot.clone(), N8), int(N8, 1));
+
+        // Overlaps are decided like for reads.
+        let access = |addr: SymExpr, data_type| TypedMemoryAccess(addr, data_type);
+        assert_eq!(mem.may_overlap(&access(ptr(0), N32), &access(ptr(3), N8)), SatResult::Sat);
+        assert_eq!(mem.may_overlap(&access(ptr(0), N32), &access(ptr(4), N8)), SatResult::Unsat);
+        assert_eq!(mem.may_overlap(&access(ptr(4), N8), &access(ptr(2), N16)), SatResult::Unsat);
+        assert_eq!(mem.may_overlap(&access(ptr(0), N32), &access(slot, N64)), SatResult::Unsat);
+        let indexed = access(ptr(0).add(index), N8);
+        assert_eq!(mem.may_overlap(&indexed, &access(ptr(0xff), N8)), SatResult::Sat);
+        assert_eq!(mem.may_overlap(&indexed, &access(ptr(0x100), N8)), SatResult::Unsat);
+    }
+
+    #[test]
+    fn input_pointers() {
+        let stack = SymExpr::Sym(Symbol(N64, "reg", 0));
+        let mut mem = SymMemory::new("mem", MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        mem.set_region(Symbol(N64, "reg", 0), Region::Stack);
+
+        // A pointer read from the input byte by byte may point to the stack.
+        for i in 0 .. 8 {
+            mem.write_expr(ptr(i), SymExpr::Sym(Symbol(N8, "stdin", i as usize)));
+        }
+        let pointer = mem.read_expr(ptr(0), N64);
+        assert_eq!(mem.regions().region_of(&pointer), None);
+
+        let slot = stack.clone().sub(SymExpr::from_ptr(8));
+        mem.write_expr(slot.clone(), int(N8, 1));
+        mem.write_expr(pointer.clone(), int(N8, 2));
+
+        let read = mem.read_expr(slot, N8);
+        let values = |aliasing: bool| move |symbol: Symbol| Some(match symbol {
+            Symbol(N64, "reg", 0) => Integer::from_ptr(0x7fff_0010),
+            Symbol(N8, "stdin", 0) => Integer(N8, if aliasing { 0x08 } else { 0x00 }),
+            Symbol(N8, "stdin", 1) => Integer(N8, 0x00),
+            Symbol(N8, "stdin", 2) => Integer(N8, 0xff),
+            Symbol(N8, "stdin", 3) => Integer(N8, 0x7f),
+            Symbol(data_type, _, _) => Integer(data_type, 0),
+        });
+        assert_eq!(read.evaluate(&values(true)), Integer(N8, 2));
+        assert_eq!(read.evaluate(&values(false)), Integer(N8, 1));
+    }
+
+    #[test]
+    fn smt_arrays() {
+        let index = SymExpr::Sym(Symbol(N8, "stdin", 0)).cast(N64, false);
+        let value = SymExpr::Sym(Symbol(N16, "stdin", 1));
+
+        let solver = Rc::new(Solver::new());
+        let mut mem = SymMemory::new("mem", MemoryStrategy::SmtArrays, solver.clone());
+        mem.write_expr(ptr(0), int(N32, 0x1122_3344));
+        mem.write_expr(ptr(0).add(index.clone()), value.clone());
+
+        // Bytes at known offsets are resolved directly.
+        mem.write_expr(ptr(16), value.clone());
+        assert_eq!(mem.read_expr(ptr(16), N16), value);
+
+        // Possibly aliasing reads stay selections the solver resolves.
+        let read = mem.read_expr(ptr(0), N32);
+        let selected = |i: usize| match read.clone().extract(8 * i + 7, 8 * i) {
+            SymExpr::Select(..) => true,
+            _ => false,
+        };
+        assert!((0 .. 4).all(selected));
+
+        for i in 0 .. 6 {
+            let expected = (0x1122_3344 & !(0xffff << (8 * i)) | (0x1234u64 << (8 * i))) as u32 as u64;
+            let result = read.evaluate(&|s| Some(match (s.1, s.2) {
+                ("stdin", 0) => Integer(N8, i),
+                ("stdin", _) => Integer(N16, 0x1234),
+                ("buf", _) => Integer(N64, 0x1000),
+                _ => Integer(N8, 0),
+            }));
+            assert_eq!(result, Integer(N32, expected));
+        }
+
+        let condition = read.equal(int(N32, 0x1122_3344)).not()
+            .and(index.clone().greater_than(SymExpr::from_ptr(3), false));
+        assert_eq!(solver.check_sat(&condition), SatResult::Unsat);
+
+        // Uninitialized bytes are part of the model, indexed by address.
+        let unknown = mem.read_expr(ptr(32), N8).equal(int(N8, 7));
+        let model = solver.get_model(&unknown).unwrap();
+        assert!(unknown.evaluate(&|s| model.get(&s).copied()));
+        let addr = model[&Symbol(N64, "buf", 0)].1.wrapping_add(32);
+        assert_eq!(model[&Symbol(N8, "memarr", addr as usize)], Integer(N8, 7));
+    }
+}
@@ -9,8 +9,23 @@ use crate::math::{SymExpr, SymCondition, Integer, DataType, Symbol, SharedSolver
 use crate::x86_64::{Instruction, Mnemoic, Register};
 use DataType::*;
 
+/// The maximum length of a path passed to a syscall.
+const PATH_MAX: usize = 4096;
+
+/// The default maximum number of bytes transferred by a syscall with a
+/// symbolic count.
+pub const MAX_TRANSFER: u64 = 16;
+
 mod mem;
+mod region;
+mod entry;
+mod files;
+mod heap;
 pub use mem::*;
+pub use region::*;
+pub use entry::*;
+pub use files::*;
+pub use heap::*;
 
 
 /// The symbolic execution state.
@@ -30,9 +45,13 @@ pub struct SymState {
     pub ip: u64,
     /// The shared SMT solver.
     pub solver: SharedSolver,
-    /// The number of used symbols.
-    stdin_symbols: usize,
-    stdout_symbols: usize,
+    /// The open file descriptors.
+    pub files: FileTable,
+    /// The memory allocated through syscalls.
+    pub heap: Heap,
+    /// The maximum number of bytes transferred by a read or write with a
+    /// symbolic count.
+    pub max_transfer: u64,
 }
 
 /// When and where to find the symbolic values in memory in a real execution.
@@ -46,18 +65,34 @@ pub enum Event {
     Exit,
 }
 
-/// Kinds of standard interfaces (stdin or stdout).
+/// Kinds of standard interfaces (stdin, stdout or stderr), files and inputs
+/// the process is started with.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum StdioKind {
     Stdin,
     Stdout,
+    Stderr,
+    /// Reads from the file opened with the number.
+    FileIn(usize),
+    /// Writes to the file opened with the number.
+    FileOut(usize),
+    Args,
+    Env,
+    Auxv,
+}
+
+impl StdioKind {
+    /// Whether data flows from this interface into the program.
+    pub fn is_input(self) -> bool {
+        !matches!(self, StdioKind::Stdout | StdioKind::Stderr | StdioKind::FileOut(_))
+    }
 }
 
 impl SymState {
     /// Create a blank symbolic state that will use the given solver and strategy for
     /// main memory.
     pub fn new(mem_strategy: MemoryStrategy, solver: SharedSolver) -> SymState {
-        SymState {
+        let mut state = SymState {
             temporaries: HashMap::new(),
             memory: [
                 SymMemory::new("mem", mem_strategy, solver.clone()),
@@ -66,10 +101,18 @@ impl SymState {
             symbol_map: SymbolMap::new(),
             trace: Vec::new(),
             ip: 0,
-            stdin_symbols: 0,
-            stdout_symbols: 0,
-            solver
+            solver,
+            files: FileTable::new(),
+            heap: Heap::new(),
+            max_transfer: MAX_TRANSFER,
+        };
+
+        // The initial stack pointer is the base of the stack region.
+        if let SymExpr::Sym(rsp) = state.get_reg(Register::RSP) {
+            state.memory[0].set_region(rsp, Region::Stack);
         }
+
+        state
     }
 
     /// Execute a micro operation.
@@ -159,6 +202,21 @@ impl SymState {
         symbols
     }
 
+    /// The abstract location of a storage location at an address in the
+    /// current trace, with the heap object it points into.
+    pub fn abstract_location(&self, addr: u64, storage: StorageLocation) -> AbstractLocation {
+        let region = self.get_access_for_storage(storage)
+            .and_then(|access| self.memory[0].regions().region_of(&access.0))
+            .filter(|region| matches!(region, Region::Heap(_)));
+
+        AbstractLocation {
+            addr,
+            trace: self.trace.clone(),
+            storage,
+            region,
+        }
+    }
+
     /// Return the address expression and data type of the storage location if
     /// it is a memory access.
     pub fn get_access_for_storage(&self, location: StorageLocation) -> Option<TypedMemoryAccess> {
@@ -206,7 +264,13 @@ impl SymState {
         match dest {
             Location::Temp(temp) => self.set_temp(temp, value),
             Location::Direct(_, space, addr) => {
-                self.memory[space].write_direct(addr, value);
+                // Like on the real CPU, writing a 32-bit register clears the upper half
+                // of the 64-bit register.
+                if space == 1 && value.data_type() == N32 {
+                    self.memory[space].write_direct(addr, value.cast(N64, false));
+                } else {
+                    self.memory[space].write_direct(addr, value);
+                }
             },
             Location::Indirect(_, space, temp) => {
                 let addr = self.get_temp(temp);
@@ -250,67 +314,233 @@ impl SymState {
         assert_eq!(dest.data_type(), src.data_type(), "do_move: incompatible data types for move");
         let value = self.read_location(src);
         self.write_location(dest, value);
+
+        // Remember reads from freed or never allocated heap memory.
+        if let Location::Indirect(_, 0, temp) = src {
+            if let SymExpr::Int(Integer(N64, addr)) = self.get_temp(temp) {
+                if self.heap.is_unmapped(addr) {
+                    self.heap.unmapped_reads.push((self.ip, addr));
+                }
+            }
+        }
     }
 
     /// Emulate a Linux syscall.
     fn do_syscall(&mut self, num: u64) -> Option<Event> {
-        match num {
+        let result = match num {
             // Read from or write to a file descriptor.
-            // We generate one symbol per byte read / written.
+            //
+            // Symbolic counts are capped at the maximum transfer size, which
+            // the kernel may do for any transfer, and make the position in the
+            // stream symbolic. Reads are also limited by the symbolic size of
+            // the input, so the number of bytes actually read is symbolic.
+            // Input symbols past the end of the input stand for unknown bytes,
+            // so they are written anyway.
             0 | 1 => {
-                let read = num == 0;
+                let fd = self.get_syscall_arg(Register::RDI, "fd");
+                let (count, bound) = match self.get_reg(Register::RDX) {
+                    SymExpr::Int(Integer(N64, count)) => (SymExpr::from_ptr(count), count),
+                    count => {
+                        let max = SymExpr::from_ptr(self.max_transfer);
+                        (unsigned_min(count, max), self.max_transfer)
+                    },
+                };
+
+                match self.files.transfer(fd, num == 1, &count, bound) {
+                    Ok((kind, space, start)) => {
+                        let done = match FileTable::size_symbol(kind) {
+                            Some(size) => {
+                                let size = SymExpr::Sym(size);
+                                let start = start.expr.clone();
+                                let left = size.clone().greater_than(start.clone(), false)
+                                    .if_then_else(size.sub(start), SymExpr::from_ptr(0));
+                                unsigned_min(count.clone(), left)
+                            },
+                            None => count.clone(),
+                        };
+
+                        self.set_reg(Register::RAX, done);
+                        return Some(self.do_transfer(kind, space, &start, bound, count));
+                    },
+                    Err(errno) => Err(errno),
+                }
+            },
 
-                let buf = self.get_reg(Register::RSI);
-                let count = self.get_reg(Register::RDX);
-                let byte_count = match count {
-                    SymExpr::Int(Integer(N64, bytes)) => bytes,
-                    _ => panic!("do_syscall: read: unknown byte count"),
+            // Open a file relative to the working directory or a directory
+            // descriptor, which makes no difference since paths are not resolved.
+            2 | 257 => {
+                let (path, flags) = if num == 2 {
+                    (Register::RDI, Register::RSI)
+                } else {
+                    (Register::RSI, Register::RDX)
                 };
 
-                let mut locs = vec![];
+                let path = self.read_path(self.get_reg(path));
+                let flags = self.get_syscall_arg(flags, "flags");
+                self.files.open(path, flags)
+            },
 
-                for i in 0 .. byte_count {
-                    let symbol_ptr = if read {
-                        &mut self.stdin_symbols
-                    } else {
-                        &mut self.stdout_symbols
-                    };
+            // Close a file descriptor.
+            3 => {
+                let fd = self.get_syscall_arg(Register::RDI, "fd");
+                self.files.close(fd)
+            },
 
-                    let symbol = Symbol(N8, if read { "stdin" } else { "stdout" }, *symbol_ptr);
-                    let value = SymExpr::Sym(symbol);
-                    *symbol_ptr += 1;
+            // Reposition the offset of a file descriptor.
+            8 => {
+                let fd = self.get_syscall_arg(Register::RDI, "fd");
+                let offset = self.get_syscall_arg(Register::RSI, "offset");
+                let whence = self.get_syscall_arg(Register::RDX, "whence");
+                match self.files.seek(fd, offset as i64, whence) {
+                    Ok(position) => {
+                        self.set_reg(Register::RAX, position.expr);
+                        return None;
+                    },
+                    Err(errno) => Err(errno),
+                }
+            },
 
-                    let target = buf.clone().add(SymExpr::from_ptr(i));
-                    if read {
-                        self.memory[0].write_expr(target.clone(), value);
-                    }
+            // Map memory at a fresh address, or a fixed one if requested.
+            9 => {
+                let addr = self.get_syscall_arg(Register::RDI, "address");
+                let len = self.get_syscall_arg(Register::RSI, "length");
+                let flags = self.get_syscall_arg(Register::R10, "flags");
+                self.heap.mmap(addr, len, flags).map(|(start, end, region)| {
+                    self.memory[0].set_region_range(start, end, region);
+                    start
+                })
+            },
 
-                    let location = AbstractLocation {
-                        addr: self.ip,
-                        trace: self.trace.clone(),
-                        storage: StorageLocation::Indirect {
-                            data_type: N8,
-                            base: Register::RSI,
-                            scaled_offset: None,
-                            displacement: if i > 0 { Some(i as i64) } else { None },
-                        },
-                    };
-
-                    self.symbol_map.insert(symbol, location);
-                    locs.push((symbol, TypedMemoryAccess(target, N8)));
+            // Unmap memory.
+            11 => {
+                let addr = self.get_syscall_arg(Register::RDI, "address");
+                let len = self.get_syscall_arg(Register::RSI, "length");
+                let result = self.heap.munmap(addr, len);
+                if result.is_ok() {
+                    let end = addr.saturating_add(heap::round_up(len));
+                    self.memory[0].remove_region_range(addr, end);
                 }
+                result
+            },
 
-                let kind = if read { StdioKind::Stdin } else { StdioKind::Stdout };
-                Some(Event::Stdio(kind, locs))
+            // Move the program break.
+            12 => {
+                let addr = self.get_syscall_arg(Register::RDI, "address");
+                let (brk, grown) = self.heap.brk(addr);
+                if let Some((start, end, region)) = grown {
+                    self.memory[0].set_region_range(start, end, region);
+                }
+                Ok(brk)
             },
 
             // System exit
-            60 => Some(Event::Exit),
+            60 => return Some(Event::Exit),
             s => panic!("do_syscall: unimplemented syscall number {}", s),
+        };
+
+        // Errors are returned as negated error numbers.
+        let value = result.unwrap_or_else(|errno| errno.wrapping_neg());
+        self.set_reg(Register::RAX, SymExpr::from_ptr(value));
+        None
+    }
+
+    /// Read or write up to `bound` bytes at the buffer in `RSI`, generating
+    /// one symbol per byte. Bytes from `count` on keep their previous value.
+    ///
+    /// If the start position is symbolic, a byte is any of the symbols at the
+    /// positions it can have, depending on the start.
+    fn do_transfer(
+        &mut self,
+        kind: StdioKind,
+        space: &'static str,
+        start: &StreamPosition,
+        bound: u64,
+        count: SymExpr,
+    ) -> Event {
+        let buf = self.get_reg(Register::RSI);
+        let mut locs = vec![];
+
+        for i in 0 .. bound {
+            let symbols: Vec<Symbol> = start.candidates()
+                .map(|position| Symbol(N8, space, (position + i) as usize))
+                .collect();
+
+            let target = buf.clone().add(SymExpr::from_ptr(i));
+            if kind.is_input() {
+                let (&last, rest) = symbols.split_last().unwrap();
+                let byte = rest.iter().enumerate().rev()
+                    .fold(SymExpr::Sym(last), |byte, (k, &symbol)| {
+                        let position = SymExpr::from_ptr(start.range.0 + k as u64);
+                        start.expr.clone().equal(position).if_then_else(SymExpr::Sym(symbol), byte)
+                    });
+
+                let value = match SymExpr::from_ptr(i).less_than(count.clone(), false) {
+                    SymCondition::Bool(true) => byte,
+                    transferred => {
+                        let previous = self.memory[0].read_expr(target.clone(), N8);
+                        transferred.if_then_else(byte, previous)
+                    },
+                };
+                self.memory[0].write_expr(target.clone(), value);
+            }
+
+            let location = self.abstract_location(self.ip, StorageLocation::Indirect {
+                data_type: N8,
+                base: Register::RSI,
+                scaled_offset: None,
+                displacement: if i > 0 { Some(i as i64) } else { None },
+            });
+
+            for symbol in symbols {
+                self.symbol_map.insert(symbol, location.clone());
+                locs.push((symbol, TypedMemoryAccess(target.clone(), N8)));
+            }
+        }
+
+        Event::Stdio(kind, locs)
+    }
+
+    /// Read a null-terminated path from memory if all of its bytes are known.
+    fn read_path(&self, addr: SymExpr) -> Option<String> {
+        let addr = match addr {
+            SymExpr::Int(Integer(N64, addr)) => addr,
+            _ => return None,
+        };
+
+        let mut path = Vec::new();
+        while path.len() < PATH_MAX {
+            match self.memory[0].read_direct(addr + path.len() as u64, N8) {
+                SymExpr::Int(Integer(N8, 0)) => break,
+                SymExpr::Int(Integer(N8, byte)) => path.push(byte as u8),
+                _ => return None,
+            }
+        }
+
+        if path.len() == PATH_MAX {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(&path).into_owned())
+    }
+
+    /// Return the concrete value of a syscall argument.
+    fn get_syscall_arg(&self, reg: Register, name: &str) -> u64 {
+        match self.get_reg(reg) {
+            SymExpr::Int(Integer(N64, value)) => value,
+            _ => panic!("do_syscall: symbolic {}", name),
         }
     }
 }
 
+/// The smaller one of two unsigned values.
+fn unsigned_min(a: SymExpr, b: SymExpr) -> SymExpr {
+    match a.clone().less_equal(b.clone(), false) {
+        SymCondition::Bool(true) => a,
+        SymCondition::Bool(false) => b,
+        less => less.if_then_else(a, b),
+    }
+}
+
 /// A typed symbolic memory access.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct TypedMemoryAccess(pub SymExpr, pub DataType);
@@ -320,3 +550,118 @@ impl Display for TypedMemoryAccess {
         write!(f, "[{}]:{}", self.0, self.1)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::math::Solver;
+    use super::*;
+
+    #[test]
+    fn symbolic_transfers() {
+        let mut state = SymState::new(MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        let buf = 0x1000;
+        state.max_transfer = 4;
+        state.memory[0].write_direct(buf + 3, SymExpr::Int(Integer(N8, 0xaa)));
+
+        let count = Symbol(N64, "reg", 100);
+        let syscall = |state: &mut SymState, num: u64, count: SymExpr| {
+            state.set_reg(Register::RAX, SymExpr::from_ptr(num));
+            state.set_reg(Register::RDI, SymExpr::from_ptr(0));
+            state.set_reg(Register::RSI, SymExpr::from_ptr(buf));
+            state.set_reg(Register::RDX, count);
+            let event = state.step(0, &MicroOperation::Syscall);
+            (state.get_reg(Register::RAX), event)
+        };
+
+        let (done, event) = syscall(&mut state, 0, SymExpr::Sym(count));
+        match event {
+            Some(Event::Stdio(StdioKind::Stdin, ios)) => assert_eq!(ios.len(), 4),
+            event => panic!("expected stdin event, found {:?}", event),
+        }
+
+        // The count, the size of the input and the maximum transfer size limit
+        // how much is read.
+        let values = |count: u64, size: u64| move |symbol| Some(match symbol {
+            Symbol(N64, "reg", 100) => Integer::from_ptr(count),
+            Symbol(N64, "size", 0) => Integer::from_ptr(size),
+            Symbol(N8, "stdin", index) => Integer(N8, index as u64),
+            Symbol(data_type, _, _) => Integer(data_type, 0),
+        });
+
+        assert_eq!(done.evaluate(&values(2, 10)), Integer::from_ptr(2));
+        assert_eq!(done.evaluate(&values(9, 10)), Integer::from_ptr(4));
+        assert_eq!(done.evaluate(&values(9, 3)), Integer::from_ptr(3));
+        assert_eq!(done.evaluate(&values(9, 0)), Integer::from_ptr(0));
+
+        // Bytes past the count keep their value.
+        let last = state.memory[0].read_direct(buf + 3, N8);
+        assert_eq!(last.evaluate(&values(9, 10)), Integer(N8, 3));
+        assert_eq!(last.evaluate(&values(9, 3)), Integer(N8, 3));
+        assert_eq!(last.evaluate(&values(3, 10)), Integer(N8, 0xaa));
+
+        // Reads continue after the bytes that were actually requested and
+        // return nothing after the end of the input.
+        let (done, event) = syscall(&mut state, 0, SymExpr::from_ptr(1));
+        match event {
+            Some(Event::Stdio(StdioKind::Stdin, ios)) => assert_eq!(ios.len(), 5),
+            event => panic!("expected stdin event, found {:?}", event),
+        }
+
+        let first = state.memory[0].read_direct(buf, N8);
+        assert_eq!(first.evaluate(&values(2, 10)), Integer(N8, 2));
+        assert_eq!(first.evaluate(&values(9, 10)), Integer(N8, 4));
+        assert_eq!(first.evaluate(&values(0, 10)), Integer(N8, 0));
+        assert_eq!(done.evaluate(&values(2, 3)), Integer::from_ptr(1));
+        assert_eq!(done.evaluate(&values(2, 2)), Integer::from_ptr(0));
+        assert_eq!(done.evaluate(&values(9, 5)), Integer::from_ptr(1));
+        assert_eq!(done.evaluate(&values(9, 4)), Integer::from_ptr(0));
+
+        let (done, _) = syscall(&mut state, 1, SymExpr::from_ptr(3));
+        assert_eq!(done, SymExpr::from_ptr(3));
+    }
+
+    #[test]
+    fn heap_regions() {
+        let mut state = SymState::new(MemoryStrategy::ConditionalTrees, Rc::new(Solver::new()));
+        let syscall = |state: &mut SymState, num: u64, addr: u64, len: u64, flags: u64| {
+            state.set_reg(Register::RAX, SymExpr::from_ptr(num));
+            state.set_reg(Register::RDI, SymExpr::from_ptr(addr));
+            state.set_reg(Register::RSI, SymExpr::from_ptr(len));
+            state.set_reg(Register::R10, SymExpr::from_ptr(flags));
+            state.step(0, &MicroOperation::Syscall);
+            state.get_reg(Register::RAX)
+        };
+        let region = |state: &SymState, addr: u64| {
+            state.memory[0].regions().region_of(&SymExpr::from_ptr(addr))
+        };
+
+        assert_eq!(syscall(&mut state, 9, 0, 0x2000, 0x22), SymExpr::from_ptr(MMAP_BASE));
+        assert_eq!(region(&state, MMAP_BASE + 0x1000), Some(Region::Heap(0)));
+
+        // Unmapped memory no longer belongs to the mapping and remapping it
+        // creates a new object.
+        assert_eq!(syscall(&mut state, 11, MMAP_BASE + 0x1000, 0x1000, 0), SymExpr::from_ptr(0));
+        assert_eq!(region(&state, MMAP_BASE), Some(Region::Heap(0)));
+        assert_eq!(region(&state, MMAP_BASE + 0x1000), Some(Region::Global));
+
+        assert_eq!(syscall(&mut state, 9, MMAP_BASE + 0x1000, 0x1000, 0x32),
+            SymExpr::from_ptr(MMAP_BASE + 0x1000));
+        assert_eq!(region(&state, MMAP_BASE + 0x1000), Some(Region::Heap(1)));
+
+        // Only loads from memory that is not mapped are remembered.
+        let load = |state: &mut SymState, ip: u64, addr: u64| {
+            state.set_temp(Temporary(N64, 0), SymExpr::from_ptr(addr));
+            state.step(ip, &MicroOperation::Mov {
+                dest: Location::Temp(Temporary(N8, 1)),
+                src: Location::Indirect(N8, 0, Temporary(N64, 0)),
+            });
+        };
+
+        load(&mut state, 0x10, MMAP_BASE + 0x1fff);
+        load(&mut state, 0x20, MMAP_BASE + 0x2000);
+        load(&mut state, 0x30, BRK_BASE);
+        assert_eq!(state.heap.unmapped_reads, vec![(0x20, MMAP_BASE + 0x2000), (0x30, BRK_BASE)]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Provenance of addresses in disjoint memory regions.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::math::{SymExpr, Symbol, Integer};
+use SymExpr::*;
+
+
+/// A part of main memory that never overlaps with any other region.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Region {
+    /// The stack, addressed relative to the initial stack pointer.
+    Stack,
+    /// Static data of the executable at fixed addresses.
+    Global,
+    /// An object allocated through `brk` or `mmap`, numbered in order of
+    /// allocation.
+    Heap(usize),
+}
+
+/// Tracks the base pointers of the regions to find out which region an
+/// address points into.
+///
+/// An address is in a region if it is the base pointer of the region plus
+/// a constant offset. Constant addresses belong to the region whose address
+/// range contains them, which is global data by default. Any other symbolic
+/// value may be a pointer into any region, for example one that was loaded
+/// from memory byte by byte.
+#[derive(Debug, Clone)]
+pub struct Regions {
+    bases: HashMap<Symbol, Region>,
+    ranges: Vec<(u64, u64, Region)>,
+}
+
+/// What an expression contributes to the region of an address.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Base {
+    /// The expression is a constant.
+    Offset,
+    /// The expression points into the region.
+    Pointer(Region),
+    /// The expression may be a pointer into any region.
+    Unknown,
+}
+
+impl Regions {
+    /// Create a blank region table.
+    pub fn new() -> Regions {
+        Regions { bases: HashMap::new(), ranges: Vec::new() }
+    }
+
+    /// Declare a symbol as the base pointer of a region.
+    pub fn insert(&mut self, base: Symbol, region: Region) {
+        self.bases.insert(base, region);
+    }
+
+    /// Declare the fixed addresses from `start` up to `end` (exclusive) as
+    /// part of a region, replacing the regions they belonged to before.
+    pub fn insert_range(&mut self, start: u64, end: u64, region: Region) {
+        self.remove_range(start, end);
+        self.ranges.push((start, end, region));
+    }
+
+    /// Forget the regions of the fixed addresses from `start` up to `end`
+    /// (exclusive), splitting ranges that overlap it partially.
+    pub fn remove_range(&mut self, start: u64, end: u64) {
+        let mut ranges = Vec::new();
+        for &(from, to, region) in &self.ranges {
+            if from < start {
+                ranges.push((from, to.min(start), region));
+            }
+            if to > end {
+                ranges.push((from.max(end), to, region));
+            }
+        }
+        self.ranges = ranges;
+    }
+
+    /// The region the address points into if it is known.
+    pub fn region_of(&self, addr: &SymExpr) -> Option<Region> {
+        match self.base(addr) {
+            Base::Offset => {
+                let constant = addr.evaluate(&|symbol| Some(Integer(symbol.0, 0))).1;
+                let region = self.ranges.iter()
+                    .find(|&&(start, end, _)| start <= constant && constant < end)
+                    .map(|&(_, _, region)| region);
+                Some(region.unwrap_or(Region::Global))
+            },
+            Base::Pointer(region) => Some(region),
+            Base::Unknown => None,
+        }
+    }
+
+    /// Whether the addresses are known to point into different regions, so
+    /// that accesses through them can never alias.
+    pub fn disjoint(&self, a: &SymExpr, b: &SymExpr) -> bool {
+        match (self.region_of(a), self.region_of(b)) {
+            (Some(a), Some(b)) => a != b,
+            _ => false,
+        }
+    }
+
+    fn base(&self, expr: &SymExpr) -> Base {
+        match expr {
+            Int(_) => Base::Offset,
+            Sym(symbol) => match self.bases.get(symbol) {
+                Some(&region) => Base::Pointer(region),
+                None => Base::Unknown,
+            },
+
+            Add(a, b) => match (self.base(a), self.base(b)) {
+                (Base::Offset, x) | (x, Base::Offset) => x,
+                _ => Base::Unknown,
+            },
+            Sub(a, b) => match self.base(b) {
+                Base::Offset => self.base(a),
+                _ => Base::Unknown,
+            },
+            // Aligning a pointer keeps it in its region.
+            BitAnd(a, b) => match &**b {
+                Int(_) => self.base(a),
+                _ => Base::Unknown,
+            },
+            // Constants may lie in different ranges, so only pointers into
+            // the same region are kept.
+            IfThenElse(_, a, b) => match (self.base(a), self.base(b)) {
+                (Base::Pointer(a), Base::Pointer(b)) if a == b => Base::Pointer(a),
+                _ => Base::Unknown,
+            },
+
+            // Constant operands are folded, so everything else is built from
+            // symbols and can be any pointer, even if narrow parts like bytes
+            // of an input are combined into it.
+            _ => Base::Unknown,
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Region::Stack => write!(f, "stack"),
+            Region::Global => write!(f, "global"),
+            Region::Heap(index) => write!(f, "heap{}", index),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::DataType::*;
+    use super::*;
+
+    #[test]
+    fn provenance() {
+        let rsp = Sym(Symbol(N64, "reg", 0));
+        let heap = Sym(Symbol(N64, "reg", 1));
+        let loaded = Sym(Symbol(N64, "mem", 0));
+        let index = Sym(Symbol(N8, "stdin", 0)).cast(N64, false);
+        let ptr = |x| Int(Integer::from_ptr(x));
+
+        let mut regions = Regions::new();
+        regions.insert(Symbol(N64, "reg", 0), Region::Stack);
+        regions.insert(Symbol(N64, "reg", 1), Region::Heap(0));
+
+        let slot = rsp.clone().sub(ptr(8));
+        let buf = rsp.clone().bitand(ptr(!0xf)).sub(ptr(0x410));
+        let global = ptr(0x601040);
+        let indexed = buf.clone().add(index.clone().mul(ptr(2)));
+        let bytes = Sym(Symbol(N32, "stdin", 4)).concat(Sym(Symbol(N32, "stdin", 0)));
+
+        assert_eq!(regions.region_of(&slot), Some(Region::Stack));
+        assert_eq!(regions.region_of(&buf), Some(Region::Stack));
+        assert_eq!(regions.region_of(&global), Some(Region::Global));
+        assert_eq!(regions.region_of(&heap.clone().add(ptr(16))), Some(Region::Heap(0)));
+        assert_eq!(regions.region_of(&loaded.clone().add(ptr(8))), None);
+        assert_eq!(regions.region_of(&rsp.clone().add(heap.clone())), None);
+
+        // Values built from symbols may be pointers anywhere.
+        assert_eq!(regions.region_of(&indexed), None);
+        assert_eq!(regions.region_of(&ptr(0x601040).add(index.clone())), None);
+        assert_eq!(regions.region_of(&bytes), None);
+        assert_eq!(regions.region_of(&index), None);
+
+        assert!(regions.disjoint(&slot, &global));
+        assert!(regions.disjoint(&slot, &heap));
+        assert!(!regions.disjoint(&slot, &buf));
+        assert!(!regions.disjoint(&slot, &indexed));
+        assert!(!regions.disjoint(&slot, &loaded));
+        assert!(!regions.disjoint(&slot, &bytes));
+
+        regions.insert_range(0x7000, 0x8000, Region::Stack);
+        assert_eq!(regions.region_of(&ptr(0x7ff0).sub(ptr(0x10))), Some(Region::Stack));
+        assert!(regions.disjoint(&ptr(0x7ff0), &global));
+        assert!(!regions.disjoint(&ptr(0x7ff0).add(index), &slot));
+
+        // Newer ranges replace the overlapping parts of older ones.
+        regions.insert_range(0x7400, 0x7800, Region::Heap(1));
+        assert_eq!(regions.region_of(&ptr(0x7000)), Some(Region::Stack));
+        assert_eq!(regions.region_of(&ptr(0x7500)), Some(Region::Heap(1)));
+        assert_eq!(regions.region_of(&ptr(0x7900)), Some(Region::Stack));
+
+        regions.remove_range(0x7000, 0x7600);
+        assert_eq!(regions.region_of(&ptr(0x7000)), Some(Region::Global));
+        assert_eq!(regions.region_of(&ptr(0x7700)), Some(Region::Heap(1)));
+    }
+}
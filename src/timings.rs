@@ -26,7 +26,8 @@ pub fn get() -> Measurement {
     let mut measurement = Measurement {
         name: "Measurement".to_string(),
         duration: parts.iter().map(|m| m.duration).sum(),
-        parts
+        parts,
+        counts: timer.counts.clone(),
     };
 
     measurement.resort();
@@ -42,6 +43,7 @@ pub fn reset() {
 struct Timer {
     stack: Vec<Timing>,
     finished: Vec<Measurement>,
+    counts: HashMap<String, usize>,
 }
 
 /// A single timing created by `start` and finished into a `Measurement` by `stop`.
@@ -56,12 +58,14 @@ impl Timer {
         Timer {
             stack: Vec::new(),
             finished: Vec::new(),
+            counts: HashMap::new(),
         }
     }
 
     fn reset(&mut self) {
         self.stack.clear();
         self.finished.clear();
+        self.counts.clear();
     }
 }
 
@@ -73,6 +77,18 @@ pub(crate) fn with<S: Into<String>, F, T>(what: S, f: F) -> T where F: FnOnce()
     value
 }
 
+/// Count an occurence of `what` (e.g. a cache hit).
+///
+/// Only the first occurence allocates the name, so this is cheap enough
+/// to be called for every solver query.
+pub(crate) fn count(what: &'static str) {
+    let mut timer = timer!();
+    match timer.counts.get_mut(what) {
+        Some(count) => *count += 1,
+        None => { timer.counts.insert(what.to_string(), 1); },
+    }
+}
+
 /// Tell the timer, that `what` is now being executed.
 pub(crate) fn start<S: Into<String>>(what: S) {
     let started = Instant::now();
@@ -96,6 +112,7 @@ pub(crate) fn stop() {
         name: timing.name.clone(),
         duration: stopped - timing.started,
         parts: timing.children.into_iter().map(|(_, v)| v).collect(),
+        counts: HashMap::new(),
     };
 
     if let Some(last) = timer.stack.last_mut() {
@@ -119,6 +136,10 @@ pub struct Measurement {
 
     /// The submeasurements sorted by duration (high to low).
     pub parts: Vec<Measurement>,
+
+    /// How often named events occured. Only the measurement returned by
+    /// `get` has counts.
+    pub counts: HashMap<String, usize>,
 }
 
 impl Measurement {
@@ -145,6 +166,9 @@ impl Measurement {
     /// the same name recursively.
     pub fn merge_with(&mut self, other: Measurement) {
         self.duration += other.duration;
+        for (name, count) in other.counts {
+            *self.counts.entry(name).or_insert(0) += count;
+        }
         for part in other.parts {
             if let Some(same) = self.parts.iter_mut().find(|m| m.name == part.name) {
                 same.merge_with(part);
@@ -195,6 +219,14 @@ impl Display for Measurement {
             let ratio = (time.as_nanos() as f64) / (self.duration.as_nanos() as f64);
             writeln!(f, "    {}: {:?} ({:.2} %)", name, time, 100.0 * ratio)?;
         }
+        if !self.counts.is_empty() {
+            let mut counts: Vec<_> = self.counts.iter().collect();
+            counts.sort();
+            writeln!(f, "\nCounts:")?;
+            for (name, count) in counts {
+                writeln!(f, "    {}: {}", name, count)?;
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,186 @@
+//! Validation of the lifter by differential execution.
+//!
+//! A binary is run natively, with the concrete interpreter and with the symbolic
+//! execution engine on the same standard input. The symbolic run is a concolic
+//! run, which follows the path the input takes by evaluating the symbolic
+//! values with the input bytes substituted for the `stdin` symbols. All three
+//! runs have to produce the same standard output.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::Program;
+use crate::concolic::run_concolic;
+use crate::concrete::{ConcreteState, ExecutionError};
+
+
+/// The maximum number of instructions executed in the interpreted runs.
+pub const MAX_STEPS: usize = 1_000_000;
+
+/// Run the binary natively and validate that the interpreted runs produce the
+/// same standard output. Returns the output if they all agree.
+pub fn validate<P: AsRef<Path>>(filename: P, stdin: &[u8]) -> ValidationResult<Vec<u8>> {
+    let program = Program::new(filename.as_ref());
+
+    let native = run_native(filename, stdin).map_err(ValidationError::Native)?;
+    let concrete = run_concrete(&program, stdin).map_err(ValidationError::Concrete)?;
+    let symbolic = run_symbolic(&program, stdin).map_err(ValidationError::Symbolic)?;
+
+    if native == concrete && native == symbolic {
+        Ok(native)
+    } else {
+        Err(ValidationError::Mismatch { native, concrete, symbolic })
+    }
+}
+
+/// Run the binary as a child process and return what it writes.
+///
+/// The child gets one end of a socket pair as both standard input and standard
+/// output because the test binaries also write to file descriptor zero. The
+/// input is written from another thread while the output is read, so that
+/// neither side blocks on a full socket. Exiting with a non-zero status or
+/// through a signal is an error.
+pub fn run_native<P: AsRef<Path>>(filename: P, stdin: &[u8]) -> io::Result<Vec<u8>> {
+    let (mut parent, child) = UnixStream::pair()?;
+    let child_out = child.try_clone()?;
+
+    let mut command = Command::new(filename.as_ref());
+    unsafe {
+        command
+            .stdin(Stdio::from_raw_fd(child.into_raw_fd()))
+            .stdout(Stdio::from_raw_fd(child_out.into_raw_fd()));
+    }
+
+    let mut process = command.spawn()?;
+
+    // Drop the command so that our copies of the child's socket are closed
+    // and reading reaches the end once the child exits.
+    drop(command);
+
+    let mut writer = parent.try_clone()?;
+    let input = stdin.to_vec();
+    let writing = thread::spawn(move || {
+        writer.write_all(&input)?;
+        writer.shutdown(Shutdown::Write)
+    });
+
+    let mut stdout = Vec::new();
+    parent.read_to_end(&mut stdout)?;
+    writing.join().expect("failed to join input writer")?;
+
+    let status = process.wait()?;
+    if !status.success() {
+        let message = format!("{} failed with {}", filename.as_ref().display(), status);
+        return Err(io::Error::other(message));
+    }
+
+    Ok(stdout)
+}
+
+/// Run the program with the concrete interpreter and return what it writes.
+pub fn run_concrete(program: &Program, stdin: &[u8]) -> Result<Vec<u8>, ExecutionError> {
+    let mut state = ConcreteState::from_program(program, stdin.to_vec());
+    state.run(program, MAX_STEPS)?;
+    Ok(state.stdout)
+}
+
+/// Run the program symbolically along the path taken by the input and
+/// return what it writes.
+pub fn run_symbolic(program: &Program, stdin: &[u8]) -> Result<Vec<u8>, ExecutionError> {
+    run_concolic(program, stdin).map(|path| path.stdout)
+}
+
+
+/// The error type for lifter validation.
+pub enum ValidationError {
+    /// Running the binary natively failed.
+    Native(io::Error),
+    /// Concrete interpretation failed.
+    Concrete(ExecutionError),
+    /// Symbolic interpretation failed.
+    Symbolic(ExecutionError),
+    /// The outputs of the runs differ.
+    Mismatch { native: Vec<u8>, concrete: Vec<u8>, symbolic: Vec<u8> },
+}
+
+pub(in super) type ValidationResult<T> = Result<T, ValidationError>;
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Native(err) => write!(f, "Native execution failed: {}", err),
+            ValidationError::Concrete(err) => write!(f, "Concrete execution failed: {}", err),
+            ValidationError::Symbolic(err) => write!(f, "Symbolic execution failed: {}", err),
+            ValidationError::Mismatch { native, concrete, symbolic } => {
+                writeln!(f, "Outputs differ [")?;
+                writeln!(f, "    native: {:02x?}", native)?;
+                writeln!(f, "    concrete: {:02x?}", concrete)?;
+                writeln!(f, "    symbolic: {:02x?}", symbolic)?;
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ValidationError::Native(err) => Some(err),
+            ValidationError::Concrete(err) | ValidationError::Symbolic(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+debug_display!(ValidationError);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(filename: &str, inputs: &[&[u8]]) {
+        let path = format!("target/bin/{}", filename);
+        for input in inputs {
+            if let Err(err) = validate(&path, input) {
+                panic!("validation of {} with input {:02x?} failed: {}", filename, input, err);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_io() {
+        test("case", &[b"q", b"Q", b"a", b"z", b"{", b"\x00", b"\xff"]);
+        test("func", &[b"A", b"@", b"\x00", b"\xff"]);
+        test("bufs", &[b"z:S", b"ap!", b"\x00\x00\x01", b"\xff\xff\x7f"]);
+        test("paths", &[b"aS", b"\x81S\x01", b"\xffS\xff"]);
+        test("deep", &[b"\x00\x00\x00", b"\x05\x05\x05", b"aaa"]);
+        test("overwrite", &[b"a\x01S", b"\x90\x90S", b"\x90\x91S"]);
+        test("global", &[b"\x00", b"\x03", b"\x07"]);
+        test("echo", &[b"\x00", b"\x03", b"\x08"]);
+    }
+
+    #[test]
+    fn native_status() {
+        // The output is read while the input is still being written.
+        let input: Vec<u8> = (0 .. 1 << 20).map(|i| i as u8).collect();
+        assert_eq!(run_native("/bin/cat", &input).unwrap(), input);
+
+        assert!(run_native("/bin/true", b"").is_ok());
+        assert!(run_native("/bin/false", b"").is_err());
+    }
+
+    #[test]
+    fn validate_silent() {
+        test("block-1", &[b""]);
+        test("block-2", &[b""]);
+        test("twice", &[b""]);
+        test("recursive-1", &[b""]);
+    }
+}
@@ -0,0 +1,24 @@
+#define LIB_IO
+#include "lib.h"
+
+// Write `len` bytes to stdout.
+void write_bytes(char* buf, long len) {
+    asm("movq %0, %%rsi;"
+        "movq $0, %%rdi;"
+        "movq %1, %%rdx;"
+        "movq $1, %%rax;"
+        "syscall;"
+        :: "r"(buf), "r"(len)
+        : "rax", "rdi", "rsi", "rdx", "rcx", "r11", "memory"
+    );
+}
+
+void main() {
+    char buf[8];
+    for (int i = 0; i < 8; i++) {
+        buf[i] = 'a' + i;
+    }
+
+    unsigned char len = read_one_byte();
+    write_bytes(buf, len);
+}
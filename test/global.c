@@ -0,0 +1,13 @@
+#define LIB_IO
+#include "lib.h"
+
+const char digits[] = "0123456789";
+char letters[] = "abcdefgh";
+int counter;
+
+void main() {
+    unsigned char x = read_one_byte();
+    counter += 3;
+    write_one_byte(letters[x]);
+    write_one_byte(digits[counter]);
+}